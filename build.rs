@@ -0,0 +1,97 @@
+//! Checks that the compiler flags match the enabled features, and embeds the build identification
+//! used by the `version` module.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=CARGO_ENCODED_RUSTFLAGS");
+
+    let flags = env::var("CARGO_ENCODED_RUSTFLAGS").unwrap_or_default();
+    if env::var_os("CARGO_FEATURE_PAUTH").is_some() && !flags.contains("branch-protection") {
+        println!(
+            "cargo:warning=the pauth feature is enabled, but return addresses are only signed \
+             when building with -Z branch-protection=pac-ret (see `just build-pauth`)"
+        );
+    }
+
+    emit_build_id();
+}
+
+/// Emits the `L4SM_*` variables read by the `version` module.
+///
+/// Everything degrades to "unknown" rather than failing the build, source snapshots might be
+/// built without git.
+fn emit_build_id() {
+    // Rebuild when the checked out commit or the working tree changes
+    for path in [".git/HEAD", ".git/index"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let commit = run("git", &["rev-parse", "--short", "HEAD"]);
+    let dirty = run("git", &["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    // "rustc 1.80.0 (051478957 2024-07-21)", keep the version
+    let rustc_version = run(&rustc, &["--version"])
+        .and_then(|version| version.split_whitespace().nth(1).map(String::from));
+
+    let commit = match commit {
+        Some(commit) if dirty => format!("{commit}-dirty"),
+        Some(commit) => commit,
+        None => "unknown".into(),
+    };
+    println!("cargo:rustc-env=L4SM_GIT_COMMIT={commit}");
+    println!(
+        "cargo:rustc-env=L4SM_PROFILE={}",
+        env::var("PROFILE").unwrap_or_else(|_| "unknown".into())
+    );
+    println!(
+        "cargo:rustc-env=L4SM_RUSTC_VERSION={}",
+        rustc_version.as_deref().unwrap_or("unknown")
+    );
+    println!("cargo:rustc-env=L4SM_BUILD_DATE={}", build_date());
+}
+
+/// Runs a command and returns its trimmed output, if it succeeded.
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+/// Returns the build date as YYYY-MM-DD, honoring `SOURCE_DATE_EPOCH` for reproducible builds.
+fn build_date() -> String {
+    let seconds = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|now| now.as_secs())
+        });
+    let Some(seconds) = seconds else {
+        return "unknown".into();
+    };
+
+    // Days since the epoch to a civil date, from Howard Hinnant's `civil_from_days`
+    let days = (seconds / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
@@ -3,8 +3,9 @@ SECTIONS
   /* Not needed with panic=abort, and would end up before .text in the flat binary */
   /DISCARD/ : { *(.eh_frame*) }
 
-  /* Start address */
+  /* Link address, the image is position independent and may be loaded elsewhere */
   . = 0x0e090000; /* We use the same address as RF-A on QEMU */
+  _image_start = .;
 
   /* Output a text section, starting with the entry point */
   .text : ALIGN(0x4) {
@@ -12,6 +13,11 @@ SECTIONS
     *(.text)
     *(.text.*)
   }
+  _text_end = .;
+
+  /* Page-align the rodata, so that the text can be mapped executable on its own */
+  . = ALIGN(0x1000);
+  _rodata_start = .;
 
   /* Output the rodata */
   .rodata : ALIGN(0x8) {
@@ -20,28 +26,77 @@ SECTIONS
     *(.rodata.*)
   }
 
+  /* Dynamic relocations of the position-independent image, applied by _start */
+  .rela.dyn : ALIGN(0x8) {
+    _rela_start = .;
+    *(.rela .rela.*)
+    _rela_end = .;
+  }
+
+  /* Left over from PIE linking, unused */
+  .dynsym : { *(.dynsym) }
+  .dynstr : { *(.dynstr) }
+  .hash : { *(.hash) }
+  .gnu.hash : { *(.gnu.hash) }
+  .dynamic : ALIGN(0x8) { *(.dynamic) }
+
+  /* Lower-EL payloads embedded in the image */
+  .payload : ALIGN(0x8) {
+    _ns_payload_start = .;
+    KEEP(*(.payload.ns))
+    _ns_payload_end = .;
+    . = ALIGN(0x8);
+    _secure_payload_start = .;
+    KEEP(*(.payload.secure))
+    _secure_payload_end = .;
+    . = ALIGN(0x8);
+    /* The realm payload is an ELF image, keep its headers aligned */
+    . = ALIGN(0x8);
+    _realm_payload_start = .;
+    KEEP(*(.payload.realm))
+    _realm_payload_end = .;
+  }
+  _rodata_end = .;
+
+  /* Page-align the data, so that everything before can be mapped read-only */
+  . = ALIGN(0x1000);
+  _data_start = .;
+
   /* Finally, all data                                         */
   /* NOTE: no need to page-align bss, both bss and data are RW */
+  /* On-target tests registered with kernel_test!, their descriptors hold relocated pointers */
+  .l4sm_tests : ALIGN(0x8) {
+    _tests_start = .;
+    KEEP(*(.l4sm_tests))
+    _tests_end = .;
+  }
   .data : ALIGN(0x8) {
     KEEP(*(__*))
     *(.data)
     *(.data.*)
   }
+  .got : ALIGN(0x8) {
+    *(.got)
+    *(.got.plt)
+  }
   .sdata : ALIGN(0x8) {
     KEEP(*(__*))
     *(.sdata)
     *(.sdata.*)
   }
-  . = ALIGN(0x8);
+  /* The BSS is cleared 16 bytes at a time */
+  . = ALIGN(0x10);
   _bss_start = .;
   .sbss : {
     *(.sbss)
     *(.sbss.*)
   }
+  /* Sorted by name, the init_check canary (.bss.~l4sm_canary) ends up last */
   .bss : ALIGN(0x8) {
     *(.bss)
-    *(.bss.*)
+    *(SORT_BY_NAME(.bss.*))
   }
+  . = ALIGN(0x10);
   _bss_stop = .;
 
   /* Then we mark the start of the stack (or the end, as the stack grows
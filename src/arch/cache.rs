@@ -0,0 +1,89 @@
+//! Cache maintenance.
+//!
+//! Range operations work on virtual addresses and cover every cache line that overlaps the range.
+
+use crate::ktest::kernel_test;
+use core::arch::asm;
+
+/// Returns the size of the smallest data cache line, in bytes.
+fn dcache_line_size() -> usize {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, CTR_EL0", out(reg) ctr) };
+    // DminLine is the log2 of the number of 4-byte words
+    4 << ((ctr >> 16) & 0xF)
+}
+
+/// Cleans the data cache lines covering a range to the point of coherency.
+///
+/// Makes data written through the caches visible to non-cacheable accesses, such as a lower EL
+/// running with its MMU off.
+pub fn clean_dcache_range(start: usize, len: usize) {
+    for line in lines(start, len, dcache_line_size()) {
+        unsafe { asm!("dc cvac, {}", in(reg) line) };
+    }
+    unsafe { asm!("dsb sy") };
+}
+
+/// Invalidates the data cache lines covering a range.
+///
+/// Dirty lines are discarded, this must only be used on memory whose content in the caches is
+/// not (or no longer) relevant.
+pub fn invalidate_dcache_range(start: usize, len: usize) {
+    for line in lines(start, len, dcache_line_size()) {
+        unsafe { asm!("dc ivac, {}", in(reg) line) };
+    }
+    unsafe { asm!("dsb sy") };
+}
+
+/// Cleans and invalidates the data cache lines covering a range to the point of coherency.
+///
+/// Must be used before the caches are disabled, so that no dirty data is left behind.
+pub fn clean_invalidate_dcache_range(start: usize, len: usize) {
+    for line in lines(start, len, dcache_line_size()) {
+        unsafe { asm!("dc civac, {}", in(reg) line) };
+    }
+    unsafe { asm!("dsb sy") };
+}
+
+/// Invalidates all instruction caches to the point of unification.
+pub fn invalidate_icache_all() {
+    unsafe { asm!("ic iallu", "dsb sy", "isb") };
+}
+
+/// Returns the address of each cache line overlapping a range.
+///
+/// The start is rounded down to a line boundary, and a partial line at the end is included.
+fn lines(start: usize, len: usize, line_size: usize) -> impl Iterator<Item = usize> {
+    let end = start + len;
+    let first = if len == 0 {
+        end
+    } else {
+        start & !(line_size - 1)
+    };
+    (first..end).step_by(line_size)
+}
+
+kernel_test! {
+    fn lines_cover_unaligned_ranges() {
+        let check = |start, len, expected: &[usize]| {
+            assert!(
+                lines(start, len, 64).eq(expected.iter().copied()),
+                "lines of {start:#x}+{len:#x}"
+            );
+        };
+        check(0x1000, 0x80, &[0x1000, 0x1040]);
+        // Unaligned start
+        check(0x1010, 0x70, &[0x1000, 0x1040]);
+        check(0x1010, 0x40, &[0x1000, 0x1040]);
+        check(0x103f, 1, &[0x1000]);
+        // Unaligned end
+        check(0x1000, 0x41, &[0x1000, 0x1040]);
+        check(0x1000, 0x3f, &[0x1000]);
+        // Both, across a line boundary
+        check(0x103f, 2, &[0x1000, 0x1040]);
+        check(0x1001, 0x80, &[0x1000, 0x1040, 0x1080]);
+        // Nothing to maintain
+        check(0x1000, 0, &[]);
+        check(0x1010, 0, &[]);
+    }
+}
@@ -0,0 +1,558 @@
+//! Per-world CPU contexts.
+//!
+//! The secure, non-secure, and realm worlds share the EL1 system registers, so EL3 must save and
+//! restore them (together with the general purpose registers) whenever it switches from one world
+//! to another. Each CPU holds one context per world, and records which world it is running.
+//!
+//! World switches happen on the exception return path: the state of the interrupted world is
+//! saved from the exception frame, and the frame is then overwritten with the state of the other
+//! world before the ERET.
+
+use crate::arch::exception::ExceptionFrame;
+use crate::arch::feature;
+use crate::arch::lower_el::{SCTLR_EL1_RES1, SPSR_DAIF, SPSR_M_EL1H};
+#[cfg(feature = "pauth")]
+use crate::arch::pauth;
+use crate::arch::scr::ScrEl3;
+use crate::ktest::kernel_test;
+use crate::sync::SpinLock;
+use crate::{percpu, platform};
+use core::arch::global_asm;
+use core::mem::{offset_of, size_of};
+
+const _: () = assert!(
+    size_of::<El1SysRegs>() == 24 * 8,
+    "the EL1 system registers must be packed"
+);
+
+/// Checks that two registers saved as a pair are adjacent in [El1SysRegs].
+macro_rules! assert_pair {
+    ($first:ident, $second:ident) => {
+        const _: () =
+            assert!(offset_of!(El1SysRegs, $second) == offset_of!(El1SysRegs, $first) + 8);
+    };
+}
+
+assert_pair!(sp_el1, elr);
+assert_pair!(spsr, sctlr);
+assert_pair!(actlr, cpacr);
+assert_pair!(csselr, ttbr0);
+assert_pair!(ttbr1, tcr);
+assert_pair!(mair, amair);
+assert_pair!(vbar, contextidr);
+assert_pair!(tpidr_el0, tpidrro_el0);
+assert_pair!(tpidr_el1, esr);
+assert_pair!(far, par);
+assert_pair!(afsr0, afsr1);
+assert_pair!(cntkctl, mdscr);
+
+/// The contexts of each CPU.
+static WORLDS: [SpinLock<WorldContext>; platform::MAX_CPUS] =
+    [const { SpinLock::new(WorldContext::new()) }; platform::MAX_CPUS];
+
+/// A security state of the lower ELs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum World {
+    Secure,
+    NonSecure,
+    /// The realm world, only available with RME.
+    Realm,
+}
+
+impl World {
+    /// Returns the world a yield from this world switches to.
+    ///
+    /// The secure and non-secure worlds yield to each other, the realm world yields to the
+    /// non-secure world.
+    fn peer(self) -> Self {
+        match self {
+            World::Secure | World::Realm => World::NonSecure,
+            World::NonSecure => World::Secure,
+        }
+    }
+
+    /// Panics if this world is not supported by the hardware.
+    pub fn assert_supported(self) {
+        if self == World::Realm && !feature::has_rme() {
+            panic!("The realm world requires RME, which the hardware does not support");
+        }
+    }
+}
+
+/// Initializes the context of `world` on the calling CPU, so that it starts at `entry` in EL1
+/// with `arg` in `x0` when first switched to.
+///
+/// # Panics
+///
+/// Panics if `world` is not supported by the hardware.
+pub fn init(world: World, entry: usize, arg: usize) {
+    world.assert_supported();
+    let mut worlds = WORLDS[percpu::current().index()].lock();
+    let context = worlds.get_mut(world);
+    *context = CpuContext::new();
+    context.x[0] = arg as u64;
+    context.elr_el3 = entry as u64;
+    context.spsr_el3 = SPSR_DAIF | SPSR_M_EL1H;
+    context.el1.sctlr = SCTLR_EL1_RES1;
+    context.initialized = true;
+}
+
+/// Returns the world the calling CPU is running, if any.
+pub fn running() -> Option<World> {
+    WORLDS[percpu::current().index()].lock().running
+}
+
+/// Records that the calling CPU is about to enter `world` directly, without a world switch.
+pub fn set_running(world: World) {
+    WORLDS[percpu::current().index()].lock().running = Some(world);
+}
+
+/// Requests a switch to the peer of the running world on the next exception return of the
+/// calling CPU.
+///
+/// Returns `false` if the peer world has not been initialized, or was killed.
+pub fn request_switch() -> bool {
+    WORLDS[percpu::current().index()].lock().request_switch()
+}
+
+/// Kills the running world of the calling CPU, and requests a switch to its peer on the next
+/// exception return. The killed world is never resumed: yields to it fail.
+///
+/// Returns the killed world, or `None` if there is no running world or its peer has not been
+/// initialized: there would be nothing left to run.
+pub fn kill_running() -> Option<World> {
+    WORLDS[percpu::current().index()].lock().kill_running()
+}
+
+/// Switches world if a switch was requested, by saving the interrupted world from the exception
+/// frame and restoring the requested world into it.
+pub fn switch_if_requested(frame: &mut ExceptionFrame) {
+    let mut worlds = WORLDS[percpu::current().index()].lock();
+    let Some(to) = worlds.switch_pending.take() else {
+        return;
+    };
+
+    // A switch can only be requested while a world is running
+    let from = worlds.running.expect("no running world");
+    worlds.get_mut(from).save(frame);
+    worlds.get_mut(to).restore(frame);
+    worlds.running = Some(to);
+
+    ScrEl3::read().world(to).write();
+    log::trace!("Switched from {from:?} to {to:?} world");
+}
+
+// ——————————————————————————————— Contexts ————————————————————————————————— //
+
+/// The secure, non-secure, and realm contexts of a CPU.
+pub struct WorldContext {
+    pub ns: CpuContext,
+    pub s: CpuContext,
+    pub realm: CpuContext,
+    /// The world the CPU is running, whose state lives in the lower-EL registers rather than in
+    /// its context.
+    running: Option<World>,
+    /// The world to switch to on the next exception return, if any.
+    switch_pending: Option<World>,
+}
+
+impl WorldContext {
+    const fn new() -> Self {
+        Self {
+            ns: CpuContext::new(),
+            s: CpuContext::new(),
+            realm: CpuContext::new(),
+            running: None,
+            switch_pending: None,
+        }
+    }
+
+    fn request_switch(&mut self) -> bool {
+        let Some(from) = self.running else {
+            return false;
+        };
+        let to = from.peer();
+        if !self.get_mut(to).is_runnable() {
+            return false;
+        }
+        self.switch_pending = Some(to);
+        true
+    }
+
+    fn kill_running(&mut self) -> Option<World> {
+        let from = self.running?;
+        let to = from.peer();
+        if !self.get_mut(to).is_runnable() {
+            return None;
+        }
+        self.get_mut(from).killed = true;
+        self.switch_pending = Some(to);
+        Some(from)
+    }
+
+    fn get_mut(&mut self, world: World) -> &mut CpuContext {
+        match world {
+            World::Secure => &mut self.s,
+            World::NonSecure => &mut self.ns,
+            World::Realm => &mut self.realm,
+        }
+    }
+}
+
+/// The state of a lower-EL world, as seen by EL3.
+pub struct CpuContext {
+    /// General purpose registers x0 to x30.
+    pub x: [u64; 31],
+    pub sp_el0: u64,
+    pub elr_el3: u64,
+    pub spsr_el3: u64,
+    pub el1: El1SysRegs,
+    #[cfg(feature = "fpsimd")]
+    pub fpsimd: FpSimdRegs,
+    #[cfg(feature = "pauth")]
+    pub pauth: pauth::Keys,
+    /// Whether the context holds a state, either initial or saved.
+    initialized: bool,
+    /// Whether the world was killed. Unlike `initialized`, saving the world's state on the switch
+    /// away from it doesn't clear it, only [init] does.
+    killed: bool,
+}
+
+impl CpuContext {
+    const fn new() -> Self {
+        Self {
+            x: [0; 31],
+            sp_el0: 0,
+            elr_el3: 0,
+            spsr_el3: 0,
+            el1: El1SysRegs::new(),
+            #[cfg(feature = "fpsimd")]
+            fpsimd: FpSimdRegs::new(),
+            #[cfg(feature = "pauth")]
+            pauth: pauth::Keys::new(),
+            initialized: false,
+            killed: false,
+        }
+    }
+
+    /// Returns `true` if the world can be switched to.
+    fn is_runnable(&self) -> bool {
+        self.initialized && !self.killed
+    }
+
+    /// Saves the current lower-EL state, taking the registers saved on exception entry from the
+    /// frame.
+    fn save(&mut self, frame: &ExceptionFrame) {
+        self.x = frame.x;
+        self.elr_el3 = frame.elr;
+        self.spsr_el3 = frame.spsr;
+        self.sp_el0 = frame.sp_el0;
+        unsafe {
+            save_el1_sysregs(&mut self.el1);
+            #[cfg(feature = "fpsimd")]
+            save_fpsimd_regs(&mut self.fpsimd);
+        }
+        #[cfg(feature = "pauth")]
+        self.pauth.save(frame);
+        self.initialized = true;
+    }
+
+    /// Restores this context as the lower-EL state, the registers restored on exception return
+    /// are written to the frame.
+    fn restore(&self, frame: &mut ExceptionFrame) {
+        frame.x = self.x;
+        frame.elr = self.elr_el3;
+        frame.spsr = self.spsr_el3;
+        frame.sp_el0 = self.sp_el0;
+        unsafe {
+            restore_el1_sysregs(&self.el1);
+            #[cfg(feature = "fpsimd")]
+            restore_fpsimd_regs(&self.fpsimd);
+        }
+        #[cfg(feature = "pauth")]
+        self.pauth.restore(frame);
+    }
+}
+
+/// The EL1 (and EL0) system registers that are shared between worlds.
+///
+/// The layout must match the save and restore sequences below, which access the registers in
+/// pairs.
+#[repr(C)]
+pub struct El1SysRegs {
+    pub sp_el1: u64,
+    pub elr: u64,
+    pub spsr: u64,
+    pub sctlr: u64,
+    pub actlr: u64,
+    pub cpacr: u64,
+    pub csselr: u64,
+    pub ttbr0: u64,
+    pub ttbr1: u64,
+    pub tcr: u64,
+    pub mair: u64,
+    pub amair: u64,
+    pub vbar: u64,
+    pub contextidr: u64,
+    pub tpidr_el0: u64,
+    pub tpidrro_el0: u64,
+    pub tpidr_el1: u64,
+    pub esr: u64,
+    pub far: u64,
+    pub par: u64,
+    pub afsr0: u64,
+    pub afsr1: u64,
+    pub cntkctl: u64,
+    pub mdscr: u64,
+}
+
+impl El1SysRegs {
+    const fn new() -> Self {
+        Self {
+            sp_el1: 0,
+            elr: 0,
+            spsr: 0,
+            sctlr: 0,
+            actlr: 0,
+            cpacr: 0,
+            csselr: 0,
+            ttbr0: 0,
+            ttbr1: 0,
+            tcr: 0,
+            mair: 0,
+            amair: 0,
+            vbar: 0,
+            contextidr: 0,
+            tpidr_el0: 0,
+            tpidrro_el0: 0,
+            tpidr_el1: 0,
+            esr: 0,
+            far: 0,
+            par: 0,
+            afsr0: 0,
+            afsr1: 0,
+            cntkctl: 0,
+            mdscr: 0,
+        }
+    }
+}
+
+// ——————————————————————— System Register Save/Restore ——————————————————————— //
+
+unsafe extern "C" {
+    fn save_el1_sysregs(regs: *mut El1SysRegs);
+    fn restore_el1_sysregs(regs: *const El1SysRegs);
+}
+
+global_asm!(
+r#"
+.text
+.global save_el1_sysregs
+save_el1_sysregs:
+    mrs x9, SP_EL1
+    mrs x10, ELR_EL1
+    stp x9, x10, [x0, #{sp_el1}]
+    mrs x9, SPSR_EL1
+    mrs x10, SCTLR_EL1
+    stp x9, x10, [x0, #{spsr}]
+    mrs x9, ACTLR_EL1
+    mrs x10, CPACR_EL1
+    stp x9, x10, [x0, #{actlr}]
+    mrs x9, CSSELR_EL1
+    mrs x10, TTBR0_EL1
+    stp x9, x10, [x0, #{csselr}]
+    mrs x9, TTBR1_EL1
+    mrs x10, TCR_EL1
+    stp x9, x10, [x0, #{ttbr1}]
+    mrs x9, MAIR_EL1
+    mrs x10, AMAIR_EL1
+    stp x9, x10, [x0, #{mair}]
+    mrs x9, VBAR_EL1
+    mrs x10, CONTEXTIDR_EL1
+    stp x9, x10, [x0, #{vbar}]
+    mrs x9, TPIDR_EL0
+    mrs x10, TPIDRRO_EL0
+    stp x9, x10, [x0, #{tpidr_el0}]
+    mrs x9, TPIDR_EL1
+    mrs x10, ESR_EL1
+    stp x9, x10, [x0, #{tpidr_el1}]
+    mrs x9, FAR_EL1
+    mrs x10, PAR_EL1
+    stp x9, x10, [x0, #{far}]
+    mrs x9, AFSR0_EL1
+    mrs x10, AFSR1_EL1
+    stp x9, x10, [x0, #{afsr0}]
+    mrs x9, CNTKCTL_EL1
+    mrs x10, MDSCR_EL1
+    stp x9, x10, [x0, #{cntkctl}]
+    ret
+
+.global restore_el1_sysregs
+restore_el1_sysregs:
+    ldp x9, x10, [x0, #{sp_el1}]
+    msr SP_EL1, x9
+    msr ELR_EL1, x10
+    ldp x9, x10, [x0, #{spsr}]
+    msr SPSR_EL1, x9
+    msr SCTLR_EL1, x10
+    ldp x9, x10, [x0, #{actlr}]
+    msr ACTLR_EL1, x9
+    msr CPACR_EL1, x10
+    ldp x9, x10, [x0, #{csselr}]
+    msr CSSELR_EL1, x9
+    msr TTBR0_EL1, x10
+    ldp x9, x10, [x0, #{ttbr1}]
+    msr TTBR1_EL1, x9
+    msr TCR_EL1, x10
+    ldp x9, x10, [x0, #{mair}]
+    msr MAIR_EL1, x9
+    msr AMAIR_EL1, x10
+    ldp x9, x10, [x0, #{vbar}]
+    msr VBAR_EL1, x9
+    msr CONTEXTIDR_EL1, x10
+    ldp x9, x10, [x0, #{tpidr_el0}]
+    msr TPIDR_EL0, x9
+    msr TPIDRRO_EL0, x10
+    ldp x9, x10, [x0, #{tpidr_el1}]
+    msr TPIDR_EL1, x9
+    msr ESR_EL1, x10
+    ldp x9, x10, [x0, #{far}]
+    msr FAR_EL1, x9
+    msr PAR_EL1, x10
+    ldp x9, x10, [x0, #{afsr0}]
+    msr AFSR0_EL1, x9
+    msr AFSR1_EL1, x10
+    ldp x9, x10, [x0, #{cntkctl}]
+    msr CNTKCTL_EL1, x9
+    msr MDSCR_EL1, x10
+    isb
+    ret
+"#,
+    sp_el1 = const offset_of!(El1SysRegs, sp_el1),
+    spsr = const offset_of!(El1SysRegs, spsr),
+    actlr = const offset_of!(El1SysRegs, actlr),
+    csselr = const offset_of!(El1SysRegs, csselr),
+    ttbr1 = const offset_of!(El1SysRegs, ttbr1),
+    mair = const offset_of!(El1SysRegs, mair),
+    vbar = const offset_of!(El1SysRegs, vbar),
+    tpidr_el0 = const offset_of!(El1SysRegs, tpidr_el0),
+    tpidr_el1 = const offset_of!(El1SysRegs, tpidr_el1),
+    far = const offset_of!(El1SysRegs, far),
+    afsr0 = const offset_of!(El1SysRegs, afsr0),
+    cntkctl = const offset_of!(El1SysRegs, cntkctl),
+);
+
+// ————————————————————————————— FP/SIMD State —————————————————————————————— //
+
+/// The FP/SIMD registers, shared between worlds when FP/SIMD is enabled.
+///
+/// The layout must match the save and restore sequences below.
+#[cfg(feature = "fpsimd")]
+#[repr(C)]
+pub struct FpSimdRegs {
+    pub q: [u128; 32],
+    pub fpcr: u64,
+    pub fpsr: u64,
+}
+
+#[cfg(feature = "fpsimd")]
+impl FpSimdRegs {
+    const fn new() -> Self {
+        Self {
+            q: [0; 32],
+            fpcr: 0,
+            fpsr: 0,
+        }
+    }
+}
+
+#[cfg(feature = "fpsimd")]
+unsafe extern "C" {
+    fn save_fpsimd_regs(regs: *mut FpSimdRegs);
+    fn restore_fpsimd_regs(regs: *const FpSimdRegs);
+}
+
+// The monitor is built for a soft-float target, FP/SIMD instructions must be enabled explicitly.
+#[cfg(feature = "fpsimd")]
+global_asm!(
+r#"
+.text
+.arch_extension fp
+.arch_extension simd
+.global save_fpsimd_regs
+save_fpsimd_regs:
+    stp q0, q1, [x0, #0x0]
+    stp q2, q3, [x0, #0x20]
+    stp q4, q5, [x0, #0x40]
+    stp q6, q7, [x0, #0x60]
+    stp q8, q9, [x0, #0x80]
+    stp q10, q11, [x0, #0xa0]
+    stp q12, q13, [x0, #0xc0]
+    stp q14, q15, [x0, #0xe0]
+    stp q16, q17, [x0, #0x100]
+    stp q18, q19, [x0, #0x120]
+    stp q20, q21, [x0, #0x140]
+    stp q22, q23, [x0, #0x160]
+    stp q24, q25, [x0, #0x180]
+    stp q26, q27, [x0, #0x1a0]
+    stp q28, q29, [x0, #0x1c0]
+    stp q30, q31, [x0, #0x1e0]
+    mrs x9, FPCR
+    str x9, [x0, #{fpcr}]
+    mrs x9, FPSR
+    str x9, [x0, #{fpsr}]
+    ret
+
+.global restore_fpsimd_regs
+restore_fpsimd_regs:
+    ldp q0, q1, [x0, #0x0]
+    ldp q2, q3, [x0, #0x20]
+    ldp q4, q5, [x0, #0x40]
+    ldp q6, q7, [x0, #0x60]
+    ldp q8, q9, [x0, #0x80]
+    ldp q10, q11, [x0, #0xa0]
+    ldp q12, q13, [x0, #0xc0]
+    ldp q14, q15, [x0, #0xe0]
+    ldp q16, q17, [x0, #0x100]
+    ldp q18, q19, [x0, #0x120]
+    ldp q20, q21, [x0, #0x140]
+    ldp q22, q23, [x0, #0x160]
+    ldp q24, q25, [x0, #0x180]
+    ldp q26, q27, [x0, #0x1a0]
+    ldp q28, q29, [x0, #0x1c0]
+    ldp q30, q31, [x0, #0x1e0]
+    ldr x9, [x0, #{fpcr}]
+    msr FPCR, x9
+    ldr x9, [x0, #{fpsr}]
+    msr FPSR, x9
+    ret
+"#,
+    fpcr = const offset_of!(FpSimdRegs, fpcr),
+    fpsr = const offset_of!(FpSimdRegs, fpsr),
+);
+
+kernel_test! {
+    fn killed_world_is_not_resumed() {
+        let mut worlds = WorldContext::new();
+        worlds.running = Some(World::NonSecure);
+        assert_eq!(worlds.kill_running(), None);
+        worlds.s.initialized = true;
+        assert_eq!(worlds.kill_running(), Some(World::NonSecure));
+        assert_eq!(worlds.switch_pending, Some(World::Secure));
+
+        // The switch away saves the killed world's state, it must stay dead
+        let frame = ExceptionFrame {
+            x: [0; 31],
+            sp_el0: 0,
+            elr: 0,
+            spsr: 0,
+            esr: 0,
+            far: 0,
+            apia: [0; 2],
+        };
+        worlds.ns.save(&frame);
+        worlds.running = worlds.switch_pending.take();
+        assert!(!worlds.request_switch());
+        assert_eq!(worlds.switch_pending, None);
+    }
+}
@@ -0,0 +1,63 @@
+//! Data Independent Timing (FEAT_DIT).
+//!
+//! With PSTATE.DIT set, the timing of data-processing instructions doesn't depend on the values
+//! they operate on. The monitor sets it at boot, and again on each exception entry: taking an
+//! exception doesn't set it, so exceptions from the lower ELs run with the value of DIT of the
+//! interrupted EL.
+//!
+//! DIT only covers instruction timing, code handling secrets must still avoid branches and memory
+//! accesses that depend on them (see `crypto_util::ct_eq`).
+
+use crate::arch::feature;
+use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once DIT is enabled, read by the exception entry code.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The DIT bit, as accessed through the `DIT` register.
+const DIT: u64 = 1 << 24;
+
+/// Enables DIT at EL3, if it is implemented.
+pub fn enable() {
+    if !feature::has_dit() {
+        log::info!("DIT: not implemented");
+        return;
+    }
+
+    // The assembler might not know the DIT register
+    unsafe { asm!("msr S3_3_C4_C2_5, {}", in(reg) DIT) };
+    ENABLED.store(true, Ordering::Relaxed);
+    log::info!("DIT: enabled");
+}
+
+/// Returns `true` if PSTATE.DIT is set.
+pub fn is_enabled() -> bool {
+    if !feature::has_dit() {
+        return false;
+    }
+    let value: u64;
+    unsafe { asm!("mrs {}, S3_3_C4_C2_5", out(reg) value) };
+    value & DIT != 0
+}
+
+// Sets PSTATE.DIT again if it was enabled at boot, called by the exception vectors.
+//
+// Clobbers x0, and must be called through `bl`.
+global_asm!(
+r#"
+.pushsection .text.l4sm_reassert_dit, "ax"
+.global l4sm_reassert_dit
+l4sm_reassert_dit:
+    adrp x0, {enabled}
+    ldrb w0, [x0, :lo12:{enabled}]
+    cbz w0, 1f
+    mov x0, #{dit}
+    msr S3_3_C4_C2_5, x0
+1:
+    ret
+.popsection
+"#,
+    enabled = sym ENABLED,
+    dit = const DIT,
+);
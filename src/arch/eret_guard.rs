@@ -0,0 +1,130 @@
+//! Checks of the state the monitor returns to a lower EL with.
+//!
+//! Every ERET to a lower EL goes through [prepare]: the initial entry into a world, and every
+//! exception return from a lower EL, world switches included. A bug letting a lower EL choose
+//! the SPSR of the return could otherwise hand it EL3.
+//!
+//! The lower ELs keep control of their own DAIF masks: the interrupts and SErrors routed to EL3
+//! are taken regardless of them.
+
+use crate::arch::context::World;
+use crate::arch::feature;
+use crate::ktest::kernel_test;
+use crate::memory_layout;
+use core::fmt;
+
+/// The SPSR_EL3 bits defined for a return to AArch64: NZCV, TCO, DIT, UAO, PAN, SS, IL, ALLINT,
+/// SSBS, BTYPE, DAIF, and M.
+const SPSR_DEFINED: u64 = (0b1111 << 28)
+    | (0b11_1111 << 20)
+    | (0b1111 << 10)
+    | (0b1111 << 6)
+    | SPSR_M_EXECUTION_STATE
+    | SPSR_M_MODE;
+
+/// SPSR.M[4], set for a return to AArch32.
+const SPSR_M_EXECUTION_STATE: u64 = 1 << 4;
+/// SPSR.M[3:0], the target EL and stack pointer.
+const SPSR_M_MODE: u64 = 0b1111;
+
+/// A reason to refuse a return to a lower EL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// The SPSR targets AArch32, lower ELs run in AArch64.
+    Aarch32,
+    /// The SPSR targets a mode that the world can't run in, with the value of M[3:0].
+    Mode(u64),
+    /// The return address is within the monitor image.
+    ElrInMonitor(u64),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Violation::Aarch32 => write!(f, "return to AArch32"),
+            Violation::Mode(mode) => write!(f, "return to mode {mode:#06b}"),
+            Violation::ElrInMonitor(elr) => write!(f, "return address {elr:#x} in the monitor"),
+        }
+    }
+}
+
+/// Checks a return to `world` with `spsr` and `elr`, and returns the SPSR to use.
+///
+/// Bits of the SPSR that are not defined for AArch64 are cleared. The return is refused if it
+/// targets EL3, AArch32, or an EL2 that the world doesn't have, or if ELR points into the monitor
+/// image. ELR is a virtual address of the target EL, but there is no good reason for a lower EL to
+/// run at an address colliding with the monitor, with its MMU off it would run the monitor's code.
+pub fn prepare(spsr: u64, elr: u64, world: World) -> Result<u64, Violation> {
+    if spsr & SPSR_M_EXECUTION_STATE != 0 {
+        return Err(Violation::Aarch32);
+    }
+
+    let mode = spsr & SPSR_M_MODE;
+    let allowed = match mode {
+        // EL0t, EL1t, and EL1h
+        0b0000 | 0b0100 | 0b0101 => true,
+        // EL2t and EL2h
+        0b1000 | 0b1001 => has_el2(world),
+        _ => false,
+    };
+    if !allowed {
+        return Err(Violation::Mode(mode));
+    }
+
+    let image = memory_layout::image();
+    if (image.start as u64..image.end as u64).contains(&elr) {
+        return Err(Violation::ElrInMonitor(elr));
+    }
+
+    Ok(spsr & SPSR_DEFINED)
+}
+
+/// Returns `true` if the world has an EL2.
+fn has_el2(world: World) -> bool {
+    match world {
+        World::Secure => feature::has_sel2(),
+        World::NonSecure | World::Realm => feature::has_el2(),
+    }
+}
+
+kernel_test! {
+    fn prepare_sanitizes_returns() {
+        let image = memory_layout::image();
+        let (start, end) = (image.start as u64, image.end as u64);
+        let el2 = |world| match has_el2(world) {
+            true => Ok(0b1001),
+            false => Err(Violation::Mode(0b1001)),
+        };
+        let cases = [
+            // Allowed modes
+            (0b0000, end, World::NonSecure, Ok(0b0000)),
+            (0b0100, end, World::Secure, Ok(0b0100)),
+            (0b0101, end, World::Realm, Ok(0b0101)),
+            (0b1001, end, World::NonSecure, el2(World::NonSecure)),
+            (0b1001, end, World::Secure, el2(World::Secure)),
+            (0b1001, end, World::Realm, el2(World::Realm)),
+            // AArch32, whatever the mode
+            (0b1_0000, end, World::NonSecure, Err(Violation::Aarch32)),
+            (0b1_0011, end, World::Secure, Err(Violation::Aarch32)),
+            (0b1_1101, end, World::NonSecure, Err(Violation::Aarch32)),
+            // EL3t and EL3h
+            (0b1100, end, World::NonSecure, Err(Violation::Mode(0b1100))),
+            (0b1101, end, World::Secure, Err(Violation::Mode(0b1101))),
+            // Reserved modes
+            (0b0001, end, World::NonSecure, Err(Violation::Mode(0b0001))),
+            (0b0110, end, World::NonSecure, Err(Violation::Mode(0b0110))),
+            (0b1011, end, World::Realm, Err(Violation::Mode(0b1011))),
+            // Return addresses around the image
+            (0b0101, start, World::NonSecure, Err(Violation::ElrInMonitor(start))),
+            (0b0101, end - 4, World::Secure, Err(Violation::ElrInMonitor(end - 4))),
+            (0b0101, end, World::NonSecure, Ok(0b0101)),
+        ];
+        for (spsr, elr, world, expected) in cases {
+            assert_eq!(prepare(spsr, elr, world), expected, "SPSR {spsr:#x}, ELR {elr:#x}");
+        }
+
+        // Reserved bits are cleared, defined ones are kept
+        let spsr = !(SPSR_M_EXECUTION_STATE | SPSR_M_MODE) | 0b0101;
+        assert_eq!(prepare(spsr, end, World::NonSecure), Ok(0xF3F0_3FC5));
+    }
+}
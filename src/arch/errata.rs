@@ -0,0 +1,153 @@
+//! CPU errata workarounds, selected from `MIDR_EL1`.
+//!
+//! Each [Erratum] knows which cores it affects and how to work around it. [apply_all] walks the
+//! table once at boot, and [is_active] tells whether a workaround is in place, for instance to
+//! answer the SMCCC workaround discovery calls.
+
+use crate::arch::midr::{CORTEX_A53, CORTEX_A57, CORTEX_A72, Midr};
+use crate::ktest::kernel_test;
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Identifies an erratum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Id {
+    /// An erratum from the Arm Software Developer Errata Notices.
+    Arm(u32),
+    /// A vulnerability, by CVE year and number.
+    Cve(u16, u32),
+}
+
+/// Speculative Store Bypass (Spectre variant 4).
+pub const CVE_2018_3639: Id = Id::Cve(2018, 3639);
+
+/// A CPU erratum and its workaround.
+pub struct Erratum {
+    pub id: Id,
+    /// Returns `true` if the erratum affects the given PE.
+    pub applies: fn(Midr) -> bool,
+    /// Applies the workaround on the current PE.
+    pub apply: fn(),
+}
+
+/// The known errata.
+static ERRATA: [Erratum; 3] = [
+    Erratum {
+        id: Id::Arm(855873),
+        applies: |midr| midr.is_any(&[CORTEX_A53]) && midr.is_at_least(0, 3),
+        apply: || set_cpuactlr_bits(CPUACTLR_ENDCCASCI),
+    },
+    Erratum {
+        id: Id::Arm(859971),
+        applies: |midr| midr.is_any(&[CORTEX_A72]) && midr.is_at_most(0, 3),
+        apply: || set_cpuactlr_bits(CPUACTLR_DIS_INSTR_PREFETCH),
+    },
+    Erratum {
+        id: CVE_2018_3639,
+        applies: |midr| midr.is_any(&[CORTEX_A57, CORTEX_A72]),
+        apply: || set_cpuactlr_bits(CPUACTLR_DIS_LOAD_PASS_STORE),
+    },
+];
+
+/// The errata whose workaround is applied, one bit per entry of [ERRATA].
+static ACTIVE: AtomicU32 = AtomicU32::new(0);
+
+const _: () = assert!(ERRATA.len() <= u32::BITS as usize);
+
+/// Applies the workarounds for the errata affecting the current PE, logging each decision.
+pub fn apply_all() {
+    let midr = Midr::read();
+    log::info!("CPU: {midr}");
+
+    for (index, erratum) in ERRATA.iter().enumerate() {
+        if (erratum.applies)(midr) {
+            (erratum.apply)();
+            ACTIVE.fetch_or(1 << index, Ordering::Relaxed);
+            log::info!("{}: applied", erratum.id);
+        } else {
+            log::debug!("{}: not applicable", erratum.id);
+        }
+    }
+}
+
+/// Returns `true` if the workaround for the given erratum has been applied.
+pub fn is_active(id: Id) -> bool {
+    let active = ACTIVE.load(Ordering::Relaxed);
+    ERRATA
+        .iter()
+        .enumerate()
+        .any(|(index, erratum)| erratum.id == id && active & (1 << index) != 0)
+}
+
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Id::Arm(number) => write!(f, "erratum {number}"),
+            Id::Cve(year, number) => write!(f, "CVE-{year}-{number}"),
+        }
+    }
+}
+
+// ——————————————————————————— Auxiliary Control ———————————————————————————— //
+
+// The bits of the implementation defined CPUACTLR_EL1 used by the workarounds. The register has
+// the same encoding on the Cortex-A53, A57 and A72, but not the same layout.
+
+/// Cortex-A53: enable data cache clean as data cache clean/invalidate.
+const CPUACTLR_ENDCCASCI: u64 = 1 << 44;
+/// Cortex-A72: disable instruction prefetch.
+const CPUACTLR_DIS_INSTR_PREFETCH: u64 = 1 << 32;
+/// Cortex-A57 and A72: disable load pass store.
+const CPUACTLR_DIS_LOAD_PASS_STORE: u64 = 1 << 55;
+
+/// Sets bits in `CPUACTLR_EL1`.
+fn set_cpuactlr_bits(bits: u64) {
+    unsafe {
+        asm!(
+            "mrs {tmp}, S3_1_C15_C2_0",
+            "orr {tmp}, {tmp}, {bits}",
+            "msr S3_1_C15_C2_0, {tmp}",
+            "isb",
+            tmp = out(reg) _,
+            bits = in(reg) bits,
+        );
+    }
+}
+
+kernel_test! {
+    fn errata_predicates() {
+        use crate::arch::midr::Model;
+
+        // MIDR_EL1 of the given model and revision, with the architecture field set
+        let midr = |model: Model, variant: u64, revision: u64| {
+            let value = ((model.implementer as u64) << 24)
+                | (variant << 20)
+                | (0xF << 16)
+                | ((model.part as u64) << 4)
+                | revision;
+            Midr::decode(value)
+        };
+        // Whether each entry of ERRATA applies: 855873, 859971, and CVE-2018-3639
+        let cases = [
+            (CORTEX_A53, 0, 0, [false, false, false]),
+            (CORTEX_A53, 0, 2, [false, false, false]),
+            (CORTEX_A53, 0, 3, [true, false, false]),
+            (CORTEX_A53, 0, 4, [true, false, false]),
+            (CORTEX_A53, 1, 0, [true, false, false]),
+            (CORTEX_A57, 0, 0, [false, false, true]),
+            (CORTEX_A57, 1, 4, [false, false, true]),
+            (CORTEX_A72, 0, 0, [false, true, true]),
+            (CORTEX_A72, 0, 3, [false, true, true]),
+            (CORTEX_A72, 0, 4, [false, false, true]),
+            (CORTEX_A72, 1, 0, [false, false, true]),
+            (CORTEX_A72, 1, 4, [false, false, true]),
+        ];
+        for (model, variant, revision, expected) in cases {
+            let midr = midr(model, variant, revision);
+            for (erratum, expected) in ERRATA.iter().zip(expected) {
+                assert_eq!((erratum.applies)(midr), expected, "{} on {midr}", erratum.id);
+            }
+        }
+    }
+}
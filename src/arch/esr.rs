@@ -0,0 +1,566 @@
+//! Decoding of the Exception Syndrome Register (ESR_EL3).
+//!
+//! Only the exception classes we expect to see at EL3 are decoded in details, other classes are
+//! reported with their raw exception class and syndrome.
+
+use crate::arch::serror;
+use crate::ktest::kernel_test;
+use core::fmt;
+
+/// Decodes an ESR value.
+pub fn decode(esr: u64) -> EsrInfo {
+    let ec = ((esr >> 26) & 0x3F) as u8;
+    let iss = (esr & 0x01FF_FFFF) as u32;
+    let imm16 = (iss & 0xFFFF) as u16;
+
+    let class = match ec {
+        0x07 => ExceptionClass::FpSimd,
+        0x11 | 0x15 => ExceptionClass::Svc { imm: imm16 },
+        0x12 | 0x16 => ExceptionClass::Hvc { imm: imm16 },
+        0x13 | 0x17 => ExceptionClass::Smc { imm: imm16 },
+        0x18 => ExceptionClass::SysReg {
+            reg: SysReg {
+                op0: ((iss >> 20) & 0b11) as u8,
+                op2: ((iss >> 17) & 0b111) as u8,
+                op1: ((iss >> 14) & 0b111) as u8,
+                crn: ((iss >> 10) & 0xF) as u8,
+                crm: ((iss >> 1) & 0xF) as u8,
+            },
+            rt: ((iss >> 5) & 0x1F) as u8,
+            read: iss & 1 != 0,
+        },
+        0x1E => ExceptionClass::Gpc {
+            status: GpcStatus::decode(((iss >> 14) & 0x3F) as u8),
+            instruction: iss & (1 << 20) != 0,
+            write: iss & (1 << 6) != 0,
+            s1ptw: iss & (1 << 7) != 0,
+            s2ptw: iss & (1 << 21) != 0,
+        },
+        0x20 | 0x21 => ExceptionClass::InstructionAbort {
+            lower_el: ec == 0x20,
+            status: FaultStatus::decode((iss & 0x3F) as u8),
+            far_valid: iss & (1 << 10) == 0,
+        },
+        0x22 => ExceptionClass::PcAlignment,
+        0x24 | 0x25 => ExceptionClass::DataAbort {
+            lower_el: ec == 0x24,
+            status: FaultStatus::decode((iss & 0x3F) as u8),
+            write: iss & (1 << 6) != 0,
+            far_valid: iss & (1 << 10) == 0,
+        },
+        0x26 => ExceptionClass::SpAlignment,
+        0x2F => ExceptionClass::SError {
+            syndrome: serror::Syndrome::decode(iss),
+        },
+        0x3C => ExceptionClass::Brk { comment: imm16 },
+        _ => ExceptionClass::Other,
+    };
+
+    EsrInfo {
+        ec,
+        iss,
+        aarch32: matches!(ec, 0x11..=0x13),
+        class,
+    }
+}
+
+/// An instruction calling into a higher EL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Call {
+    Svc,
+    Hvc,
+    Smc,
+}
+
+/// Returns the call instruction that caused the exception, with its immediate.
+///
+/// Returns `None` for other exception classes: their low ISS bits are not an immediate, even when
+/// they look like one (the comment of a BRK for instance).
+pub fn call_immediate(esr: u64) -> Option<(Call, u16)> {
+    match decode(esr).class {
+        ExceptionClass::Svc { imm } => Some((Call::Svc, imm)),
+        ExceptionClass::Hvc { imm } => Some((Call::Hvc, imm)),
+        ExceptionClass::Smc { imm } => Some((Call::Smc, imm)),
+        _ => None,
+    }
+}
+
+/// A decoded exception syndrome.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EsrInfo {
+    /// The raw exception class.
+    pub ec: u8,
+    /// The raw instruction specific syndrome.
+    pub iss: u32,
+    /// Whether the exception was caused by an AArch32 instruction (SVC, HVC, and SMC only).
+    pub aarch32: bool,
+    /// The decoded exception class.
+    pub class: ExceptionClass,
+}
+
+/// The exception classes decoded by [decode].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionClass {
+    /// Access to FP/SIMD registers, trapped by `CPTR_EL3.TFP`.
+    FpSimd,
+    Svc {
+        imm: u16,
+    },
+    Hvc {
+        imm: u16,
+    },
+    Smc {
+        imm: u16,
+    },
+    /// Trapped MSR, MRS, or system instruction.
+    SysReg {
+        reg: SysReg,
+        /// The general purpose register used for the transfer.
+        rt: u8,
+        /// `true` for an MRS (read), `false` for an MSR (write).
+        read: bool,
+    },
+    /// Granule Protection Check exception, taken to EL3 when a GPT lookup fails.
+    Gpc {
+        status: GpcStatus,
+        /// `true` if the fault was caused by an instruction fetch.
+        instruction: bool,
+        /// `true` if the abort was caused by a write.
+        write: bool,
+        /// `true` if the fault happened on a stage 1 translation table walk.
+        s1ptw: bool,
+        /// `true` if the fault happened on a stage 2 translation table walk.
+        s2ptw: bool,
+    },
+    InstructionAbort {
+        lower_el: bool,
+        status: FaultStatus,
+        far_valid: bool,
+    },
+    PcAlignment,
+    DataAbort {
+        lower_el: bool,
+        status: FaultStatus,
+        /// `true` if the abort was caused by a write.
+        write: bool,
+        far_valid: bool,
+    },
+    SpAlignment,
+    SError {
+        syndrome: serror::Syndrome,
+    },
+    Brk {
+        comment: u16,
+    },
+    Other,
+}
+
+impl EsrInfo {
+    /// Returns `true` if FAR_EL3 holds the faulting address for this exception.
+    pub fn far_valid(&self) -> bool {
+        match self.class {
+            ExceptionClass::InstructionAbort { far_valid, .. }
+            | ExceptionClass::DataAbort { far_valid, .. } => far_valid,
+            ExceptionClass::PcAlignment | ExceptionClass::Gpc { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the exception is caused by a granule protection check, either reported
+    /// as such or as an instruction or data abort.
+    pub fn is_granule_protection_fault(&self) -> bool {
+        match self.class {
+            ExceptionClass::Gpc { .. } => true,
+            ExceptionClass::InstructionAbort { status, .. }
+            | ExceptionClass::DataAbort { status, .. } => matches!(
+                status,
+                FaultStatus::GranuleProtection | FaultStatus::GranuleProtectionOnWalk { .. }
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for EsrInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.aarch32 { "AArch32" } else { "AArch64" };
+        match self.class {
+            ExceptionClass::FpSimd => write!(f, "FP/SIMD access trapped"),
+            ExceptionClass::Svc { imm } => write!(f, "SVC #{imm:#x} ({state})"),
+            ExceptionClass::Hvc { imm } => write!(f, "HVC #{imm:#x} ({state})"),
+            ExceptionClass::Smc { imm } => write!(f, "SMC #{imm:#x} ({state})"),
+            ExceptionClass::SysReg { reg, rt, read } => {
+                if read {
+                    write!(f, "Trapped MRS x{rt}, {reg}")
+                } else {
+                    write!(f, "Trapped MSR {reg}, x{rt}")
+                }
+            }
+            ExceptionClass::Gpc {
+                status,
+                instruction,
+                write,
+                s1ptw,
+                s2ptw,
+            } => {
+                let access = match (instruction, write) {
+                    (true, _) => "instruction fetch",
+                    (false, true) => "write",
+                    (false, false) => "read",
+                };
+                write!(f, "Granule protection check on {access}: {status}")?;
+                if s1ptw {
+                    write!(f, " (stage 1 walk)")?;
+                }
+                if s2ptw {
+                    write!(f, " (stage 2 walk)")?;
+                }
+                Ok(())
+            }
+            ExceptionClass::InstructionAbort {
+                lower_el, status, ..
+            } => {
+                let el = if lower_el { "lower" } else { "current" };
+                write!(f, "Instruction abort from {el} EL: {status}")
+            }
+            ExceptionClass::PcAlignment => write!(f, "PC alignment fault"),
+            ExceptionClass::DataAbort {
+                lower_el,
+                status,
+                write,
+                ..
+            } => {
+                let el = if lower_el { "lower" } else { "current" };
+                let access = if write { "write" } else { "read" };
+                write!(f, "Data abort from {el} EL on {access}: {status}")
+            }
+            ExceptionClass::SpAlignment => write!(f, "SP alignment fault"),
+            ExceptionClass::SError { syndrome } => write!(f, "SError: {syndrome}"),
+            ExceptionClass::Brk { comment } => write!(f, "BRK #{comment:#x}"),
+            ExceptionClass::Other => {
+                write!(f, "Exception class {:#04x}, ISS {:#09x}", self.ec, self.iss)
+            }
+        }
+    }
+}
+
+// —————————————————————————————— Fault Status —————————————————————————————— //
+
+/// The fault status code of an instruction or data abort (IFSC/DFSC).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultStatus {
+    AddressSize { level: i8 },
+    Translation { level: i8 },
+    AccessFlag { level: i8 },
+    Permission { level: i8 },
+    SyncExternal,
+    SyncExternalOnWalk { level: i8 },
+    TagCheck,
+    Parity,
+    Alignment,
+    TlbConflict,
+    GranuleProtection,
+    GranuleProtectionOnWalk { level: i8 },
+    Other(u8),
+}
+
+impl FaultStatus {
+    fn decode(code: u8) -> Self {
+        let level = (code & 0b11) as i8;
+        match code {
+            0x00..=0x03 => FaultStatus::AddressSize { level },
+            0x04..=0x07 => FaultStatus::Translation { level },
+            0x08..=0x0B => FaultStatus::AccessFlag { level },
+            0x0C..=0x0F => FaultStatus::Permission { level },
+            0x10 => FaultStatus::SyncExternal,
+            0x11 => FaultStatus::TagCheck,
+            0x13 => FaultStatus::SyncExternalOnWalk { level: -1 },
+            0x14..=0x17 => FaultStatus::SyncExternalOnWalk { level },
+            0x18 => FaultStatus::Parity,
+            0x21 => FaultStatus::Alignment,
+            0x23 => FaultStatus::GranuleProtectionOnWalk { level: -1 },
+            0x24..=0x27 => FaultStatus::GranuleProtectionOnWalk { level },
+            0x28 => FaultStatus::GranuleProtection,
+            0x29 => FaultStatus::AddressSize { level: -1 },
+            0x2B => FaultStatus::Translation { level: -1 },
+            0x30 => FaultStatus::TlbConflict,
+            _ => FaultStatus::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultStatus::AddressSize { level } => write!(f, "address size fault, level {level}"),
+            FaultStatus::Translation { level } => write!(f, "translation fault, level {level}"),
+            FaultStatus::AccessFlag { level } => write!(f, "access flag fault, level {level}"),
+            FaultStatus::Permission { level } => write!(f, "permission fault, level {level}"),
+            FaultStatus::SyncExternal => write!(f, "synchronous external abort"),
+            FaultStatus::SyncExternalOnWalk { level } => {
+                write!(f, "synchronous external abort on table walk, level {level}")
+            }
+            FaultStatus::TagCheck => write!(f, "tag check fault"),
+            FaultStatus::Parity => write!(f, "parity or ECC error"),
+            FaultStatus::Alignment => write!(f, "alignment fault"),
+            FaultStatus::TlbConflict => write!(f, "TLB conflict abort"),
+            FaultStatus::GranuleProtection => write!(f, "granule protection fault"),
+            FaultStatus::GranuleProtectionOnWalk { level } => {
+                write!(f, "granule protection fault on table walk, level {level}")
+            }
+            FaultStatus::Other(code) => write!(f, "fault status {code:#04x}"),
+        }
+    }
+}
+
+// ——————————————————————— Granule Protection Status ———————————————————————— //
+
+/// The status code of a Granule Protection Check exception (GPCSC).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpcStatus {
+    /// The GPT address is out of the configured physical address size.
+    AddressSize {
+        level: u8,
+    },
+    /// The GPT walk hit an invalid descriptor.
+    Walk {
+        level: u8,
+    },
+    /// The GPT entry doesn't allow the access.
+    Protection {
+        level: u8,
+    },
+    /// Synchronous external abort while fetching a GPT entry.
+    SyncExternalOnFetch {
+        level: u8,
+    },
+    Other(u8),
+}
+
+impl GpcStatus {
+    fn decode(code: u8) -> Self {
+        let level = code & 0b11;
+        match code >> 2 {
+            0b0000 => GpcStatus::AddressSize { level },
+            0b0001 => GpcStatus::Walk { level },
+            0b0011 => GpcStatus::Protection { level },
+            0b0101 => GpcStatus::SyncExternalOnFetch { level },
+            _ => GpcStatus::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for GpcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpcStatus::AddressSize { level } => write!(f, "GPT address size fault, level {level}"),
+            GpcStatus::Walk { level } => write!(f, "GPT walk fault, level {level}"),
+            GpcStatus::Protection { level } => write!(f, "granule protection fault, level {level}"),
+            GpcStatus::SyncExternalOnFetch { level } => {
+                write!(f, "synchronous external abort on GPT fetch, level {level}")
+            }
+            GpcStatus::Other(code) => write!(f, "GPC status {code:#04x}"),
+        }
+    }
+}
+
+// ———————————————————————————— System Registers ———————————————————————————— //
+
+/// A system register encoding, as reported by a trapped MSR or MRS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SysReg {
+    pub op0: u8,
+    pub op1: u8,
+    pub crn: u8,
+    pub crm: u8,
+    pub op2: u8,
+}
+
+impl SysReg {
+    /// Returns the name of the register, if it is one we know about.
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match (self.op0, self.op1, self.crn, self.crm, self.op2) {
+            (3, 0, 0, 0, 0) => "MIDR_EL1",
+            (3, 0, 0, 0, 5) => "MPIDR_EL1",
+            (3, 0, 0, 0, 6) => "REVIDR_EL1",
+            (3, 0, 0, 4, 0) => "ID_AA64PFR0_EL1",
+            (3, 0, 0, 4, 1) => "ID_AA64PFR1_EL1",
+            (3, 0, 0, 4, 2) => "ID_AA64PFR2_EL1",
+            (3, 0, 0, 4, 4) => "ID_AA64ZFR0_EL1",
+            (3, 0, 0, 4, 5) => "ID_AA64SMFR0_EL1",
+            (3, 0, 0, 5, 0) => "ID_AA64DFR0_EL1",
+            (3, 0, 0, 5, 1) => "ID_AA64DFR1_EL1",
+            (3, 0, 0, 5, 4) => "ID_AA64AFR0_EL1",
+            (3, 0, 0, 5, 5) => "ID_AA64AFR1_EL1",
+            (3, 0, 0, 6, 0) => "ID_AA64ISAR0_EL1",
+            (3, 0, 0, 6, 1) => "ID_AA64ISAR1_EL1",
+            (3, 0, 0, 6, 2) => "ID_AA64ISAR2_EL1",
+            (3, 0, 0, 7, 0) => "ID_AA64MMFR0_EL1",
+            (3, 0, 0, 7, 1) => "ID_AA64MMFR1_EL1",
+            (3, 0, 0, 7, 2) => "ID_AA64MMFR2_EL1",
+            (3, 0, 0, 7, 3) => "ID_AA64MMFR3_EL1",
+            (3, 3, 14, 0, 0) => "CNTFRQ_EL0",
+            (3, 3, 14, 0, 1) => "CNTPCT_EL0",
+            (3, 3, 14, 0, 2) => "CNTVCT_EL0",
+            (3, 3, 14, 2, 0) => "CNTP_TVAL_EL0",
+            (3, 3, 14, 2, 1) => "CNTP_CTL_EL0",
+            (3, 3, 14, 2, 2) => "CNTP_CVAL_EL0",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+impl fmt::Display for SysReg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(
+                f,
+                "S{}_{}_C{}_C{}_{}",
+                self.op0, self.op1, self.crn, self.crm, self.op2
+            ),
+        }
+    }
+}
+
+kernel_test! {
+    fn decodes_real_syndromes() {
+        let class = |esr| decode(esr).class;
+
+        // Calls, the immediate in the low bits of the ISS
+        assert_eq!(class(0x5E00_0000), ExceptionClass::Smc { imm: 0 });
+        assert_eq!(class(0x5E00_1234), ExceptionClass::Smc { imm: 0x1234 });
+        assert!(decode(0x4E00_0000).aarch32);
+        assert!(!decode(0x5E00_0000).aarch32);
+        assert_eq!(class(0x5A00_0000), ExceptionClass::Hvc { imm: 0 });
+        assert_eq!(class(0x5600_0001), ExceptionClass::Svc { imm: 1 });
+        assert_eq!(class(0xF200_F000), ExceptionClass::Brk { comment: 0xF000 });
+        assert_eq!(class(0xF200_0000), ExceptionClass::Brk { comment: 0 });
+
+        // Data aborts: current EL write with a level 3 translation fault, lower EL read with a
+        // level 2 permission fault, and an external abort without a valid FAR
+        let esr = decode(0x9600_0047);
+        assert_eq!(
+            esr.class,
+            ExceptionClass::DataAbort {
+                lower_el: false,
+                status: FaultStatus::Translation { level: 3 },
+                write: true,
+                far_valid: true,
+            }
+        );
+        assert!(esr.far_valid());
+        assert_eq!(
+            class(0x9200_000E),
+            ExceptionClass::DataAbort {
+                lower_el: true,
+                status: FaultStatus::Permission { level: 2 },
+                write: false,
+                far_valid: true,
+            }
+        );
+        assert!(!decode(0x9600_0410).far_valid());
+        assert!(decode(0x9600_0028).is_granule_protection_fault());
+
+        // Instruction aborts, from a lower and from the current EL
+        assert_eq!(
+            class(0x8200_0005),
+            ExceptionClass::InstructionAbort {
+                lower_el: true,
+                status: FaultStatus::Translation { level: 1 },
+                far_valid: true,
+            }
+        );
+        assert_eq!(
+            class(0x8600_000F),
+            ExceptionClass::InstructionAbort {
+                lower_el: false,
+                status: FaultStatus::Permission { level: 3 },
+                far_valid: true,
+            }
+        );
+
+        // MRS x1, ID_AA64PFR0_EL1
+        let ExceptionClass::SysReg { reg, rt, read } = class(0x6230_0029) else {
+            panic!("not a system register trap");
+        };
+        assert_eq!((reg.name(), rt, read), (Some("ID_AA64PFR0_EL1"), 1, true));
+
+        assert_eq!(class(0x1E00_0000), ExceptionClass::FpSimd);
+        assert_eq!(class(0x0200_0000), ExceptionClass::Other);
+        // The GPC status is 6 bits wide, ISS bit 20 above it is InD
+        assert!(matches!(
+            class(0x7A13_4000),
+            ExceptionClass::Gpc {
+                status: GpcStatus::Protection { level: 1 },
+                instruction: true,
+                ..
+            }
+        ));
+    }
+}
+
+kernel_test! {
+    fn decodes_gpc_syndromes() {
+        let gpc = |esr| match decode(esr).class {
+            ExceptionClass::Gpc {
+                status,
+                instruction,
+                write,
+                s1ptw,
+                s2ptw,
+            } => (status, instruction, write, s1ptw, s2ptw),
+            class => panic!("{esr:#x} decoded as {class:?}"),
+        };
+
+        // GPF at level 1 on a read
+        assert_eq!(
+            gpc(0x7A03_4000),
+            (GpcStatus::Protection { level: 1 }, false, false, false, false)
+        );
+        // GPT walk fault at level 0 on an instruction fetch (InD, bit 20)
+        assert_eq!(
+            gpc(0x7A11_0000),
+            (GpcStatus::Walk { level: 0 }, true, false, false, false)
+        );
+        // GPT address size fault on a write
+        assert_eq!(
+            gpc(0x7A00_0040),
+            (GpcStatus::AddressSize { level: 0 }, false, true, false, false)
+        );
+        // External abort on a GPT fetch at level 1, during a stage 1 walk
+        assert_eq!(
+            gpc(0x7A05_4080),
+            (GpcStatus::SyncExternalOnFetch { level: 1 }, false, false, true, false)
+        );
+        // GPF during a stage 2 walk (S2PTW, bit 21)
+        assert_eq!(
+            gpc(0x7A23_4000),
+            (GpcStatus::Protection { level: 1 }, false, false, false, true)
+        );
+        // Bit 24 is RES0
+        assert_eq!(gpc(0x7B03_4000), gpc(0x7A03_4000));
+        assert_eq!(gpc(0x7A0F_C000).0, GpcStatus::Other(0x3F));
+
+        let esr = decode(0x7A03_4000);
+        assert!(esr.is_granule_protection_fault());
+        assert!(esr.far_valid());
+    }
+}
+
+kernel_test! {
+    fn extracts_call_immediates() {
+        assert_eq!(call_immediate(0x5600_0042), Some((Call::Svc, 0x42)));
+        assert_eq!(call_immediate(0x5A00_1234), Some((Call::Hvc, 0x1234)));
+        assert_eq!(call_immediate(0x5E00_FFFF), Some((Call::Smc, 0xFFFF)));
+        assert_eq!(call_immediate(0x5E00_0000), Some((Call::Smc, 0)));
+        // AArch32
+        assert_eq!(call_immediate(0x4600_0007), Some((Call::Svc, 7)));
+        assert_eq!(call_immediate(0x4A00_0001), Some((Call::Hvc, 1)));
+        // Other classes with non-zero low ISS bits: BRK, data abort, trapped MRS
+        assert_eq!(call_immediate(0xF200_0042), None);
+        assert_eq!(call_immediate(0x9600_0047), None);
+        assert_eq!(call_immediate(0x6230_0029), None);
+    }
+}
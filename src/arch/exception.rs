@@ -0,0 +1,484 @@
+//! EL3 exception vector table and handlers.
+//!
+//! The vector table has 16 entries, one per exception kind (synchronous, IRQ, FIQ, SError) and
+//! origin (current EL with SP0 or SPx, lower EL in AArch64 or AArch32). Each entry saves a full
+//! register frame on the stack, calls the Rust handler for its kind, and then restores the frame
+//! and returns with ERET.
+
+use crate::arch::esr::{self, ExceptionClass};
+use crate::arch::{context, eret_guard, mmu, serror};
+use crate::debug::{self, Brk};
+use crate::driver::gic;
+#[cfg(feature = "run-tests")]
+use crate::ktest;
+use crate::ktest::kernel_test;
+use crate::logger::emergency_log;
+use crate::{crash, platform, smccc, stack, watchdog};
+use core::arch::{asm, global_asm};
+use core::fmt;
+use core::mem::{offset_of, size_of};
+
+/// Size of the exception frame, in bytes.
+const FRAME_SIZE: usize = size_of::<ExceptionFrame>();
+
+const _: () = assert!(
+    FRAME_SIZE.is_multiple_of(16),
+    "the stack pointer must stay 16-byte aligned"
+);
+
+/// Checks that two registers saved as a pair by the vector table are adjacent in the frame.
+macro_rules! assert_pair {
+    ($first:expr, $second:expr) => {
+        const _: () = assert!(
+            $second == $first + 8,
+            "registers saved as a pair must be adjacent"
+        );
+    };
+}
+
+assert_pair!(
+    offset_of!(ExceptionFrame, x) + 30 * 8,
+    offset_of!(ExceptionFrame, sp_el0)
+);
+assert_pair!(
+    offset_of!(ExceptionFrame, elr),
+    offset_of!(ExceptionFrame, spsr)
+);
+assert_pair!(
+    offset_of!(ExceptionFrame, esr),
+    offset_of!(ExceptionFrame, far)
+);
+
+/// Installs the exception vector table in `VBAR_EL3`, and checks that exceptions reach their
+/// handlers by taking a checkpoint.
+///
+/// The table is not installed if it is misaligned. A vector table that is installed but broken
+/// enough to lose the checkpoint exception can still hang the self-test.
+pub fn install() -> Result<(), InstallError> {
+    unsafe extern "C" {
+        static l4sm_exception_vectors: u8;
+    }
+
+    // VBAR_EL3 ignores the low bits, a misaligned table would send exceptions to the wrong entries.
+    let vectors = &raw const l4sm_exception_vectors as usize;
+    if !is_vector_table_aligned(vectors) {
+        return Err(InstallError::Misaligned(vectors));
+    }
+
+    unsafe { asm!("msr VBAR_EL3, {}", "isb", in(reg) vectors) };
+    let vbar = vbar_el3();
+    if vbar != vectors {
+        return Err(InstallError::NotWritten { vectors, vbar });
+    }
+
+    debug::CHECKPOINT_REACHED.take();
+    debug::checkpoint!(0);
+    if !debug::CHECKPOINT_REACHED.take() {
+        return Err(InstallError::SelfTestFailed);
+    }
+    Ok(())
+}
+
+/// An error while installing the vector table.
+#[derive(Clone, Copy, Debug)]
+pub enum InstallError {
+    /// The vector table is not suitably aligned.
+    Misaligned(usize),
+    /// `VBAR_EL3` doesn't hold the address of the vector table after writing it.
+    NotWritten { vectors: usize, vbar: usize },
+    /// The checkpoint exception of the self-test did not reach its handler.
+    SelfTestFailed,
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            InstallError::Misaligned(vectors) => write!(
+                f,
+                "Misaligned exception vectors at {vectors:#x}, check the linker script"
+            ),
+            InstallError::NotWritten { vectors, vbar } => write!(
+                f,
+                "VBAR_EL3 reads {vbar:#x} after installing the vectors at {vectors:#x}"
+            ),
+            InstallError::SelfTestFailed => {
+                write!(
+                    f,
+                    "Exception vectors self-test failed, the checkpoint was not handled"
+                )
+            }
+        }
+    }
+}
+
+/// Returns the value of `VBAR_EL3`.
+fn vbar_el3() -> usize {
+    let value: usize;
+    unsafe { asm!("mrs {}, VBAR_EL3", out(reg) value) };
+    value
+}
+
+/// Returns `true` if `addr` is suitably aligned for a vector table (2 KiB).
+fn is_vector_table_aligned(addr: usize) -> bool {
+    addr.is_multiple_of(0x800)
+}
+
+// ———————————————————————————— Exception Frame ————————————————————————————— //
+
+/// The register state saved on exception entry.
+///
+/// The vector table accesses the fields through offsets taken from this definition, the
+/// assertions above check the pairs it saves and restores together.
+#[repr(C)]
+pub struct ExceptionFrame {
+    /// General purpose registers x0 to x30.
+    pub x: [u64; 31],
+    /// The EL0 stack pointer of the interrupted context, restored on exception return.
+    pub sp_el0: u64,
+    /// The exception link register, i.e. the return address.
+    pub elr: u64,
+    /// The saved program status register.
+    pub spsr: u64,
+    /// The exception syndrome, read-only.
+    pub esr: u64,
+    /// The fault address, read-only and only meaningful for some exceptions.
+    pub far: u64,
+    /// The instruction key A of the interrupted context, only saved when pointer authentication
+    /// is enabled (see [pauth](crate::arch::pauth)).
+    pub apia: [u64; 2],
+}
+
+impl ExceptionFrame {
+    /// Returns the `n`-th argument register of a call, i.e. `xn`.
+    pub fn arg(&self, n: usize) -> u64 {
+        self.x[n]
+    }
+
+    /// Sets the `n`-th result register of a call, i.e. `xn`.
+    pub fn set_ret(&mut self, n: usize, value: u64) {
+        self.x[n] = value;
+    }
+
+    /// Returns the value of SP_EL3 before the exception, the frame was pushed right below it.
+    pub fn sp_el3(&self) -> u64 {
+        self as *const Self as u64 + FRAME_SIZE as u64
+    }
+}
+
+/// Where an exception was taken from.
+#[repr(u64)]
+#[derive(Clone, Copy, Debug)]
+pub enum Origin {
+    CurrentElSp0 = 0,
+    CurrentElSpx = 1,
+    LowerElAarch64 = 2,
+    LowerElAarch32 = 3,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Origin::CurrentElSp0 => "current EL (SP0)",
+            Origin::CurrentElSpx => "current EL (SPx)",
+            Origin::LowerElAarch64 => "lower EL (AArch64)",
+            Origin::LowerElAarch32 => "lower EL (AArch32)",
+        })
+    }
+}
+
+// ———————————————————————————————— Handlers ———————————————————————————————— //
+
+extern "C" fn handle_sync(frame: &mut ExceptionFrame, origin: Origin) {
+    let esr = esr::decode(frame.esr);
+    if mmu::rollback_failed_enable() {
+        panic!("Fault while enabling the MMU: {esr} (ELR {:#x})", frame.elr);
+    }
+
+    let from_lower_el = matches!(origin, Origin::LowerElAarch64 | Origin::LowerElAarch32);
+    if from_lower_el && let Some((esr::Call::Smc, imm)) = esr::call_immediate(frame.esr) {
+        // ELR already points to the instruction following the SMC
+        smccc::dispatch(frame, imm);
+        context::switch_if_requested(frame);
+        stack::assert_not_overflowed();
+        return;
+    }
+
+    if !from_lower_el && platform::skip_failed_semihosting(frame) {
+        return;
+    }
+
+    if !from_lower_el && let ExceptionClass::Brk { comment } = esr.class {
+        handle_brk(frame, comment);
+        return;
+    }
+
+    #[cfg(feature = "run-tests")]
+    if !from_lower_el && ktest::catch_fault(frame) {
+        return;
+    }
+
+    if !from_lower_el && esr.class == ExceptionClass::FpSimd {
+        emergency_log(format_args!(
+            "FP/SIMD used at EL3 while trapped, the monitor must not use FP/SIMD instructions"
+        ));
+    }
+    crash::dump(
+        Some(frame),
+        format_args!("Unhandled synchronous exception from {origin}"),
+    );
+    crash::halt(platform::EXIT_FAULT);
+}
+
+/// Reports a BRK taken from EL3, then either resumes after it or halts.
+fn handle_brk(frame: &mut ExceptionFrame, comment: u16) {
+    let brk = Brk::decode(comment);
+    let watermark = stack::high_watermark();
+    if let Brk::Checkpoint(_) = brk {
+        debug::CHECKPOINT_REACHED.raise();
+        log::info!(
+            "{brk} at {:#x}, stack high-watermark: {watermark} bytes",
+            frame.elr
+        );
+        crash::print_registers(frame, log_debug);
+
+        // ELR points to the BRK itself, resume at the next instruction. No ISB is needed: the
+        // new ELR only takes effect on ERET, which is context synchronizing.
+        frame.elr += 4;
+        return;
+    }
+
+    crash::dump(Some(frame), format_args!("{brk} at {:#x}", frame.elr));
+    crash::halt(platform::EXIT_FAULT);
+}
+
+extern "C" fn handle_irq(frame: &mut ExceptionFrame, origin: Origin) {
+    unhandled("IRQ", frame, origin);
+}
+
+extern "C" fn handle_fiq(frame: &mut ExceptionFrame, origin: Origin) {
+    let intid = gic::acknowledge_group0();
+    match intid {
+        platform::SECURE_TIMER_INTID => watchdog::handle_interrupt(frame),
+        gic::SPURIOUS_INTID => return,
+        _ => {
+            emergency_log(format_args!("Unexpected interrupt {intid}"));
+            unhandled("FIQ", frame, origin);
+        }
+    }
+    gic::end_of_interrupt_group0(intid);
+}
+
+extern "C" fn handle_serror(frame: &mut ExceptionFrame, origin: Origin) {
+    // An SError can arrive while the logger is locked, so only the emergency logger is used.
+    // Interrupts are masked on exception entry and stay masked until we exit.
+    let syndrome = serror::Syndrome::decode(esr::decode(frame.esr).iss);
+    emergency_log(format_args!("SError from {origin}: {syndrome}"));
+    serror::log_error_records();
+    if serror::pending() {
+        emergency_log(format_args!("  Another SError is pending"));
+    }
+
+    let from_lower_el = matches!(origin, Origin::LowerElAarch64 | Origin::LowerElAarch32);
+    match serror::action(syndrome, from_lower_el) {
+        serror::Action::Resume => return,
+        serror::Action::Contain => {
+            if let Some(world) = context::kill_running() {
+                emergency_log(format_args!("  Killed the {world:?} world"));
+                context::switch_if_requested(frame);
+                return;
+            }
+            emergency_log(format_args!(
+                "  No other world to run, can't contain the error"
+            ));
+        }
+        serror::Action::Panic => {}
+    }
+    crash::dump(Some(frame), format_args!("SError from {origin}"));
+    crash::halt(platform::EXIT_SERROR);
+}
+
+/// Checks the state of the interrupted context before returning to it, if it runs at a lower EL.
+///
+/// Called by the vector table for all exceptions once the handler returned.
+extern "C" fn check_exception_return(frame: &mut ExceptionFrame, origin: Origin) {
+    if !matches!(origin, Origin::LowerElAarch64 | Origin::LowerElAarch32) {
+        return;
+    }
+
+    let Some(world) = context::running() else {
+        crash::dump(
+            Some(frame),
+            format_args!("Exception from {origin} without a running world"),
+        );
+        crash::halt(platform::EXIT_FAULT);
+    };
+    match eret_guard::prepare(frame.spsr, frame.elr, world) {
+        Ok(spsr) => frame.spsr = spsr,
+        Err(violation) => {
+            crash::dump(
+                Some(frame),
+                format_args!("Refusing {violation} to the {world:?} world"),
+            );
+            crash::halt(platform::EXIT_FAULT);
+        }
+    }
+}
+
+/// Reports an unexpected exception and halts.
+fn unhandled(kind: &str, frame: &ExceptionFrame, origin: Origin) -> ! {
+    crash::dump(
+        Some(frame),
+        format_args!("Unhandled {kind} exception from {origin}"),
+    );
+    crash::halt(platform::EXIT_FAULT);
+}
+
+/// Logs a line at the debug level, for use with [crash::print_registers].
+fn log_debug(args: fmt::Arguments) {
+    log::debug!("{args}");
+}
+
+// —————————————————————————————— Vector Table —————————————————————————————— //
+
+global_asm!(
+r#"
+// Saves the register frame, calls the handler, and returns from the exception.
+//
+// Each vector table entry is 128 bytes (32 instructions) long, this macro must fit within that.
+.macro l4sm_vector_entry handler, origin
+    .balign 0x80
+    sub sp, sp, #{frame_size}
+    stp x0, x1, [sp, #{x} + 16 * 0]
+    stp x2, x3, [sp, #{x} + 16 * 1]
+    stp x4, x5, [sp, #{x} + 16 * 2]
+    stp x6, x7, [sp, #{x} + 16 * 3]
+    stp x8, x9, [sp, #{x} + 16 * 4]
+    stp x10, x11, [sp, #{x} + 16 * 5]
+    stp x12, x13, [sp, #{x} + 16 * 6]
+    stp x14, x15, [sp, #{x} + 16 * 7]
+    stp x16, x17, [sp, #{x} + 16 * 8]
+    stp x18, x19, [sp, #{x} + 16 * 9]
+    stp x20, x21, [sp, #{x} + 16 * 10]
+    stp x22, x23, [sp, #{x} + 16 * 11]
+    stp x24, x25, [sp, #{x} + 16 * 12]
+    stp x26, x27, [sp, #{x} + 16 * 13]
+    stp x28, x29, [sp, #{x} + 16 * 14]
+    mrs x0, SP_EL0
+    stp x30, x0, [sp, #{x} + 16 * 15]
+    mrs x0, ELR_EL3
+    mrs x1, SPSR_EL3
+    stp x0, x1, [sp, #{elr}]
+    mrs x0, ESR_EL3
+    mrs x1, FAR_EL3
+    stp x0, x1, [sp, #{esr}]
+    bl l4sm_reassert_dit
+    bl l4sm_pauth_entry
+    mov x0, sp
+    mov x1, #\origin
+    // x19 is callee-saved, it keeps the origin for the exception return
+    mov x19, x1
+    bl \handler
+    b l4sm_exception_return
+.endm
+
+.pushsection .text.vectors, "ax"
+.balign 0x800
+.global l4sm_exception_vectors
+l4sm_exception_vectors:
+    // Current EL with SP0
+    l4sm_vector_entry {sync}, {sp0}
+    l4sm_vector_entry {irq}, {sp0}
+    l4sm_vector_entry {fiq}, {sp0}
+    l4sm_vector_entry {serror}, {sp0}
+
+    // Current EL with SPx
+    l4sm_vector_entry {sync}, {spx}
+    l4sm_vector_entry {irq}, {spx}
+    l4sm_vector_entry {fiq}, {spx}
+    l4sm_vector_entry {serror}, {spx}
+
+    // Lower EL using AArch64
+    l4sm_vector_entry {sync}, {a64}
+    l4sm_vector_entry {irq}, {a64}
+    l4sm_vector_entry {fiq}, {a64}
+    l4sm_vector_entry {serror}, {a64}
+
+    // Lower EL using AArch32
+    l4sm_vector_entry {sync}, {a32}
+    l4sm_vector_entry {irq}, {a32}
+    l4sm_vector_entry {fiq}, {a32}
+    l4sm_vector_entry {serror}, {a32}
+
+// Restores the register frame (including the possibly updated SP_EL0, ELR, and SPSR) and
+// returns.
+l4sm_exception_return:
+    mov x0, sp
+    mov x1, x19
+    bl {exit}
+    bl l4sm_pauth_exit
+    ldp x0, x1, [sp, #{elr}]
+    msr ELR_EL3, x0
+    msr SPSR_EL3, x1
+    ldp x30, x0, [sp, #{x} + 16 * 15]
+    msr SP_EL0, x0
+    ldp x0, x1, [sp, #{x} + 16 * 0]
+    ldp x2, x3, [sp, #{x} + 16 * 1]
+    ldp x4, x5, [sp, #{x} + 16 * 2]
+    ldp x6, x7, [sp, #{x} + 16 * 3]
+    ldp x8, x9, [sp, #{x} + 16 * 4]
+    ldp x10, x11, [sp, #{x} + 16 * 5]
+    ldp x12, x13, [sp, #{x} + 16 * 6]
+    ldp x14, x15, [sp, #{x} + 16 * 7]
+    ldp x16, x17, [sp, #{x} + 16 * 8]
+    ldp x18, x19, [sp, #{x} + 16 * 9]
+    ldp x20, x21, [sp, #{x} + 16 * 10]
+    ldp x22, x23, [sp, #{x} + 16 * 11]
+    ldp x24, x25, [sp, #{x} + 16 * 12]
+    ldp x26, x27, [sp, #{x} + 16 * 13]
+    ldp x28, x29, [sp, #{x} + 16 * 14]
+    add sp, sp, #{frame_size}
+    eret
+.popsection
+"#,
+    sync = sym handle_sync,
+    irq = sym handle_irq,
+    fiq = sym handle_fiq,
+    serror = sym handle_serror,
+    exit = sym check_exception_return,
+    frame_size = const FRAME_SIZE,
+    x = const offset_of!(ExceptionFrame, x),
+    elr = const offset_of!(ExceptionFrame, elr),
+    esr = const offset_of!(ExceptionFrame, esr),
+    sp0 = const Origin::CurrentElSp0 as u64,
+    spx = const Origin::CurrentElSpx as u64,
+    a64 = const Origin::LowerElAarch64 as u64,
+    a32 = const Origin::LowerElAarch32 as u64,
+);
+
+kernel_test! {
+    fn data_abort_reaches_handler() {
+        // Below the device window, nothing is mapped there
+        let addr = platform::DEVICE_BASE - 0x1000;
+        let fault = ktest::expect_fault(|| unsafe {
+            asm!("ldr {}, [{}]", out(reg) _, in(reg) addr);
+        });
+        let fault = fault.expect("the read didn't fault");
+        assert!(
+            matches!(
+                fault.esr.class,
+                ExceptionClass::DataAbort {
+                    lower_el: false,
+                    status: esr::FaultStatus::Translation { .. },
+                    write: false,
+                    far_valid: true,
+                }
+            ),
+            "unexpected fault: {}",
+            fault.esr
+        );
+        assert_eq!(fault.far, addr as u64);
+
+        // Nothing is reported without a fault
+        assert!(ktest::expect_fault(|| {}).is_none());
+    }
+}
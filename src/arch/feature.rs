@@ -1,7 +1,11 @@
 //! Hardware feature detection via AArch64 system registers.
 //!
-//! Reference: ID_AA64PFR0_EL1, AArch64 Processor Feature Register 0.
+//! Reference: ID_AA64PFR0_EL1 and ID_AA64PFR1_EL1, AArch64 Processor Feature Registers 0 and 1,
+//! ID_AA64MMFR0_EL1 to ID_AA64MMFR2_EL1, AArch64 Memory Model Feature Registers 0 to 2,
+//! ID_AA64ISAR0_EL1 to ID_AA64ISAR2_EL1, AArch64 Instruction Set Attribute Registers 0 to 2, and
+//! ID_AA64DFR0_EL1, AArch64 Debug Feature Register 0.
 
+use crate::ktest::kernel_test;
 use core::arch::asm;
 
 /// Returns the value of `ID_AA64PFR0_EL1`.
@@ -11,16 +15,215 @@ fn id_aa64pfr0() -> u64 {
     value
 }
 
+/// Returns the value of `ID_AA64PFR1_EL1`.
+fn id_aa64pfr1() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ID_AA64PFR1_EL1", out(reg) value) };
+    value
+}
+
+/// Returns the value of `ID_AA64MMFR0_EL1`.
+fn id_aa64mmfr0() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ID_AA64MMFR0_EL1", out(reg) value) };
+    value
+}
+
+/// Returns the value of `ID_AA64MMFR1_EL1`.
+fn id_aa64mmfr1() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ID_AA64MMFR1_EL1", out(reg) value) };
+    value
+}
+
+/// Returns the value of `ID_AA64MMFR2_EL1`.
+fn id_aa64mmfr2() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ID_AA64MMFR2_EL1", out(reg) value) };
+    value
+}
+
+/// Returns the value of `ID_AA64ISAR0_EL1`.
+fn id_aa64isar0() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ID_AA64ISAR0_EL1", out(reg) value) };
+    value
+}
+
+/// Returns the value of `ID_AA64ISAR1_EL1`.
+fn id_aa64isar1() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ID_AA64ISAR1_EL1", out(reg) value) };
+    value
+}
+
+/// Returns the value of `ID_AA64ISAR2_EL1`.
+fn id_aa64isar2() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ID_AA64ISAR2_EL1", out(reg) value) };
+    value
+}
+
+/// Returns the value of `ID_AA64DFR0_EL1`.
+fn id_aa64dfr0() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, ID_AA64DFR0_EL1", out(reg) value) };
+    value
+}
+
 /// Extracts a 4-bit field from a register value.
 fn field(reg: u64, shift: u32) -> u64 {
     (reg >> shift) & 0xF
 }
 
+/// Returns `true` if EL2 is implemented.
+pub fn has_el2() -> bool {
+    field(id_aa64pfr0(), 8) != 0
+}
+
+/// Returns `true` if EL2 is implemented in the Secure state.
+pub fn has_sel2() -> bool {
+    field(id_aa64pfr0(), 36) != 0
+}
+
+/// Returns `true` if the RAS extension is implemented.
+pub fn has_ras() -> bool {
+    field(id_aa64pfr0(), 28) != 0
+}
+
 /// Returns `true` if the Realm Management Extension (RME) is implemented.
 pub fn has_rme() -> bool {
     field(id_aa64pfr0(), 52) != 0
 }
 
+/// Returns `true` if Data Independent Timing (DIT) is implemented.
+pub fn has_dit() -> bool {
+    field(id_aa64pfr0(), 48) != 0
+}
+
+/// Returns `true` if Branch Target Identification (BTI) is implemented.
+pub fn has_bti() -> bool {
+    field(id_aa64pfr1(), 0) != 0
+}
+
+/// Returns `true` if the Speculative Store Bypass Safe (SSBS) control is implemented.
+pub fn has_ssbs() -> bool {
+    field(id_aa64pfr1(), 4) != 0
+}
+
+/// Returns `true` if the Memory Tagging Extension (MTE) is implemented, possibly without tag
+/// storage (the EL0-only instructions of FEAT_MTE).
+pub fn has_mte() -> bool {
+    field(id_aa64pfr1(), 8) != 0
+}
+
+/// Returns `true` if the Scalable Matrix Extension (SME) is implemented.
+pub fn has_sme() -> bool {
+    field(id_aa64pfr1(), 24) != 0
+}
+
+/// Returns `true` if the Large System Extensions (LSE) atomic instructions are implemented.
+pub fn has_lse() -> bool {
+    field(id_aa64isar0(), 20) >= 0b0010
+}
+
+/// Returns `true` if the random number registers (FEAT_RNG) are implemented.
+pub fn has_rng() -> bool {
+    field(id_aa64isar0(), 60) != 0
+}
+
+/// Returns `true` if the CRC32 instructions are implemented.
+pub fn has_crc32() -> bool {
+    field(id_aa64isar0(), 16) != 0
+}
+
+/// Returns `true` if address authentication (pointer authentication of instruction and data
+/// addresses) is implemented, with any algorithm.
+pub fn has_pauth() -> bool {
+    has_address_auth(id_aa64isar1(), id_aa64isar2())
+}
+
+/// Returns `true` if the memory copy and set instructions (FEAT_MOPS) are implemented.
+pub fn has_mops() -> bool {
+    field(id_aa64isar2(), 16) != 0
+}
+
+/// Returns `true` if any of the APA, API, or APA3 fields report address authentication.
+pub fn has_address_auth(isar1: u64, isar2: u64) -> bool {
+    field(isar1, 4) != 0 || field(isar1, 8) != 0 || field(isar2, 12) != 0
+}
+
+/// Returns `true` if any of the GPA, GPI, or GPA3 fields report generic authentication.
+pub fn has_generic_auth(isar1: u64, isar2: u64) -> bool {
+    field(isar1, 24) != 0 || field(isar1, 28) != 0 || field(isar2, 8) != 0
+}
+
+/// Returns the raw `PARange` field of `ID_AA64MMFR0_EL1`, encoding the supported physical
+/// address size.
+pub fn pa_range() -> u64 {
+    field(id_aa64mmfr0(), 0)
+}
+
+/// Returns the supported physical address size, in bits.
+pub fn pa_bits() -> u8 {
+    pa_range_bits(pa_range())
+}
+
+/// Decodes a `PARange` value into a physical address size, in bits.
+///
+/// Reserved encodings are reported as the largest architected size.
+pub fn pa_range_bits(pa_range: u64) -> u8 {
+    match pa_range {
+        0b0000 => 32,
+        0b0001 => 36,
+        0b0010 => 40,
+        0b0011 => 42,
+        0b0100 => 44,
+        0b0101 => 48,
+        0b0110 => 52,
+        _ => 56,
+    }
+}
+
+/// Returns the number of hardware breakpoints.
+pub fn num_breakpoints() -> u8 {
+    breakpoints(id_aa64dfr0())
+}
+
+/// Returns the number of hardware watchpoints.
+pub fn num_watchpoints() -> u8 {
+    watchpoints(id_aa64dfr0())
+}
+
+/// Decodes the number of breakpoints from `ID_AA64DFR0_EL1`.
+fn breakpoints(dfr0: u64) -> u8 {
+    // BRPs holds the number of breakpoints minus one
+    field(dfr0, 12) as u8 + 1
+}
+
+/// Decodes the number of watchpoints from `ID_AA64DFR0_EL1`.
+fn watchpoints(dfr0: u64) -> u8 {
+    // WRPs holds the number of watchpoints minus one
+    field(dfr0, 20) as u8 + 1
+}
+
+/// Returns the raw `DebugVer` field of `ID_AA64DFR0_EL1`, the version of the debug architecture.
+pub fn debug_version() -> u64 {
+    field(id_aa64dfr0(), 0)
+}
+
+/// Returns `true` if the Performance Monitors Extension is implemented, with the architected
+/// interface.
+pub fn has_pmu() -> bool {
+    // PMUVer reports an implementation defined PMU as 0b1111
+    !matches!(field(id_aa64dfr0(), 8), 0b0000 | 0b1111)
+}
+
+/// Returns `true` if the 4 KiB translation granule is implemented.
+pub fn supports_4k_granule() -> bool {
+    field(id_aa64mmfr0(), 28) != 0b1111
+}
+
 /// Logs the features reported by `ID_AA64PFR0_EL1`.
 pub fn log_features() {
     let pfr0 = id_aa64pfr0();
@@ -73,8 +276,7 @@ pub fn log_features() {
     log::info!("  SVE: {}", if sve != 0 { "yes" } else { "no" });
 
     // Secure EL2
-    let sel2 = field(pfr0, 36);
-    log::info!("  Secure EL2: {}", if sel2 != 0 { "yes" } else { "no" });
+    log::info!("  Secure EL2: {}", if has_sel2() { "yes" } else { "no" });
 
     // MPAM
     let mpam = field(pfr0, 40);
@@ -90,8 +292,7 @@ pub fn log_features() {
     });
 
     // DIT
-    let dit = field(pfr0, 48);
-    log::info!("  DIT: {}", if dit != 0 { "yes" } else { "no" });
+    log::info!("  DIT: {}", if has_dit() { "yes" } else { "no" });
 
     // RME
     let rme = field(pfr0, 52);
@@ -116,6 +317,260 @@ pub fn log_features() {
     // CSV3
     let csv3 = field(pfr0, 60);
     log::info!("  CSV3: {}", if csv3 != 0 { "yes" } else { "no" });
+
+    let pfr1 = id_aa64pfr1();
+
+    log::info!("ID_AA64PFR1_EL1: {pfr1:#018x}");
+
+    // BTI
+    log::info!("  BTI: {}", if has_bti() { "yes" } else { "no" });
+
+    // The predicates are what the rest of the monitor goes by
+    let ssbs = if has_ssbs() {
+        decode_ssbs(pfr1)
+    } else {
+        "none"
+    };
+    let mte = if has_mte() { decode_mte(pfr1) } else { "none" };
+    let sme = if has_sme() { decode_sme(pfr1) } else { "none" };
+    log::info!("  SSBS: {ssbs} | MTE: {mte} | SME: {sme}");
+
+    let mmfr0 = id_aa64mmfr0();
+
+    log::info!("ID_AA64MMFR0_EL1: {mmfr0:#018x}");
+
+    // Physical address range
+    log::info!("  PA range: {} bits", pa_bits());
+
+    // Translation granules
+    let tgran4 = field(mmfr0, 28);
+    let tgran16 = field(mmfr0, 20);
+    let tgran64 = field(mmfr0, 24);
+    log::info!(
+        "  4K granule: {} | 16K granule: {} | 64K granule: {}",
+        granule_description(tgran4),
+        granule16_description(tgran16),
+        granule_description(tgran64),
+    );
+
+    let mmfr1 = id_aa64mmfr1();
+
+    log::info!("ID_AA64MMFR1_EL1: {mmfr1:#018x}");
+
+    // Hardware updates of the access flag and dirty state
+    log::info!("  HAFDBS: {}", decode_hafdbs(mmfr1));
+
+    // Virtualization Host Extensions
+    let vh = field(mmfr1, 8);
+    log::info!("  VHE: {}", if vh != 0 { "yes" } else { "no" });
+
+    // Privileged Access Never
+    log::info!("  PAN: {}", decode_pan(mmfr1));
+
+    let mmfr2 = id_aa64mmfr2();
+
+    log::info!("ID_AA64MMFR2_EL1: {mmfr2:#018x}");
+
+    // Common not Private translations
+    let cnp = field(mmfr2, 0);
+    log::info!("  CnP: {}", if cnp != 0 { "yes" } else { "no" });
+
+    // Unaligned single-copy atomicity
+    let at = field(mmfr2, 32);
+    log::info!("  AT: {}", if at != 0 { "yes" } else { "no" });
+
+    let isar0 = id_aa64isar0();
+
+    log::info!("ID_AA64ISAR0_EL1: {isar0:#018x}");
+
+    // Atomics
+    log::info!("  Atomics: {}", decode_atomics(isar0));
+
+    // CRC32
+    log::info!("  CRC32: {}", if has_crc32() { "yes" } else { "no" });
+
+    // Random numbers
+    log::info!("  RNDR: {}", if has_rng() { "yes" } else { "no" });
+
+    // Crypto
+    log::info!(
+        "  AES: {} | SHA2: {}",
+        decode_aes(isar0),
+        decode_sha2(isar0)
+    );
+
+    let isar1 = id_aa64isar1();
+    let isar2 = id_aa64isar2();
+
+    log::info!("ID_AA64ISAR1_EL1: {isar1:#018x}");
+    log::info!("ID_AA64ISAR2_EL1: {isar2:#018x}");
+
+    // Pointer authentication
+    log::info!(
+        "  PAuth: {} | Generic auth: {}",
+        if has_pauth() {
+            decode_pauth_algorithm(isar1, isar2)
+        } else {
+            "none"
+        },
+        if has_generic_auth(isar1, isar2) {
+            "yes"
+        } else {
+            "no"
+        },
+    );
+
+    // Load-acquire RCpc
+    log::info!("  LRCPC: {}", decode_lrcpc(isar1));
+
+    // Data persistence
+    log::info!("  DC CVAP: {}", decode_dpb(isar1));
+
+    // Memory copy and set
+    log::info!("  MOPS: {}", if has_mops() { "yes" } else { "no" });
+
+    let dfr0 = id_aa64dfr0();
+    log::info!("ID_AA64DFR0_EL1: {dfr0:#018x}");
+
+    // Debug architecture
+    log::info!("  Debug: {}", decode_debug_version(debug_version()));
+    log::info!(
+        "  Breakpoints: {} | Watchpoints: {}",
+        num_breakpoints(),
+        num_watchpoints()
+    );
+
+    // Performance monitors
+    log::info!("  PMU: {}", if has_pmu() { "yes" } else { "no" });
+}
+
+/// Describes the SSBS field of `ID_AA64PFR1_EL1`.
+fn decode_ssbs(pfr1: u64) -> &'static str {
+    match field(pfr1, 4) {
+        0b0000 => "none",
+        0b0001 => "yes",
+        0b0010 => "yes + MSR/MRS",
+        _ => "unknown",
+    }
+}
+
+/// Describes the MTE field of `ID_AA64PFR1_EL1`.
+fn decode_mte(pfr1: u64) -> &'static str {
+    match field(pfr1, 8) {
+        0b0000 => "none",
+        0b0001 => "instructions only",
+        0b0010 => "MTE2",
+        0b0011 => "MTE3",
+        _ => "unknown",
+    }
+}
+
+/// Describes the SME field of `ID_AA64PFR1_EL1`.
+fn decode_sme(pfr1: u64) -> &'static str {
+    match field(pfr1, 24) {
+        0b0000 => "none",
+        0b0001 => "SME",
+        0b0010 => "SME2",
+        _ => "unknown",
+    }
+}
+
+/// Describes the HAFDBS field of `ID_AA64MMFR1_EL1`.
+fn decode_hafdbs(mmfr1: u64) -> &'static str {
+    match field(mmfr1, 0) {
+        0b0000 => "none",
+        0b0001 => "access flag",
+        0b0010 => "access flag + dirty state",
+        0b0011 => "access flag + dirty state + table access flag",
+        _ => "unknown",
+    }
+}
+
+/// Describes the PAN field of `ID_AA64MMFR1_EL1`.
+fn decode_pan(mmfr1: u64) -> &'static str {
+    match field(mmfr1, 20) {
+        0b0000 => "none",
+        0b0001 => "v1",
+        0b0010 => "v2",
+        0b0011 => "v3",
+        _ => "unknown",
+    }
+}
+
+/// Describes the Atomic field of `ID_AA64ISAR0_EL1`.
+fn decode_atomics(isar0: u64) -> &'static str {
+    match field(isar0, 20) {
+        0b0000 => "none",
+        0b0010 => "LSE",
+        0b0011 => "LSE + LSE128",
+        _ => "unknown",
+    }
+}
+
+/// Describes the AES field of `ID_AA64ISAR0_EL1`.
+fn decode_aes(isar0: u64) -> &'static str {
+    match field(isar0, 4) {
+        0b0000 => "none",
+        0b0001 => "yes",
+        0b0010 => "yes + PMULL",
+        _ => "unknown",
+    }
+}
+
+/// Describes the SHA2 field of `ID_AA64ISAR0_EL1`.
+fn decode_sha2(isar0: u64) -> &'static str {
+    match field(isar0, 12) {
+        0b0000 => "none",
+        0b0001 => "SHA256",
+        0b0010 => "SHA256 + SHA512",
+        _ => "unknown",
+    }
+}
+
+/// Returns the address authentication algorithm reported by `ID_AA64ISAR1_EL1` and
+/// `ID_AA64ISAR2_EL1`, assuming there is one.
+fn decode_pauth_algorithm(isar1: u64, isar2: u64) -> &'static str {
+    if field(isar2, 12) != 0 {
+        "QARMA3"
+    } else if field(isar1, 4) != 0 {
+        "QARMA5"
+    } else {
+        "implementation defined"
+    }
+}
+
+/// Describes the LRCPC field of `ID_AA64ISAR1_EL1`.
+fn decode_lrcpc(isar1: u64) -> &'static str {
+    match field(isar1, 20) {
+        0b0000 => "none",
+        0b0001 => "v1",
+        0b0010 => "v2",
+        0b0011 => "v3",
+        _ => "unknown",
+    }
+}
+
+/// Describes the DPB field of `ID_AA64ISAR1_EL1`.
+fn decode_dpb(isar1: u64) -> &'static str {
+    match field(isar1, 0) {
+        0b0000 => "none",
+        0b0001 => "yes",
+        0b0010 => "yes + DC CVADP",
+        _ => "unknown",
+    }
+}
+
+/// Describes a `DebugVer` value of `ID_AA64DFR0_EL1`.
+fn decode_debug_version(version: u64) -> &'static str {
+    match version {
+        0b0110 => "v8.0",
+        0b0111 => "v8.0 + VHE",
+        0b1000 => "v8.2",
+        0b1001 => "v8.4",
+        0b1010 => "v8.8",
+        0b1011 => "v8.9",
+        _ => "unknown",
+    }
 }
 
 fn el_description(val: u64) -> &'static str {
@@ -127,6 +582,25 @@ fn el_description(val: u64) -> &'static str {
     }
 }
 
+fn granule_description(val: u64) -> &'static str {
+    match val {
+        0b0000 => "yes",
+        0b0001 => "yes (52-bit)",
+        0b1111 => "no",
+        _ => "unknown",
+    }
+}
+
+/// TGran16 uses a different encoding than TGran4 and TGran64.
+fn granule16_description(val: u64) -> &'static str {
+    match val {
+        0b0000 => "no",
+        0b0001 => "yes",
+        0b0010 => "yes (52-bit)",
+        _ => "unknown",
+    }
+}
+
 fn fp_description(val: u64) -> &'static str {
     match val {
         0b0000 => "yes",
@@ -135,3 +609,103 @@ fn fp_description(val: u64) -> &'static str {
         _ => "unknown",
     }
 }
+
+kernel_test! {
+    fn pfr1_decoding() {
+        // BT 1, SSBS 2, MTE 2, and no SME, then the same with MTE3 and SME2
+        let mte2 = 0x0000_0000_0000_0221;
+        let sme2 = 0x0000_0000_0200_0321;
+        let decode = |pfr1| [decode_ssbs(pfr1), decode_mte(pfr1), decode_sme(pfr1)];
+        assert_eq!(decode(mte2), ["yes + MSR/MRS", "MTE2", "none"]);
+        assert_eq!(decode(sme2), ["yes + MSR/MRS", "MTE3", "SME2"]);
+        assert_eq!(decode(0), ["none"; 3]);
+        // Each field is read at its own position
+        assert_eq!(decode_ssbs(1 << 4), "yes");
+        assert_eq!(decode_mte(1 << 8), "instructions only");
+        assert_eq!(decode_sme(1 << 24), "SME");
+        assert_eq!(decode_mte(0xF << 8), "unknown");
+    }
+}
+
+kernel_test! {
+    fn mmfr_decoding() {
+        let pa_ranges = [
+            (0b0000, 32),
+            (0b0001, 36),
+            (0b0010, 40),
+            (0b0011, 42),
+            (0b0100, 44),
+            (0b0101, 48),
+            (0b0110, 52),
+        ];
+        for (pa_range, bits) in pa_ranges {
+            assert_eq!(pa_range_bits(pa_range), bits, "PARange {pa_range:#06b}");
+        }
+        // Reserved encodings
+        assert_eq!(pa_range_bits(0b0111), 56);
+        assert_eq!(pa_range_bits(0b1111), 56);
+
+        // TGran4 and TGran64 report "not implemented" as 0b1111, TGran16 as 0b0000
+        assert_eq!(granule_description(0b0000), "yes");
+        assert_eq!(granule_description(0b0001), "yes (52-bit)");
+        assert_eq!(granule_description(0b1111), "no");
+        assert_eq!(granule16_description(0b0000), "no");
+        assert_eq!(granule16_description(0b0010), "yes (52-bit)");
+
+        // HAFDBS 2, VH 1, PAN 2
+        let mmfr1 = 0x0000_0000_1021_2122;
+        assert_eq!(decode_hafdbs(mmfr1), "access flag + dirty state");
+        assert_eq!(decode_pan(mmfr1), "v2");
+        assert_eq!(decode_hafdbs(0b0011), "access flag + dirty state + table access flag");
+        assert_eq!(decode_pan(0b0011 << 20), "v3");
+        assert_eq!(decode_pan(0b0100 << 20), "unknown");
+    }
+}
+
+kernel_test! {
+    fn isar_decoding() {
+        // AES 2, SHA1 1, SHA2 2, CRC32 1, Atomic 2
+        let isar0 = 0x0000_0000_0021_2120;
+        assert_eq!(decode_atomics(isar0), "LSE");
+        assert_eq!(decode_aes(isar0), "yes + PMULL");
+        assert_eq!(decode_sha2(isar0), "SHA256 + SHA512");
+        assert_eq!(decode_atomics(0b0011 << 20), "LSE + LSE128");
+        // 0b0001 is reserved, LSE starts at 0b0010
+        assert_eq!(decode_atomics(0b0001 << 20), "unknown");
+
+        // DPB 2, LRCPC 2
+        let isar1 = 0x0000_0000_0020_0002;
+        assert_eq!(decode_dpb(isar1), "yes + DC CVADP");
+        assert_eq!(decode_lrcpc(isar1), "v2");
+
+        // Address and generic authentication, each reported by one of three fields
+        let (apa, api, gpa, gpi) = (1 << 4, 1 << 8, 1 << 24, 1 << 28);
+        let (gpa3, apa3) = (1 << 8, 1 << 12);
+        assert!(!has_address_auth(0, 0) && !has_generic_auth(0, 0));
+        for (isar1, isar2) in [(apa, 0), (api, 0), (0, apa3)] {
+            assert!(has_address_auth(isar1, isar2), "ISAR1 {isar1:#x}, ISAR2 {isar2:#x}");
+            assert!(!has_generic_auth(isar1, isar2), "ISAR1 {isar1:#x}, ISAR2 {isar2:#x}");
+        }
+        for (isar1, isar2) in [(gpa, 0), (gpi, 0), (0, gpa3)] {
+            assert!(has_generic_auth(isar1, isar2), "ISAR1 {isar1:#x}, ISAR2 {isar2:#x}");
+            assert!(!has_address_auth(isar1, isar2), "ISAR1 {isar1:#x}, ISAR2 {isar2:#x}");
+        }
+        assert_eq!(decode_pauth_algorithm(apa, 0), "QARMA5");
+        assert_eq!(decode_pauth_algorithm(api, 0), "implementation defined");
+        assert_eq!(decode_pauth_algorithm(0, apa3), "QARMA3");
+    }
+}
+
+kernel_test! {
+    fn dfr0_decoding() {
+        // The counts are encoded minus one: 0 means one, 0b1111 means 16
+        assert_eq!((breakpoints(0), watchpoints(0)), (1, 1));
+        assert_eq!((breakpoints(0xF << 12), watchpoints(0xF << 20)), (16, 16));
+        // DebugVer 6, 6 breakpoints and 4 watchpoints, with a PMU and context-aware breakpoints
+        let dfr0 = 0x0000_0000_1030_5106;
+        assert_eq!((breakpoints(dfr0), watchpoints(dfr0)), (6, 4));
+        assert_eq!(decode_debug_version(field(dfr0, 0)), "v8.0");
+        assert_eq!(decode_debug_version(0b1000), "v8.2");
+        assert_eq!(decode_debug_version(0b0000), "unknown");
+    }
+}
@@ -0,0 +1,39 @@
+//! Access to the FP/SIMD registers.
+//!
+//! `CPTR_EL3.TFP` traps FP/SIMD instructions from all ELs to EL3. With the `fpsimd` feature, the
+//! trap is disabled and the FP/SIMD state becomes part of the per-world CPU contexts, which EL3
+//! must then switch along with the worlds. Without it, any FP/SIMD use traps: at EL3 this is
+//! reported as a fatal error, and lower ELs must not use FP/SIMD.
+//!
+//! The monitor itself is built for a soft-float target and never uses FP/SIMD instructions, except
+//! for saving and restoring the lower-EL state.
+
+use core::arch::asm;
+
+/// CPTR_EL3.TFP: trap FP/SIMD accesses to EL3.
+const CPTR_EL3_TFP: u64 = 1 << 10;
+
+/// Gives all ELs access to the FP/SIMD registers.
+pub fn enable() {
+    write_cptr(read_cptr() & !CPTR_EL3_TFP);
+}
+
+/// Traps FP/SIMD accesses from all ELs to EL3.
+pub fn disable() {
+    write_cptr(read_cptr() | CPTR_EL3_TFP);
+}
+
+/// Returns `true` if FP/SIMD accesses are not trapped.
+pub fn is_enabled() -> bool {
+    read_cptr() & CPTR_EL3_TFP == 0
+}
+
+fn read_cptr() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, CPTR_EL3", out(reg) value) };
+    value
+}
+
+fn write_cptr(value: u64) {
+    unsafe { asm!("msr CPTR_EL3, {}", "isb", in(reg) value) };
+}
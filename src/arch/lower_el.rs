@@ -0,0 +1,158 @@
+//! Entering lower exception levels.
+
+use crate::arch::context::{self, World};
+use crate::arch::scr::ScrEl3;
+use crate::arch::{eret_guard, feature, pauth, timer};
+use crate::platform;
+use core::arch::asm;
+
+/// SCTLR_EL1 with the MMU and caches disabled (RES1 bits only).
+pub(super) const SCTLR_EL1_RES1: u64 =
+    (1 << 29) | (1 << 28) | (1 << 23) | (1 << 22) | (1 << 20) | (1 << 11);
+/// SCTLR_EL2 with the MMU and caches disabled (RES1 bits only).
+const SCTLR_EL2_RES1: u64 = (1 << 29)
+    | (1 << 28)
+    | (1 << 23)
+    | (1 << 22)
+    | (1 << 18)
+    | (1 << 16)
+    | (1 << 11)
+    | (1 << 5)
+    | (1 << 4);
+
+/// HCR_EL2.RW: EL1 executes in AArch64.
+const HCR_EL2_RW: u64 = 1 << 31;
+/// CPTR_EL2 RES1 bits, without trapping FP/SIMD.
+const CPTR_EL2_RES1: u64 = 0x33FF;
+
+// SPSR_EL3 bits
+pub(super) const SPSR_DAIF: u64 = 0b1111 << 6;
+pub(super) const SPSR_M_EL1H: u64 = 0b0101;
+const SPSR_M_EL2H: u64 = 0b1001;
+
+/// An exception level, running in AArch64.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionLevel {
+    El0,
+    El1,
+    El2,
+    El3,
+}
+
+/// Returns the exception level we are running at.
+pub fn current_el() -> ExceptionLevel {
+    let current_el: u64;
+    unsafe { asm!("mrs {}, CurrentEL", out(reg) current_el) };
+    match (current_el >> 2) & 0b11 {
+        0 => ExceptionLevel::El0,
+        1 => ExceptionLevel::El1,
+        2 => ExceptionLevel::El2,
+        _ => ExceptionLevel::El3,
+    }
+}
+
+/// Drops to `target` in `world`, starting execution at `entry` with `arg` in `x0`.
+///
+/// The target EL starts with its MMU and caches disabled and all exceptions masked. All other
+/// general purpose registers are cleared so that no EL3 state leaks.
+///
+/// # Panics
+///
+/// Panics if `target` is not EL1 or EL2, or if `world` is not supported by the hardware.
+pub fn enter_lower_el(entry: usize, arg: usize, target: ExceptionLevel, world: World) -> ! {
+    world.assert_supported();
+    timer::configure_lower_el_access(platform::LOWER_EL_TIMER_ACCESS);
+    let to_el2 = target == ExceptionLevel::El2;
+    let scr = ScrEl3::read()
+        .world(world)
+        .hce(to_el2)
+        .eel2(to_el2 && world == World::Secure);
+
+    let mode = match target {
+        ExceptionLevel::El1 => {
+            if feature::has_el2() {
+                init_el2_for_el1();
+            }
+            unsafe { asm!("msr SCTLR_EL1, {}", in(reg) SCTLR_EL1_RES1) };
+            timer::trap_el0_access();
+            SPSR_M_EL1H
+        }
+        ExceptionLevel::El2 => {
+            unsafe { asm!("msr SCTLR_EL2, {}", in(reg) SCTLR_EL2_RES1) };
+            SPSR_M_EL2H
+        }
+        ExceptionLevel::El0 | ExceptionLevel::El3 => panic!("can not enter {target:?}"),
+    };
+    let spsr = eret_guard::prepare(SPSR_DAIF | mode, entry as u64, world)
+        .unwrap_or_else(|violation| panic!("Refusing {violation} to the {world:?} world"));
+
+    log::debug!("Entering {target:?} ({world:?} world) at {entry:#x}");
+    log::debug!("  SCR_EL3: {scr}");
+    context::set_running(world);
+    scr.write();
+    if pauth::is_enabled() {
+        // Don't hand the monitor's key over. This function never returns, so it doesn't
+        // authenticate its own return address with it.
+        unsafe { asm!("msr S3_0_C2_C1_0, xzr", "msr S3_0_C2_C1_1, xzr") };
+    }
+    unsafe {
+        asm!(
+            "msr ELR_EL3, {entry}",
+            "msr SPSR_EL3, {spsr}",
+            "isb",
+            "mov x1, xzr",
+            "mov x2, xzr",
+            "mov x3, xzr",
+            "mov x4, xzr",
+            "mov x5, xzr",
+            "mov x6, xzr",
+            "mov x7, xzr",
+            "mov x8, xzr",
+            "mov x9, xzr",
+            "mov x10, xzr",
+            "mov x11, xzr",
+            "mov x12, xzr",
+            "mov x13, xzr",
+            "mov x14, xzr",
+            "mov x15, xzr",
+            "mov x16, xzr",
+            "mov x17, xzr",
+            "mov x18, xzr",
+            "mov x19, xzr",
+            "mov x20, xzr",
+            "mov x21, xzr",
+            "mov x22, xzr",
+            "mov x23, xzr",
+            "mov x24, xzr",
+            "mov x25, xzr",
+            "mov x26, xzr",
+            "mov x27, xzr",
+            "mov x28, xzr",
+            "mov x29, xzr",
+            "mov x30, xzr",
+            "eret",
+            entry = in(reg) entry,
+            spsr = in(reg) spsr,
+            in("x0") arg,
+            options(noreturn),
+        );
+    }
+}
+
+/// Configures EL2 so that it doesn't get in the way of an EL1 payload.
+fn init_el2_for_el1() {
+    unsafe {
+        asm!(
+            "msr HCR_EL2, {hcr}",
+            "msr CPTR_EL2, {cptr}",
+            "msr HSTR_EL2, xzr",
+            "mrs {tmp}, MIDR_EL1",
+            "msr VPIDR_EL2, {tmp}",
+            "mrs {tmp}, MPIDR_EL1",
+            "msr VMPIDR_EL2, {tmp}",
+            hcr = in(reg) HCR_EL2_RW,
+            cptr = in(reg) CPTR_EL2_RES1,
+            tmp = out(reg) _,
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Identification of the PE through the Main ID Register.
+//!
+//! Reference: MIDR_EL1, Main ID Register.
+
+use crate::ktest::kernel_test;
+use core::arch::asm;
+use core::fmt;
+
+/// A PE model, identified by its implementer and part number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Model {
+    pub implementer: u8,
+    pub part: u16,
+}
+
+/// Implementer code of Arm Limited.
+const ARM: u8 = 0x41;
+
+pub const CORTEX_A53: Model = Model::new(ARM, 0xD03);
+pub const CORTEX_A57: Model = Model::new(ARM, 0xD07);
+pub const CORTEX_A72: Model = Model::new(ARM, 0xD08);
+pub const NEOVERSE_N1: Model = Model::new(ARM, 0xD0C);
+/// The QEMU "max" CPU, which has no implementer and uses 'Q' as part number.
+pub const QEMU_MAX: Model = Model::new(0x00, 0x051);
+
+/// The models we know by name.
+const KNOWN_MODELS: [(Model, &str); 5] = [
+    (CORTEX_A53, "Cortex-A53"),
+    (CORTEX_A57, "Cortex-A57"),
+    (CORTEX_A72, "Cortex-A72"),
+    (NEOVERSE_N1, "Neoverse N1"),
+    (QEMU_MAX, "QEMU max"),
+];
+
+impl Model {
+    pub const fn new(implementer: u8, part: u16) -> Self {
+        Self { implementer, part }
+    }
+
+    /// Returns the name of the model, if known.
+    pub fn name(self) -> Option<&'static str> {
+        KNOWN_MODELS
+            .iter()
+            .find(|(model, _)| *model == self)
+            .map(|(_, name)| *name)
+    }
+}
+
+/// The decoded value of `MIDR_EL1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Midr {
+    pub model: Model,
+    /// The major revision number, the `x` in `rxpy`.
+    pub variant: u8,
+    /// The minor revision number, the `y` in `rxpy`.
+    pub revision: u8,
+}
+
+impl Midr {
+    /// Reads and decodes `MIDR_EL1` of the current PE.
+    pub fn read() -> Self {
+        let value: u64;
+        unsafe { asm!("mrs {}, MIDR_EL1", out(reg) value) };
+        Self::decode(value)
+    }
+
+    /// Decodes a value of `MIDR_EL1`.
+    pub fn decode(value: u64) -> Self {
+        Midr {
+            model: Model {
+                implementer: ((value >> 24) & 0xFF) as u8,
+                part: ((value >> 4) & 0xFFF) as u16,
+            },
+            variant: ((value >> 20) & 0xF) as u8,
+            revision: (value & 0xF) as u8,
+        }
+    }
+
+    /// Returns `true` if the PE is one of the given models.
+    pub fn is_any(self, models: &[Model]) -> bool {
+        models.contains(&self.model)
+    }
+
+    /// Returns `true` if the PE is at revision `r{variant}p{revision}` or earlier.
+    pub fn is_at_most(self, variant: u8, revision: u8) -> bool {
+        (self.variant, self.revision) <= (variant, revision)
+    }
+
+    /// Returns `true` if the PE is at revision `r{variant}p{revision}` or later.
+    pub fn is_at_least(self, variant: u8, revision: u8) -> bool {
+        (self.variant, self.revision) >= (variant, revision)
+    }
+}
+
+impl fmt::Display for Midr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.model.name() {
+            Some(name) => write!(f, "{name}")?,
+            None => write!(
+                f,
+                "implementer {:#04x} part {:#05x}",
+                self.model.implementer, self.model.part
+            )?,
+        }
+        write!(f, " r{}p{}", self.variant, self.revision)
+    }
+}
+
+kernel_test! {
+    fn midr_decoding() {
+        // Cortex-A53 r0p4, Cortex-A72 r1p2, and QEMU max
+        let cases = [
+            (0x410F_D034, CORTEX_A53, 0, 4),
+            (0x411F_D082, CORTEX_A72, 1, 2),
+            (0x000F_0510, QEMU_MAX, 0, 0),
+        ];
+        for (value, model, variant, revision) in cases {
+            let midr = Midr::decode(value);
+            assert_eq!(midr, Midr { model, variant, revision }, "MIDR {value:#x}");
+        }
+        // The architecture field and the upper bits are ignored
+        assert_eq!(Midr::decode(0xFFFF_FFFF_4100_D034), Midr::decode(0x410F_D034));
+        assert!(Midr::decode(0x411F_D082).is_any(&[CORTEX_A57, CORTEX_A72]));
+        assert!(!Midr::decode(0x410F_D034).is_any(&[CORTEX_A57, CORTEX_A72]));
+    }
+}
@@ -0,0 +1,487 @@
+//! EL3 stage 1 translation.
+//!
+//! The monitor runs on an identity map with a 4 KiB granule. The image is mapped with one set of
+//! permissions per section (executable text, read-only rodata, and writable data, bss, and
+//! stack), RAM as writable normal memory, and the device window as device memory. Only the text
+//! is executable, and the translation tables become read-only once the MMU is enabled.
+
+use crate::arch::{cache, feature, tlb};
+use crate::ktest::kernel_test;
+use crate::sync::SpinLock;
+use crate::{memory_layout, platform};
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const PAGE_SIZE: usize = 0x1000;
+/// Number of entries in a translation table.
+const ENTRIES: usize = 512;
+/// Number of translation tables available to build the map.
+const TABLE_COUNT: usize = 16;
+/// The largest PARange we support, 48 bits. Larger address spaces require FEAT_LPA2.
+const MAX_PA_RANGE: u64 = 0b0101;
+
+// Descriptor bits
+const DESC_VALID: u64 = 1 << 0;
+const DESC_TABLE: u64 = 1 << 1; // Also set for level 3 pages
+const DESC_ATTR_INDEX_SHIFT: u64 = 2;
+const DESC_AP_RES1: u64 = 1 << 6; // AP[1] is RES1 in the EL3 regime
+const DESC_AP_RO: u64 = 1 << 7;
+const DESC_SH_INNER: u64 = 0b11 << 8;
+const DESC_AF: u64 = 1 << 10;
+const DESC_XN: u64 = 1 << 54;
+const DESC_ADDR_MASK: u64 = 0x0000_FFFF_FFFF_F000;
+
+// Memory attributes, as indices into MAIR_EL3
+const ATTR_DEVICE: u64 = 0;
+const ATTR_NORMAL: u64 = 1;
+const MAIR: u64 = (0x04 << (8 * ATTR_DEVICE)) // Device-nGnRE
+    | (0xFF << (8 * ATTR_NORMAL)); // Normal, write-back read/write-allocate
+
+// TCR_EL3 fields, TG0 is left to 0 for a 4 KiB granule
+const TCR_RES1: u64 = (1 << 31) | (1 << 23);
+const TCR_IRGN0_WB: u64 = 0b01 << 8;
+const TCR_ORGN0_WB: u64 = 0b01 << 10;
+const TCR_SH0_INNER: u64 = 0b11 << 12;
+const TCR_PS_SHIFT: u64 = 16;
+
+// SCTLR_EL3 bits
+const SCTLR_M: u64 = 1 << 0;
+const SCTLR_C: u64 = 1 << 2;
+const SCTLR_I: u64 = 1 << 12;
+const SCTLR_WXN: u64 = 1 << 19;
+
+/// The translation tables, the first one is the root.
+static PAGE_TABLES: SpinLock<PageTables> = SpinLock::new(PageTables::new());
+
+/// Set while the MMU is being enabled, so that a fault can roll back to a known state.
+static ENABLING: AtomicBool = AtomicBool::new(false);
+
+/// Builds the identity map and enables the MMU and caches.
+///
+/// # Panics
+///
+/// Panics if the 4 KiB granule is not supported, if the map can not be built, or if enabling the
+/// MMU faults.
+pub fn init() {
+    assert!(
+        feature::supports_4k_granule(),
+        "4 KiB granule not supported"
+    );
+    let pa_range = feature::pa_range().min(MAX_PA_RANGE);
+    let va_bits = feature::pa_range_bits(pa_range) as u32;
+
+    let image = memory_layout::image();
+    let (image_start, image_end) = (image.start, image.end);
+    let rodata_start = memory_layout::rodata().start;
+    let data_start = memory_layout::data().start;
+    let secure_ram_end = platform::SECURE_RAM_BASE + platform::SECURE_RAM_SIZE;
+    assert!(
+        platform::SECURE_RAM_BASE <= image_start && image_end <= secure_ram_end,
+        "the image must be in secure RAM"
+    );
+
+    let mut tables = PAGE_TABLES.lock();
+    tables.init(va_bits);
+    tables.map(platform::SECURE_RAM_BASE, image_start, Mapping::ReadWrite);
+    tables.map(image_start, rodata_start, Mapping::Code);
+    tables.map(rodata_start, data_start, Mapping::ReadOnly);
+    tables.map(data_start, image_end, Mapping::ReadWrite);
+    tables.map(image_end, secure_ram_end, Mapping::ReadWrite);
+    tables.map(
+        platform::DEVICE_BASE,
+        platform::DEVICE_BASE + platform::DEVICE_SIZE,
+        Mapping::Device,
+    );
+    tables.map(
+        platform::DRAM_BASE,
+        platform::DRAM_BASE + platform::DRAM_SIZE,
+        Mapping::ReadWrite,
+    );
+
+    let tcr = TCR_RES1
+        | TCR_IRGN0_WB
+        | TCR_ORGN0_WB
+        | TCR_SH0_INNER
+        | (pa_range << TCR_PS_SHIFT)
+        | (64 - va_bits) as u64; // T0SZ
+
+    // The image (including the tables) was written with the caches off, the caches may still
+    // hold stale lines from the previous boot stage.
+    cache::invalidate_dcache_range(image_start, image_end - image_start);
+
+    unsafe {
+        asm!(
+            "msr MAIR_EL3, {mair}",
+            "msr TCR_EL3, {tcr}",
+            "msr TTBR0_EL3, {ttbr}",
+            "isb",
+            mair = in(reg) MAIR,
+            tcr = in(reg) tcr,
+            ttbr = in(reg) tables.root_address(),
+        );
+    }
+    tlb::invalidate_all_el3();
+
+    ENABLING.store(true, Ordering::SeqCst);
+    unsafe {
+        asm!(
+            "mrs {sctlr}, SCTLR_EL3",
+            "orr {sctlr}, {sctlr}, {flags}",
+            "msr SCTLR_EL3, {sctlr}",
+            "isb",
+            sctlr = out(reg) _,
+            flags = in(reg) SCTLR_M | SCTLR_C | SCTLR_I | SCTLR_WXN,
+        );
+    }
+    ENABLING.store(false, Ordering::SeqCst);
+
+    tables.write_protect();
+    log::info!(
+        "MMU enabled, {va_bits}-bit address space, {}/{TABLE_COUNT} page tables",
+        tables.used
+    );
+}
+
+/// Disables the MMU and caches if they were being enabled.
+///
+/// Must be called on synchronous exceptions. Returns `true` if the exception was caused by
+/// enabling the MMU, in which case it must be reported as a fatal error.
+pub fn rollback_failed_enable() -> bool {
+    if !ENABLING.swap(false, Ordering::SeqCst) {
+        return false;
+    }
+
+    // Write back the dirty lines (including the exception frame), they would be lost otherwise.
+    let image = memory_layout::image();
+    cache::clean_invalidate_dcache_range(image.start, image.len());
+    unsafe {
+        asm!(
+            "mrs {sctlr}, SCTLR_EL3",
+            "bic {sctlr}, {sctlr}, {flags}",
+            "msr SCTLR_EL3, {sctlr}",
+            "isb",
+            sctlr = out(reg) _,
+            flags = in(reg) SCTLR_M | SCTLR_C | SCTLR_I | SCTLR_WXN,
+        );
+    }
+    true
+}
+
+// —————————————————————————————— Page Tables ——————————————————————————————— //
+
+/// The kind of memory of a mapping, and its permissions.
+#[derive(Clone, Copy, Debug)]
+enum Mapping {
+    /// Read-only and executable normal memory.
+    Code,
+    /// Read-only normal memory.
+    ReadOnly,
+    /// Writable normal memory.
+    ReadWrite,
+    /// Writable device memory.
+    Device,
+}
+
+impl Mapping {
+    /// Returns the attributes of a block or page descriptor.
+    fn attributes(self) -> u64 {
+        let normal = (ATTR_NORMAL << DESC_ATTR_INDEX_SHIFT) | DESC_SH_INNER;
+        let device = ATTR_DEVICE << DESC_ATTR_INDEX_SHIFT;
+        let common = DESC_AF | DESC_AP_RES1;
+        common
+            | match self {
+                Mapping::Code => normal | DESC_AP_RO,
+                Mapping::ReadOnly => normal | DESC_AP_RO | DESC_XN,
+                Mapping::ReadWrite => normal | DESC_XN,
+                Mapping::Device => device | DESC_XN,
+            }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, align(4096))]
+struct Table([u64; ENTRIES]);
+
+/// A pool of translation tables, allocated in order.
+struct PageTables {
+    tables: [Table; TABLE_COUNT],
+    /// Number of tables in use.
+    used: usize,
+    /// The lookup level of the root table.
+    root_level: usize,
+    /// Size of the address space, in bits.
+    va_bits: u32,
+}
+
+impl PageTables {
+    const fn new() -> Self {
+        Self {
+            tables: [Table([0; ENTRIES]); TABLE_COUNT],
+            used: 0,
+            root_level: 0,
+            va_bits: 0,
+        }
+    }
+
+    /// Prepares an empty root table for an address space of `va_bits` bits.
+    fn init(&mut self, va_bits: u32) {
+        assert!(self.used == 0, "page tables already initialized");
+        // Each level resolves 9 bits, on top of the 12 bits of the page offset
+        let levels = (va_bits as usize - 12).div_ceil(9);
+        self.root_level = 4 - levels;
+        self.va_bits = va_bits;
+        self.used = 1;
+    }
+
+    /// Identity-maps the region between `start` and `end`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the region is not page aligned, overlaps an existing mapping, does not fit in the
+    /// address space, or if we run out of tables.
+    fn map(&mut self, start: usize, end: usize, mapping: Mapping) {
+        assert!(
+            start.is_multiple_of(PAGE_SIZE) && end.is_multiple_of(PAGE_SIZE),
+            "unaligned mapping {start:#x}-{end:#x}"
+        );
+        assert!(
+            end >> self.va_bits == 0,
+            "{end:#x} is out of the address space"
+        );
+        log::debug!("Mapping {start:#x}-{end:#x} as {mapping:?}");
+        self.map_range(0, self.root_level, start, end, mapping.attributes());
+    }
+
+    fn map_range(&mut self, table: usize, level: usize, start: usize, end: usize, attrs: u64) {
+        let shift = 12 + 9 * (3 - level);
+        let entry_size = 1 << shift;
+
+        let mut addr = start;
+        while addr < end {
+            let index = (addr >> shift) % ENTRIES;
+            let entry_end = (addr & !(entry_size - 1)) + entry_size;
+            let chunk_end = entry_end.min(end);
+            let covers_entry = addr.is_multiple_of(entry_size) && chunk_end == entry_end;
+
+            if level == 3 || (level > 0 && covers_entry) {
+                // Level 0 can not hold blocks with a 4 KiB granule
+                let page = if level == 3 { DESC_TABLE } else { 0 };
+                let entry = &mut self.tables[table].0[index];
+                assert!(*entry & DESC_VALID == 0, "overlapping mapping at {addr:#x}");
+                *entry = addr as u64 | attrs | page | DESC_VALID;
+            } else {
+                let next = self.next_table(table, index);
+                self.map_range(next, level + 1, addr, chunk_end, attrs);
+            }
+            addr = chunk_end;
+        }
+    }
+
+    /// Returns the table pointed to by an entry, allocating it if needed.
+    fn next_table(&mut self, table: usize, index: usize) -> usize {
+        let entry = self.tables[table].0[index];
+        if entry & DESC_VALID != 0 {
+            assert!(entry & DESC_TABLE != 0, "overlapping block mapping");
+            return self.table_index(entry);
+        }
+
+        assert!(self.used < TABLE_COUNT, "out of page tables");
+        let next = self.used;
+        self.used += 1;
+        let address = &self.tables[next] as *const Table as u64;
+        self.tables[table].0[index] = address | DESC_TABLE | DESC_VALID;
+        next
+    }
+
+    /// Makes the tables read-only, so that stray writes can not change the map.
+    ///
+    /// Must be called once the MMU is enabled, the map can not be modified afterward.
+    fn write_protect(&mut self) {
+        let start = self.root_address() as usize;
+        let entries: [(usize, usize); TABLE_COUNT] =
+            core::array::from_fn(|i| self.page_entry(start + i * PAGE_SIZE));
+
+        // The tables holding the entries we modify must be protected last.
+        let holds_entries = |table: usize| entries.iter().any(|&(t, _)| t == table);
+        for last in [false, true] {
+            for (page, &(table, index)) in entries.iter().enumerate() {
+                if holds_entries(page) == last {
+                    self.tables[table].0[index] |= DESC_AP_RO;
+                }
+            }
+        }
+        for page in 0..TABLE_COUNT {
+            tlb::invalidate_va_el3(start + page * PAGE_SIZE);
+        }
+    }
+
+    /// Returns the table and index of the level 3 entry mapping `addr`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `addr` is not mapped by a page.
+    fn page_entry(&self, addr: usize) -> (usize, usize) {
+        let mut table = 0;
+        for level in self.root_level..3 {
+            let index = (addr >> (12 + 9 * (3 - level))) % ENTRIES;
+            let entry = self.tables[table].0[index];
+            assert!(
+                entry & (DESC_VALID | DESC_TABLE) == DESC_VALID | DESC_TABLE,
+                "{addr:#x} is not mapped by a page"
+            );
+            table = self.table_index(entry);
+        }
+        (table, (addr / PAGE_SIZE) % ENTRIES)
+    }
+
+    /// Returns the index of the table pointed to by a table descriptor.
+    fn table_index(&self, entry: u64) -> usize {
+        let offset = (entry & DESC_ADDR_MASK) - self.root_address();
+        offset as usize / PAGE_SIZE
+    }
+
+    fn root_address(&self) -> u64 {
+        &self.tables[0] as *const Table as u64
+    }
+}
+
+// ——————————————————————————— Register Decoding ———————————————————————————— //
+
+/// A value of SCTLR_EL3, displayed with the names of the bits that are set.
+#[derive(Clone, Copy, Debug)]
+pub struct SctlrEl3(pub u64);
+
+impl SctlrEl3 {
+    /// Names of the decoded bits.
+    const FIELDS: [(u64, &str); 8] = [
+        (SCTLR_M, "M"),
+        (1 << 1, "A"),
+        (SCTLR_C, "C"),
+        (1 << 3, "SA"),
+        (SCTLR_I, "I"),
+        (SCTLR_WXN, "WXN"),
+        (1 << 25, "EE"),
+        (1 << 31, "EnIA"),
+    ];
+
+    pub fn read() -> Self {
+        let value: u64;
+        unsafe { asm!("mrs {}, SCTLR_EL3", out(reg) value) };
+        Self(value)
+    }
+}
+
+impl fmt::Display for SctlrEl3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x} [", self.0)?;
+        let mut first = true;
+        for (bit, name) in Self::FIELDS {
+            if self.0 & bit != 0 {
+                if !first {
+                    f.write_str(" ")?;
+                }
+                f.write_str(name)?;
+                first = false;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+/// A value of TCR_EL3, displayed with its main fields.
+#[derive(Clone, Copy, Debug)]
+pub struct TcrEl3(pub u64);
+
+impl TcrEl3 {
+    pub fn read() -> Self {
+        let value: u64;
+        unsafe { asm!("mrs {}, TCR_EL3", out(reg) value) };
+        Self(value)
+    }
+}
+
+impl fmt::Display for TcrEl3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t0sz = self.0 & 0x3F;
+        let granule = match (self.0 >> 14) & 0b11 {
+            0b00 => "4K",
+            0b01 => "64K",
+            0b10 => "16K",
+            _ => "reserved",
+        };
+        let pa_bits = feature::pa_range_bits((self.0 >> TCR_PS_SHIFT) & 0b111);
+        write!(
+            f,
+            "{:#018x} [T0SZ={t0sz} ({}-bit VA) TG0={granule} PS={pa_bits}-bit]",
+            self.0,
+            64 - t0sz
+        )
+    }
+}
+
+kernel_test! {
+    fn execution_continues_across_enable() {
+        // The tests run once the boot is over, well after the enable
+        let flags = SCTLR_M | SCTLR_C | SCTLR_I | SCTLR_WXN;
+        let sctlr = SctlrEl3::read();
+        assert_eq!(sctlr.0 & flags, flags, "SCTLR_EL3 is {sctlr}");
+        assert!(!ENABLING.load(Ordering::SeqCst), "the enable didn't complete");
+    }
+}
+
+kernel_test! {
+    fn text_is_write_protected() {
+        use crate::arch::esr::{ExceptionClass, FaultStatus};
+        use crate::ktest;
+
+        let addr = memory_layout::text().start;
+        let fault = ktest::expect_fault(|| unsafe {
+            asm!("str {}, [{}]", in(reg) 0u64, in(reg) addr);
+        });
+        let fault = fault.expect("the write to the text didn't fault");
+        assert!(
+            matches!(
+                fault.esr.class,
+                ExceptionClass::DataAbort {
+                    lower_el: false,
+                    status: FaultStatus::Permission { .. },
+                    write: true,
+                    far_valid: true,
+                }
+            ),
+            "unexpected fault: {}",
+            fault.esr
+        );
+        assert_eq!(fault.far, addr as u64);
+    }
+}
+
+kernel_test! {
+    fn uart_is_device_memory() {
+        use crate::driver::serial::SerialPort;
+
+        // PAR_EL1 belongs to the EL1 state of the worlds, keep it
+        let par: u64;
+        unsafe {
+            asm!(
+                "mrs {saved}, PAR_EL1",
+                "at s1e3r, {addr}",
+                "isb",
+                "mrs {par}, PAR_EL1",
+                "msr PAR_EL1, {saved}",
+                addr = in(reg) platform::UART1_BASE,
+                saved = out(reg) _,
+                par = out(reg) par,
+            );
+        }
+        assert_eq!(par & 1, 0, "the UART is not mapped, PAR_EL1 is {par:#x}");
+        assert_eq!((par & DESC_ADDR_MASK) as usize, platform::UART1_BASE);
+        assert_eq!(par >> 56, 0x04, "the UART is not Device-nGnRE");
+
+        // Reads and writes of the UART registers still work
+        let uart = unsafe { platform::secure_uart() };
+        uart.flush();
+        assert!(uart.can_write());
+    }
+}
@@ -1,3 +1,26 @@
 //! AArch64 architecture helpers (system registers, feature detection).
 
+pub mod cache;
+pub mod context;
+pub mod dit;
+pub mod eret_guard;
+pub mod errata;
+pub mod esr;
+pub mod exception;
 pub mod feature;
+pub mod fpsimd;
+mod lower_el;
+pub mod midr;
+pub mod mmu;
+mod mpidr;
+pub mod park;
+pub mod pauth;
+pub mod pmu;
+pub mod rand;
+pub mod scr;
+pub mod serror;
+pub mod timer;
+pub mod tlb;
+
+pub use lower_el::{ExceptionLevel, current_el, enter_lower_el};
+pub use mpidr::{CoreId, Topology};
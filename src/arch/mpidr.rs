@@ -0,0 +1,143 @@
+//! Identification of the cores through their affinity, from the Multiprocessor Affinity Register.
+//!
+//! Reference: MPIDR_EL1, Multiprocessor Affinity Register.
+
+use crate::ktest::kernel_test;
+use crate::platform;
+use core::arch::asm;
+use core::fmt;
+
+/// The affinity of a core, the four affinity fields of `MPIDR_EL1` packed together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CoreId(u32);
+
+impl CoreId {
+    /// Returns the ID of the calling core.
+    pub fn current() -> Self {
+        let mpidr: u64;
+        unsafe { asm!("mrs {}, MPIDR_EL1", out(reg) mpidr) };
+        Self::from_mpidr(mpidr)
+    }
+
+    /// Extracts the affinity fields of a value of `MPIDR_EL1`.
+    pub fn from_mpidr(mpidr: u64) -> Self {
+        // Aff3 is at [39:32], apart from the others at [23:0]
+        let low = mpidr & 0x00FF_FFFF;
+        let aff3 = (mpidr >> 32) & 0xFF;
+        CoreId((low | (aff3 << 24)) as u32)
+    }
+
+    /// Rebuilds an ID from the value returned by [CoreId::packed].
+    pub fn from_packed(packed: u32) -> Self {
+        CoreId(packed)
+    }
+
+    /// Returns the affinity at the given level, from 0 to 3.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the level is larger than 3.
+    pub fn aff(self, level: u32) -> u8 {
+        assert!(level <= 3, "invalid affinity level: {level}");
+        (self.0 >> (8 * level)) as u8
+    }
+
+    /// Returns the affinity as `Aff3.Aff2.Aff1.Aff0`, the layout used by the GIC.
+    pub fn packed(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the linear index of the core in the platform topology, if it is within bounds.
+    pub fn linear_index(self) -> Option<usize> {
+        platform::linear_index(self)
+    }
+}
+
+/// The layout of the cores of a platform in the affinity levels.
+#[derive(Clone, Copy, Debug)]
+pub struct Topology {
+    /// Whether `MPIDR_EL1.MT` is set: Aff0 identifies a thread within a core, and the cores and
+    /// clusters are one level up.
+    pub multithreaded: bool,
+    pub threads_per_core: usize,
+    pub cores_per_cluster: usize,
+    pub clusters: usize,
+}
+
+impl Topology {
+    /// Returns the linear index of a core, thread by thread, core by core, and cluster by
+    /// cluster. Returns `None` for cores outside of the topology.
+    pub fn linear_index(&self, core: CoreId) -> Option<usize> {
+        let (thread, id, cluster, upper) = match self.multithreaded {
+            true => (core.aff(0), core.aff(1), core.aff(2), core.aff(3)),
+            false => (0, core.aff(0), core.aff(1), core.aff(2) | core.aff(3)),
+        };
+        let (thread, id, cluster) = (thread as usize, id as usize, cluster as usize);
+        if upper != 0
+            || thread >= self.threads_per_core
+            || id >= self.cores_per_cluster
+            || cluster >= self.clusters
+        {
+            return None;
+        }
+        Some((cluster * self.cores_per_cluster + id) * self.threads_per_core + thread)
+    }
+}
+
+impl fmt::Display for CoreId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.aff(3),
+            self.aff(2),
+            self.aff(1),
+            self.aff(0)
+        )
+    }
+}
+
+kernel_test! {
+    fn affinity_extraction() {
+        let core = CoreId::from_mpidr(0x0000_00A5_81C3_B2D1);
+        assert_eq!([core.aff(0), core.aff(1), core.aff(2), core.aff(3)], [0xD1, 0xB2, 0xC3, 0xA5]);
+        assert_eq!(core.packed(), 0xA5C3_B2D1);
+        assert_eq!(CoreId::from_packed(core.packed()), core);
+        // U and MT, bits 30 and 24, are not part of the affinity, nor is RES1 bit 31
+        assert_eq!(CoreId::from_mpidr(0xC100_0003), CoreId::from_mpidr(3));
+    }
+}
+
+kernel_test! {
+    fn linear_index_by_topology() {
+        // QEMU virt: Aff0 is the core within a cluster of 16, the index is capped to MAX_CPUS
+        let qemu = [(0x0, Some(0)), (0x7, Some(7)), (0x8, None), (0x100, None), (0x1_0000, None)];
+        for (mpidr, expected) in qemu {
+            assert_eq!(CoreId::from_mpidr(mpidr).linear_index(), expected, "MPIDR {mpidr:#x}");
+        }
+
+        // FVP with 4 clusters of 4 cores, MT set: Aff1 is the core, Aff2 the cluster
+        let fvp = Topology {
+            multithreaded: true,
+            threads_per_core: 1,
+            cores_per_cluster: 4,
+            clusters: 4,
+        };
+        let cases = [
+            (0x0100_0000, Some(0)),
+            (0x0100_0100, Some(1)),
+            (0x0100_0300, Some(3)),
+            (0x0101_0000, Some(4)),
+            (0x0103_0300, Some(15)),
+            // Second thread, fifth core, fifth cluster, and Aff3
+            (0x0100_0001, None),
+            (0x0100_0400, None),
+            (0x0104_0000, None),
+            (0x1_0100_0000, None),
+        ];
+        for (mpidr, expected) in cases {
+            let index = fvp.linear_index(CoreId::from_mpidr(mpidr));
+            assert_eq!(index, expected, "MPIDR {mpidr:#x}");
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! The landing pad of offline cores.
+
+use core::arch::asm;
+
+/// Parks the calling core until it is reset.
+///
+/// All exceptions are masked, and the core waits in a low-power state. Pending interrupts still
+/// wake WFI up even when masked, hence the loop. The core leaves the loop only through a reset:
+/// a later `CPU_ON` restarts it from the firmware entry point, with none of the parked state.
+///
+/// ```ignore
+/// // The calling core won't be used again until it is turned back on
+/// arch::park::park_secondary();
+/// ```
+pub fn park_secondary() -> ! {
+    unsafe { asm!("msr DAIFSet, #0b1111", "isb") };
+    loop {
+        unsafe { asm!("dsb sy", "wfi") };
+    }
+}
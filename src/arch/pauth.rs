@@ -0,0 +1,201 @@
+//! Pointer authentication (FEAT_PAuth).
+//!
+//! With the `pauth` feature, and when built with `-Z branch-protection=pac-ret`, the monitor signs
+//! its return addresses with the instruction key A. The lower ELs are given access to pointer
+//! authentication too, and their keys become part of the per-world CPU contexts.
+//!
+//! The key registers are shared between EL3 and the lower ELs. The exception vectors save the
+//! instruction key A of the interrupted context in the exception frame and load the monitor's key,
+//! and put the saved key back on exception return. The other keys are not used by the monitor, and
+//! are only switched along with the worlds.
+//!
+//! Enabling signing must be done from a function that never returns: the return addresses signed
+//! before that would not authenticate. Without the compiler flag the monitor simply doesn't sign
+//! anything, and without hardware support nothing is enabled.
+
+use crate::arch::exception::ExceptionFrame;
+use crate::arch::scr::ScrEl3;
+use crate::arch::{feature, rand};
+use core::arch::{asm, global_asm, naked_asm};
+use core::mem::offset_of;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Set once pointer authentication is enabled, read by the exception vectors.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The monitor's instruction key A (low and high halves), loaded by the exception vectors.
+static EL3_KEY: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+/// SCTLR_EL3.EnIA: enables pointer authentication with the instruction key A.
+const SCTLR_EL3_ENIA: u64 = 1 << 31;
+
+/// Generates the monitor's key and gives the lower ELs access to pointer authentication.
+///
+/// Returns `false` if pointer authentication is not implemented. Otherwise, signing must then be
+/// started with [enable_el3].
+pub fn init() -> bool {
+    if !feature::has_pauth() {
+        log::info!("PAuth: not implemented");
+        return false;
+    }
+
+    let (lo, entropy) = rand::random_u64();
+    let (hi, _) = rand::random_u64();
+    EL3_KEY[0].store(lo, Ordering::Relaxed);
+    EL3_KEY[1].store(hi, Ordering::Relaxed);
+    unsafe { asm!("msr S3_0_C2_C1_0, {}", "msr S3_0_C2_C1_1, {}", in(reg) lo, in(reg) hi) };
+    ScrEl3::read().pauth(true).write();
+    ENABLED.store(true, Ordering::Relaxed);
+    log::info!("PAuth: enabled, key from {entropy:?}");
+    true
+}
+
+/// Returns `true` if pointer authentication is enabled.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Starts signing return addresses at EL3 by setting SCTLR_EL3.EnIA.
+///
+/// This function is naked so that it doesn't authenticate its own return address, which it
+/// didn't sign.
+///
+/// # Safety
+///
+/// The caller must never return, nor any of its own callers: their return addresses were not
+/// signed. [init] must have returned `true`.
+#[unsafe(naked)]
+pub unsafe extern "C" fn enable_el3() {
+    naked_asm!(
+        "mrs x9, SCTLR_EL3",
+        "orr x9, x9, #{enia}",
+        "msr SCTLR_EL3, x9",
+        "isb",
+        "ret",
+        enia = const SCTLR_EL3_ENIA,
+    );
+}
+
+// ———————————————————————————————— World Keys ———————————————————————————————— //
+
+/// The pointer authentication keys of a world.
+#[cfg(feature = "pauth")]
+#[derive(Clone, Copy)]
+pub struct Keys {
+    /// The instruction key A, which lives in the exception frame while EL3 runs.
+    pub apia: [u64; 2],
+    pub apib: [u64; 2],
+    pub apda: [u64; 2],
+    pub apdb: [u64; 2],
+    pub apga: [u64; 2],
+}
+
+#[cfg(feature = "pauth")]
+impl Keys {
+    pub const fn new() -> Self {
+        Self {
+            apia: [0; 2],
+            apib: [0; 2],
+            apda: [0; 2],
+            apdb: [0; 2],
+            apga: [0; 2],
+        }
+    }
+
+    /// Saves the keys of the lower ELs, if pointer authentication is enabled.
+    pub fn save(&mut self, frame: &ExceptionFrame) {
+        if !is_enabled() {
+            return;
+        }
+        self.apia = frame.apia;
+        unsafe {
+            asm!(
+                "mrs {0}, S3_0_C2_C1_2",
+                "mrs {1}, S3_0_C2_C1_3",
+                "mrs {2}, S3_0_C2_C2_0",
+                "mrs {3}, S3_0_C2_C2_1",
+                "mrs {4}, S3_0_C2_C2_2",
+                "mrs {5}, S3_0_C2_C2_3",
+                "mrs {6}, S3_0_C2_C3_0",
+                "mrs {7}, S3_0_C2_C3_1",
+                out(reg) self.apib[0],
+                out(reg) self.apib[1],
+                out(reg) self.apda[0],
+                out(reg) self.apda[1],
+                out(reg) self.apdb[0],
+                out(reg) self.apdb[1],
+                out(reg) self.apga[0],
+                out(reg) self.apga[1],
+            );
+        }
+    }
+
+    /// Restores the keys of the lower ELs, if pointer authentication is enabled.
+    pub fn restore(&self, frame: &mut ExceptionFrame) {
+        if !is_enabled() {
+            return;
+        }
+        frame.apia = self.apia;
+        unsafe {
+            asm!(
+                "msr S3_0_C2_C1_2, {0}",
+                "msr S3_0_C2_C1_3, {1}",
+                "msr S3_0_C2_C2_0, {2}",
+                "msr S3_0_C2_C2_1, {3}",
+                "msr S3_0_C2_C2_2, {4}",
+                "msr S3_0_C2_C2_3, {5}",
+                "msr S3_0_C2_C3_0, {6}",
+                "msr S3_0_C2_C3_1, {7}",
+                in(reg) self.apib[0],
+                in(reg) self.apib[1],
+                in(reg) self.apda[0],
+                in(reg) self.apda[1],
+                in(reg) self.apdb[0],
+                in(reg) self.apdb[1],
+                in(reg) self.apga[0],
+                in(reg) self.apga[1],
+            );
+        }
+    }
+}
+
+// The key switches done by the exception vectors, through `bl` with the frame at the top of the
+// stack. They clobber x0 and x1.
+//
+// The key registers are accessed through their encodings, the assembler might not know them.
+global_asm!(
+r#"
+.pushsection .text.l4sm_pauth, "ax"
+.global l4sm_pauth_entry
+l4sm_pauth_entry:
+    adrp x0, {enabled}
+    ldrb w0, [x0, :lo12:{enabled}]
+    cbz w0, 1f
+    mrs x0, S3_0_C2_C1_0
+    mrs x1, S3_0_C2_C1_1
+    stp x0, x1, [sp, #{apia}]
+    adrp x0, {key}
+    add x0, x0, :lo12:{key}
+    ldp x0, x1, [x0]
+    msr S3_0_C2_C1_0, x0
+    msr S3_0_C2_C1_1, x1
+    isb
+1:
+    ret
+
+.global l4sm_pauth_exit
+l4sm_pauth_exit:
+    adrp x0, {enabled}
+    ldrb w0, [x0, :lo12:{enabled}]
+    cbz w0, 1f
+    ldp x0, x1, [sp, #{apia}]
+    msr S3_0_C2_C1_0, x0
+    msr S3_0_C2_C1_1, x1
+1:
+    ret
+.popsection
+"#,
+    enabled = sym ENABLED,
+    key = sym EL3_KEY,
+    apia = const offset_of!(ExceptionFrame, apia),
+);
@@ -0,0 +1,66 @@
+//! Cycle counting with the Performance Monitors Extension.
+//!
+//! Only the cycle counter is used, and it is enabled for all ELs. Without a PMU, [cycles] returns
+//! `None` and users fall back to the generic counter.
+//!
+//! Reference: PMCR_EL0, PMCCFILTR_EL0, and MDCR_EL3.
+
+use crate::arch::feature;
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once the cycle counter is running.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Prohibits cycle counting in Secure state (SCCD).
+const MDCR_EL3_SCCD: u64 = 1 << 23;
+/// Prohibits cycle counting at EL3 (MCCD).
+const MDCR_EL3_MCCD: u64 = 1 << 34;
+
+/// Enables the counters (E).
+const PMCR_EL0_E: u64 = 1 << 0;
+/// Resets the cycle counter (C).
+const PMCR_EL0_C: u64 = 1 << 2;
+/// Makes the cycle counter overflow at 64 bits rather than 32 bits (LC).
+const PMCR_EL0_LC: u64 = 1 << 6;
+
+/// The cycle counter enable bit of `PMCNTENSET_EL0`.
+const PMCNTENSET_C: u64 = 1 << 31;
+
+/// Starts the cycle counter, if there is a PMU.
+pub fn init() {
+    if !feature::has_pmu() {
+        log::info!("PMU: none, timing with the generic counter");
+        return;
+    }
+
+    unsafe {
+        asm!(
+            // Allow counting cycles at EL3 and in Secure state
+            "mrs {tmp}, MDCR_EL3",
+            "bic {tmp}, {tmp}, {mdcr_clear}",
+            "msr MDCR_EL3, {tmp}",
+            // The filter counts at every EL when all its bits are cleared
+            "msr PMCCFILTR_EL0, xzr",
+            "msr PMCR_EL0, {pmcr}",
+            "msr PMCNTENSET_EL0, {cntenset}",
+            "isb",
+            tmp = out(reg) _,
+            mdcr_clear = in(reg) MDCR_EL3_SCCD | MDCR_EL3_MCCD,
+            pmcr = in(reg) PMCR_EL0_E | PMCR_EL0_C | PMCR_EL0_LC,
+            cntenset = in(reg) PMCNTENSET_C,
+        );
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    log::info!("PMU: cycle counter enabled");
+}
+
+/// Returns the number of cycles counted since [init], or `None` if there is no PMU.
+pub fn cycles() -> Option<u64> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let value: u64;
+    unsafe { asm!("isb", "mrs {}, PMCCNTR_EL0", out(reg) value) };
+    Some(value)
+}
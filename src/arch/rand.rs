@@ -0,0 +1,126 @@
+//! Random numbers.
+//!
+//! Random numbers come from the RNDR register when FEAT_RNG is implemented. Otherwise, they are
+//! derived from the system counter: such numbers are NOT suitable for cryptographic use, which is
+//! why every function reports the [EntropySource] it used.
+
+use crate::arch::{feature, timer};
+use crate::ktest::kernel_test;
+use crate::sync::atomics;
+use core::arch::asm;
+use core::sync::atomic::AtomicU64;
+
+/// Number of attempts at reading RNDR before giving up.
+const RNDR_RETRIES: usize = 16;
+
+/// Increment of the weak generator state (the golden ratio, as in SplitMix64).
+const WEAK_INCREMENT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// State of the weak generator, so that successive calls differ even if the counter does not.
+static WEAK_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// The quality of random numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntropySource {
+    /// A hardware random number generator.
+    Hardware,
+    /// Counter-based mixing, not suitable for cryptographic use.
+    Weak,
+}
+
+/// Reads a random number from the RNDR register.
+///
+/// Returns `None` if FEAT_RNG is not implemented, or if no entropy was available after a few
+/// retries.
+pub fn try_rndr() -> Option<u64> {
+    if !feature::has_rng() {
+        return None;
+    }
+
+    for _ in 0..RNDR_RETRIES {
+        let value: u64;
+        let valid: u64;
+        unsafe {
+            // RNDR, encoded explicitly so that the assembler doesn't require FEAT_RNG.
+            // NZCV is 0b0100 if no entropy was available, 0b0000 otherwise.
+            asm!(
+                "mrs {value}, S3_3_C2_C4_0",
+                "cset {valid}, ne",
+                value = out(reg) value,
+                valid = out(reg) valid,
+            );
+        }
+        if valid != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Returns a random number, and the source it was obtained from.
+pub fn random_u64() -> (u64, EntropySource) {
+    let mut bytes = [0; 8];
+    let source = fill_bytes(&mut bytes);
+    (u64::from_ne_bytes(bytes), source)
+}
+
+/// Fills `buf` with random bytes.
+///
+/// Returns [EntropySource::Weak] if any of the bytes did not come from the hardware.
+pub fn fill_bytes(buf: &mut [u8]) -> EntropySource {
+    let mut source = EntropySource::Hardware;
+    for chunk in buf.chunks_mut(8) {
+        let value = try_rndr().unwrap_or_else(|| {
+            source = EntropySource::Weak;
+            weak_u64()
+        });
+        chunk.copy_from_slice(&value.to_ne_bytes()[..chunk.len()]);
+    }
+    source
+}
+
+/// Returns a non-cryptographic random number derived from the system counter.
+fn weak_u64() -> u64 {
+    let state = atomics::fetch_add(&WEAK_STATE, WEAK_INCREMENT);
+    mix(state.wrapping_add(WEAK_INCREMENT) ^ timer::counter())
+}
+
+/// The SplitMix64 finalizer, a bijective 64-bit mix function.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+kernel_test! {
+    fn mix_is_splitmix64() {
+        // The first outputs of SplitMix64 seeded with 0
+        let expected = [0xE220_A839_7B1D_CDAF, 0x6E78_9E6A_A1B9_65F4, 0x06C4_5D18_8009_454F];
+        for (i, expected) in expected.into_iter().enumerate() {
+            let state = WEAK_INCREMENT.wrapping_mul(i as u64 + 1);
+            assert_eq!(mix(state), expected, "output {i}");
+            assert_eq!(mix(state), mix(state));
+        }
+        assert_eq!(mix(0), 0);
+    }
+}
+
+kernel_test! {
+    fn fill_bytes_chunks() {
+        const SENTINEL: u8 = 0xA5;
+        for len in [0, 7, 8, 9, 17] {
+            let mut buf = [SENTINEL; 24];
+            let source = fill_bytes(&mut buf[..len]);
+            assert!(buf[len..].iter().all(|&b| b == SENTINEL), "{len} bytes: overflowed");
+            // Nothing to fill, nothing weak
+            if len == 0 || !feature::has_rng() {
+                let expected = if len == 0 { EntropySource::Hardware } else { EntropySource::Weak };
+                assert_eq!(source, expected, "{len} bytes");
+            }
+            // The full chunks are filled, a random byte of the last one could match the sentinel
+            for chunk in buf[..len].chunks_exact(8) {
+                assert!(chunk.iter().any(|&b| b != SENTINEL), "{len} bytes: {buf:x?}");
+            }
+        }
+    }
+}
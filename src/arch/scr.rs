@@ -0,0 +1,186 @@
+//! Secure Configuration Register (SCR_EL3).
+//!
+//! All accesses to SCR_EL3 go through [ScrEl3], so that the configuration of the lower ELs stays
+//! consistent across the monitor.
+
+use crate::arch::context::World;
+use crate::ktest::kernel_test;
+use core::arch::asm;
+use core::fmt;
+
+const NS: u64 = 1 << 0;
+const IRQ: u64 = 1 << 1;
+const FIQ: u64 = 1 << 2;
+const EA: u64 = 1 << 3;
+const RES1: u64 = 0b11 << 4;
+const SMD: u64 = 1 << 7;
+const HCE: u64 = 1 << 8;
+const SIF: u64 = 1 << 9;
+const RW: u64 = 1 << 10;
+const ST: u64 = 1 << 11;
+const TWI: u64 = 1 << 12;
+const TWE: u64 = 1 << 13;
+const APK: u64 = 1 << 16;
+const API: u64 = 1 << 17;
+const EEL2: u64 = 1 << 18;
+const NSE: u64 = 1 << 62;
+
+/// Names of the decoded fields, used for display.
+const FIELDS: [(u64, &str); 15] = [
+    (NS, "NS"),
+    (IRQ, "IRQ"),
+    (FIQ, "FIQ"),
+    (EA, "EA"),
+    (SMD, "SMD"),
+    (HCE, "HCE"),
+    (SIF, "SIF"),
+    (RW, "RW"),
+    (ST, "ST"),
+    (TWI, "TWI"),
+    (TWE, "TWE"),
+    (APK, "APK"),
+    (API, "API"),
+    (EEL2, "EEL2"),
+    (NSE, "NSE"),
+];
+
+/// A value of SCR_EL3.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScrEl3(u64);
+
+impl ScrEl3 {
+    /// The baseline configuration: lower ELs run in AArch64 and in the secure state, secure
+    /// instruction fetches from non-secure memory are forbidden, and nothing is routed to EL3.
+    pub const BASELINE: Self = Self(RES1 | RW | SIF);
+
+    /// Reads the current value of SCR_EL3.
+    pub fn read() -> Self {
+        let value: u64;
+        unsafe { asm!("mrs {}, SCR_EL3", out(reg) value) };
+        Self(value)
+    }
+
+    /// Writes this value to SCR_EL3.
+    pub fn write(self) {
+        unsafe { asm!("msr SCR_EL3, {}", "isb", in(reg) self.0) };
+    }
+
+    /// Puts lower ELs in the security state of `world`, by programming the NS and NSE bits.
+    ///
+    /// The remaining combination (NSE without NS) selects the Root state, which is reserved for
+    /// EL3 and can not be expressed as a [World].
+    pub const fn world(self, world: World) -> Self {
+        let (nse, ns) = match world {
+            World::Secure => (false, false),
+            World::NonSecure => (false, true),
+            World::Realm => (true, true),
+        };
+        self.with(NSE, nse).with(NS, ns)
+    }
+
+    /// Routes external aborts and SErrors to EL3.
+    pub const fn ea(self, enable: bool) -> Self {
+        self.with(EA, enable)
+    }
+
+    /// Routes FIQs to EL3.
+    pub const fn fiq(self, enable: bool) -> Self {
+        self.with(FIQ, enable)
+    }
+
+    /// Enables the HVC instruction.
+    pub const fn hce(self, enable: bool) -> Self {
+        self.with(HCE, enable)
+    }
+
+    /// Enables secure EL2.
+    pub const fn eel2(self, enable: bool) -> Self {
+        self.with(EEL2, enable)
+    }
+
+    /// Gives secure EL1 access to the secure physical timer.
+    pub const fn st(self, enable: bool) -> Self {
+        self.with(ST, enable)
+    }
+
+    /// Sets or clears both APK and API, which give the lower ELs access to pointer
+    /// authentication.
+    pub const fn pauth(self, enable: bool) -> Self {
+        self.with(APK | API, enable)
+    }
+
+    const fn with(self, bit: u64, enable: bool) -> Self {
+        if enable {
+            Self(self.0 | bit)
+        } else {
+            Self(self.0 & !bit)
+        }
+    }
+}
+
+impl fmt::Display for ScrEl3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x} [", self.0)?;
+        let mut first = true;
+        for (bit, name) in FIELDS {
+            if self.0 & bit != 0 {
+                if !first {
+                    f.write_str(" ")?;
+                }
+                f.write_str(name)?;
+                first = false;
+            }
+        }
+        f.write_str("]")
+    }
+}
+
+kernel_test! {
+    fn bit_positions() {
+        // The architectural positions, from the Arm ARM
+        let bits = [
+            NS, IRQ, FIQ, EA, SMD, HCE, SIF, RW, ST, TWI, TWE, APK, API, EEL2, NSE,
+        ];
+        let positions = [0, 1, 2, 3, 7, 8, 9, 10, 11, 12, 13, 16, 17, 18, 62];
+        for (bit, position) in bits.into_iter().zip(positions) {
+            assert_eq!(bit, 1 << position);
+        }
+        assert_eq!(RES1, 0x30);
+
+        let baseline = ScrEl3::BASELINE;
+        assert_eq!(baseline.0, 0x630);
+        assert_eq!(baseline.world(World::Secure).0, 0x630);
+        assert_eq!(baseline.world(World::NonSecure).0, 0x631);
+        assert_eq!(baseline.world(World::Realm).0, 0x4000_0000_0000_0631);
+
+        assert_eq!(baseline.ea(true).0, 0x638);
+        assert_eq!(baseline.fiq(true).0, 0x634);
+        assert_eq!(baseline.hce(true).0, 0x730);
+        assert_eq!(baseline.st(true).0, 0xE30);
+        assert_eq!(baseline.pauth(true).0, 0x3_0630);
+        assert_eq!(baseline.eel2(true).0, 0x4_0630);
+        assert_eq!(baseline.fiq(true).fiq(false), baseline);
+    }
+}
+
+kernel_test! {
+    fn world_security_states() {
+        // (NSE, NS) of the Secure, Non-secure, and Realm states
+        let worlds = [
+            (World::Secure, (false, false)),
+            (World::NonSecure, (false, true)),
+            (World::Realm, (true, true)),
+        ];
+        // The Root state is reserved for EL3, no world may select it
+        let root = (true, false);
+        for (from, _) in worlds {
+            for (to, expected) in worlds {
+                // Switching from any world leaves no stale bit
+                let scr = ScrEl3::BASELINE.world(from).world(to);
+                let state = (scr.0 & NSE != 0, scr.0 & NS != 0);
+                assert_eq!(state, expected, "{from:?} to {to:?}");
+                assert_ne!(state, root);
+            }
+        }
+    }
+}
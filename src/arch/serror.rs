@@ -0,0 +1,264 @@
+//! SError (asynchronous external abort) routing, syndrome decoding, and containment.
+//!
+//! SErrors are routed to EL3, and stay masked until [unmask] is called: an SError arriving before
+//! the monitor is ready to handle it remains pending. The handler then decides from the syndrome
+//! what to do, see [action]: corrected errors are only logged, errors contained in a lower-EL
+//! world kill that world, and the others are reported before exiting with
+//! [EXIT_SERROR](crate::platform::EXIT_SERROR).
+
+use crate::arch::feature;
+use crate::arch::scr::ScrEl3;
+use crate::ktest::kernel_test;
+use crate::logger::emergency_log;
+use core::arch::asm;
+use core::fmt;
+
+/// ISR_EL1.A: an SError is pending.
+const ISR_A: u64 = 1 << 8;
+/// ERR<n>STATUS.V: the error record is valid.
+const ERR_STATUS_V: u64 = 1 << 30;
+/// ERR<n>STATUS.AV: the address in ERR<n>ADDR is valid.
+const ERR_STATUS_AV: u64 = 1 << 31;
+/// Maximum number of error records reported.
+const MAX_ERROR_RECORDS: u64 = 16;
+
+/// Routes SErrors to EL3. They stay masked at EL3 until [unmask] is called.
+///
+/// Must be called once the exception vectors are installed.
+pub fn init() {
+    ScrEl3::read().ea(true).write();
+}
+
+/// Unmasks SErrors at EL3, a pending SError is taken right away.
+pub fn unmask() {
+    unsafe { asm!("msr DAIFClr, #0b0100", "isb") };
+}
+
+/// Returns `true` if another SError is pending.
+pub fn pending() -> bool {
+    let isr: u64;
+    unsafe { asm!("mrs {}, ISR_EL1", out(reg) isr) };
+    isr & ISR_A != 0
+}
+
+/// What to do about an SError.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// The error was corrected, the interrupted code can resume.
+    Resume,
+    /// The error is contained in the interrupted lower-EL world, which must not resume.
+    Contain,
+    /// The state of the monitor can't be trusted anymore.
+    Panic,
+}
+
+/// Decides what to do about an SError from its syndrome, and where it was taken from.
+///
+/// Errors that are not contained (UC), or whose syndrome doesn't say, are always fatal. So are
+/// uncorrected errors interrupting the monitor itself, which has no context to give up.
+pub fn action(syndrome: Syndrome, from_lower_el: bool) -> Action {
+    let Syndrome::Architected { error_type, .. } = syndrome else {
+        return Action::Panic;
+    };
+    match error_type {
+        ErrorType::Corrected => Action::Resume,
+        ErrorType::Unrecoverable | ErrorType::Restartable | ErrorType::Recoverable
+            if from_lower_el =>
+        {
+            Action::Contain
+        }
+        _ => Action::Panic,
+    }
+}
+
+/// Logs the valid RAS error records, if the RAS extension is implemented.
+pub fn log_error_records() {
+    if !feature::has_ras() {
+        return;
+    }
+    let errors: u64;
+    // ERRIDR_EL1
+    unsafe { asm!("mrs {}, S3_0_C5_C3_0", out(reg) errors) };
+    for record in 0..(errors & 0xFFFF).min(MAX_ERROR_RECORDS) {
+        let (status, addr): (u64, u64);
+        unsafe {
+            // ERRSELR_EL1, then ERXSTATUS_EL1 and ERXADDR_EL1
+            asm!(
+                "msr S3_0_C5_C3_1, {record}",
+                "isb",
+                "mrs {status}, S3_0_C5_C4_2",
+                "mrs {addr}, S3_0_C5_C4_3",
+                record = in(reg) record,
+                status = out(reg) status,
+                addr = out(reg) addr,
+            )
+        };
+        if status & ERR_STATUS_V == 0 {
+            continue;
+        }
+        if status & ERR_STATUS_AV != 0 {
+            emergency_log(format_args!(
+                "  Error record {record}: status {status:#018x}, address {addr:#018x}"
+            ));
+        } else {
+            emergency_log(format_args!(
+                "  Error record {record}: status {status:#018x}"
+            ));
+        }
+    }
+}
+
+/// Triggers an external abort by writing to an address with nothing behind it.
+///
+/// On hardware with posted writes the abort usually arrives as an SError. QEMU reports bus errors
+/// synchronously, so there the abort shows up as a synchronous external abort instead.
+#[cfg(feature = "inject-serror")]
+pub fn inject_for_test() {
+    let addr = crate::platform::ABORT_TEST_ADDR;
+    log::warn!("Writing to {addr:#x} to trigger an external abort");
+    unsafe {
+        core::ptr::write_volatile(addr as *mut u32, 0);
+        // Make sure the abort is taken here, if it is asynchronous
+        asm!("dsb sy", "isb");
+    }
+}
+
+// ———————————————————————————————— Syndrome ———————————————————————————————— //
+
+/// The decoded ISS of an SError.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syndrome {
+    /// The syndrome is implementation defined (IDS is set).
+    ImplementationDefined(u32),
+    Architected {
+        /// The state of the PE after the error (AET), only meaningful with the RAS extension.
+        error_type: ErrorType,
+        /// The error was synchronized by an implicit error synchronization barrier (IESB).
+        iesb: bool,
+        /// External abort type (EA), implementation defined.
+        ea: bool,
+        /// The fault status code (DFSC).
+        status: u8,
+    },
+}
+
+/// The error type of an SError (AET).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorType {
+    /// Uncontainable (UC).
+    Uncontainable,
+    /// Unrecoverable (UEU).
+    Unrecoverable,
+    /// Restartable (UEO).
+    Restartable,
+    /// Recoverable (UER).
+    Recoverable,
+    /// Corrected (CE).
+    Corrected,
+    Reserved(u8),
+}
+
+impl Syndrome {
+    /// Decodes the ISS of an SError.
+    pub fn decode(iss: u32) -> Self {
+        if iss & (1 << 24) != 0 {
+            return Syndrome::ImplementationDefined(iss & 0x00FF_FFFF);
+        }
+
+        let error_type = match ((iss >> 10) & 0b111) as u8 {
+            0b000 => ErrorType::Uncontainable,
+            0b001 => ErrorType::Unrecoverable,
+            0b010 => ErrorType::Restartable,
+            0b011 => ErrorType::Recoverable,
+            0b110 => ErrorType::Corrected,
+            aet => ErrorType::Reserved(aet),
+        };
+        Syndrome::Architected {
+            error_type,
+            iesb: iss & (1 << 13) != 0,
+            ea: iss & (1 << 9) != 0,
+            status: (iss & 0x3F) as u8,
+        }
+    }
+}
+
+impl fmt::Display for Syndrome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Syndrome::ImplementationDefined(iss) => {
+                write!(f, "implementation defined syndrome {iss:#08x}")
+            }
+            Syndrome::Architected {
+                error_type,
+                iesb,
+                ea,
+                status,
+            } => {
+                match status {
+                    0b000000 => write!(f, "uncategorized")?,
+                    0b010001 => write!(f, "asynchronous SError interrupt, {error_type}")?,
+                    _ => write!(f, "fault status {status:#04x}")?,
+                }
+                if iesb {
+                    write!(f, " (IESB)")?;
+                }
+                if ea {
+                    write!(f, " (EA)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorType::Uncontainable => write!(f, "uncontainable"),
+            ErrorType::Unrecoverable => write!(f, "unrecoverable"),
+            ErrorType::Restartable => write!(f, "restartable"),
+            ErrorType::Recoverable => write!(f, "recoverable"),
+            ErrorType::Corrected => write!(f, "corrected"),
+            ErrorType::Reserved(aet) => write!(f, "error type {aet:#05b}"),
+        }
+    }
+}
+
+kernel_test! {
+    fn decode_architected_syndrome() {
+        // Asynchronous SError interrupt (DFSC 0b010001), recoverable (AET 0b011), with IESB
+        let syndrome = Syndrome::decode((0b011 << 10) | (1 << 13) | 0b010001);
+        assert_eq!(
+            syndrome,
+            Syndrome::Architected {
+                error_type: ErrorType::Recoverable,
+                iesb: true,
+                ea: false,
+                status: 0b010001,
+            }
+        );
+        assert_eq!(
+            Syndrome::decode((1 << 24) | 0x12_3456),
+            Syndrome::ImplementationDefined(0x12_3456)
+        );
+        assert!(matches!(
+            Syndrome::decode(0b100 << 10),
+            Syndrome::Architected {
+                error_type: ErrorType::Reserved(0b100),
+                ..
+            }
+        ));
+    }
+}
+
+kernel_test! {
+    fn containment_decision() {
+        let syndrome = |aet: u32| Syndrome::decode((aet << 10) | 0b010001);
+        assert_eq!(action(syndrome(0b110), false), Action::Resume);
+        assert_eq!(action(syndrome(0b011), true), Action::Contain);
+        assert_eq!(action(syndrome(0b001), true), Action::Contain);
+        assert_eq!(action(syndrome(0b011), false), Action::Panic);
+        assert_eq!(action(syndrome(0b000), true), Action::Panic);
+        assert_eq!(action(Syndrome::decode(1 << 24), true), Action::Panic);
+    }
+}
@@ -0,0 +1,275 @@
+//! Generic timer helpers.
+//!
+//! EL3 owns the secure physical timer (`CNTPS_*_EL1`), which it uses for its own deadlines. It
+//! also configures which of the lower ELs can access the counters and timers.
+
+use crate::arch::feature;
+use crate::arch::scr::ScrEl3;
+use crate::ktest::kernel_test;
+use core::arch::asm;
+use core::fmt;
+
+/// CNTHCTL_EL2.EL1PCTEN: EL1 can read the physical counter.
+const CNTHCTL_EL2_EL1PCTEN: u64 = 1 << 0;
+/// CNTHCTL_EL2.EL1PCEN: EL1 can access the physical timer.
+const CNTHCTL_EL2_EL1PCEN: u64 = 1 << 1;
+/// CNTKCTL_EL1.EL0PCTEN: EL0 can read the physical counter.
+const CNTKCTL_EL1_EL0PCTEN: u64 = 1 << 0;
+/// CNTKCTL_EL1.EL0VCTEN: EL0 can read the virtual counter.
+const CNTKCTL_EL1_EL0VCTEN: u64 = 1 << 1;
+/// CNTKCTL_EL1.EL0VTEN: EL0 can access the virtual timer.
+const CNTKCTL_EL1_EL0VTEN: u64 = 1 << 8;
+/// CNTKCTL_EL1.EL0PTEN: EL0 can access the physical timer.
+const CNTKCTL_EL1_EL0PTEN: u64 = 1 << 9;
+/// CNTKCTL_EL1.EVNTEN: the event stream is enabled.
+const CNTKCTL_EL1_EVNTEN: u64 = 1 << 2;
+/// CNTKCTL_EL1.EVNTDIR: the event triggers on a 1 to 0 transition of the counter bit.
+const CNTKCTL_EL1_EVNTDIR: u64 = 1 << 3;
+/// CNTKCTL_EL1.EVNTI: the counter bit that triggers the event stream.
+const CNTKCTL_EL1_EVNTI_SHIFT: u64 = 4;
+const CNTKCTL_EL1_EVNTI_MASK: u64 = 0xF << CNTKCTL_EL1_EVNTI_SHIFT;
+/// All the EL0 access controls of `CNTKCTL_EL1`.
+const CNTKCTL_EL1_EL0_ACCESS: u64 =
+    CNTKCTL_EL1_EL0PCTEN | CNTKCTL_EL1_EL0VCTEN | CNTKCTL_EL1_EL0VTEN | CNTKCTL_EL1_EL0PTEN;
+
+/// Returns the current value of the physical counter.
+pub fn counter() -> u64 {
+    let value: u64;
+    unsafe { asm!("isb", "mrs {}, CNTPCT_EL0", out(reg) value) };
+    value
+}
+
+/// Returns the frequency of the system counter, in Hz.
+pub fn frequency() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, CNTFRQ_EL0", out(reg) value) };
+    value
+}
+
+/// Converts a duration in milliseconds into counter ticks.
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    ms.saturating_mul(frequency()) / 1000
+}
+
+/// Converts counter ticks into a duration in milliseconds.
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks.saturating_mul(1000) / frequency().max(1)
+}
+
+/// Arms the secure physical timer to fire once the counter reaches `deadline`.
+pub fn set_secure_deadline(deadline: u64) {
+    const ENABLE: u64 = 1 << 0;
+
+    unsafe {
+        asm!(
+            "msr CNTPS_CVAL_EL1, {deadline}",
+            "msr CNTPS_CTL_EL1, {ctl}",
+            "isb",
+            deadline = in(reg) deadline,
+            ctl = in(reg) ENABLE,
+        );
+    }
+}
+
+/// Disables the secure physical timer.
+pub fn disable_secure_timer() {
+    unsafe { asm!("msr CNTPS_CTL_EL1, xzr", "isb") };
+}
+
+/// Traps EL0 accesses to the counters and timers to EL1.
+///
+/// Timer accesses of EL0 tasks are then mediated by the kernel at EL1, which can grant access by
+/// updating `CNTKCTL_EL1` itself.
+pub fn trap_el0_access() {
+    // The EL1 registers are shared by the worlds, the monitor saves and restores them on world
+    // switches. Called before entering a world at EL1, this sets up that world's value. The other
+    // fields, such as the event stream, are left to the kernel.
+    write_cntkctl(read_cntkctl() & !CNTKCTL_EL1_EL0_ACCESS);
+}
+
+/// Runs `f` with the event stream enabled, so that WFE wakes up at least every 10 µs or so.
+///
+/// The event stream is configured in `CNTKCTL_EL1`, which belongs to the EL1 state of the worlds:
+/// it is restored afterward.
+pub fn with_event_stream<R>(f: impl FnOnce() -> R) -> R {
+    let saved = read_cntkctl();
+    let evnti = event_stream_bit(frequency()) << CNTKCTL_EL1_EVNTI_SHIFT;
+    let cleared = saved & !(CNTKCTL_EL1_EVNTI_MASK | CNTKCTL_EL1_EVNTDIR);
+    write_cntkctl(cleared | evnti | CNTKCTL_EL1_EVNTEN);
+    let result = f();
+    write_cntkctl(saved);
+    result
+}
+
+/// Returns the counter bit that triggers an event about every 10 µs, for a counter running at
+/// `frequency` Hz.
+fn event_stream_bit(frequency: u64) -> u64 {
+    // The bit goes from 0 to 1 every 2^(bit + 1) ticks
+    let ticks = (frequency / 100_000).max(2);
+    (ticks.ilog2() as u64 - 1).min(15)
+}
+
+fn read_cntkctl() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, CNTKCTL_EL1", out(reg) value) };
+    value
+}
+
+fn write_cntkctl(value: u64) {
+    unsafe { asm!("msr CNTKCTL_EL1, {}", "isb", in(reg) value) };
+}
+
+// ———————————————————————————— Lower EL Access ————————————————————————————— //
+
+/// Which counters and timers the lower ELs can access.
+///
+/// The EL1 controls live in `CNTHCTL_EL2`: a denied access traps to EL2, and they have no effect
+/// without an EL2 in the security state of the world. An EL2 payload can change them.
+#[derive(Clone, Copy, Debug)]
+pub struct TimerAccessPolicy {
+    /// EL1 can read the physical counter (`CNTPCT_EL0`).
+    pub el1_physical_counter: bool,
+    /// EL1 can access the physical timer (`CNTP_*_EL0`).
+    pub el1_physical_timer: bool,
+    /// Secure EL1 can access the secure physical timer (`CNTPS_*_EL1`), otherwise its accesses
+    /// trap to EL3. EL3 uses that timer for its own deadlines.
+    pub secure_el1_timer: bool,
+}
+
+impl fmt::Display for TimerAccessPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = |allowed| if allowed { "allowed" } else { "trapped" };
+        write!(
+            f,
+            "EL1 physical counter {}, EL1 physical timer {}, secure timer {}",
+            access(self.el1_physical_counter),
+            access(self.el1_physical_timer),
+            access(self.secure_el1_timer)
+        )
+    }
+}
+
+/// Configures the accesses of the lower ELs to the counters and timers according to `policy`.
+///
+/// The virtual counter offset is also cleared, so that the virtual counter of EL1 matches the
+/// physical one.
+pub fn configure_lower_el_access(policy: TimerAccessPolicy) {
+    if feature::has_el2() {
+        let mut cnthctl = 0;
+        if policy.el1_physical_counter {
+            cnthctl |= CNTHCTL_EL2_EL1PCTEN;
+        }
+        if policy.el1_physical_timer {
+            cnthctl |= CNTHCTL_EL2_EL1PCEN;
+        }
+        unsafe {
+            asm!(
+                "msr CNTHCTL_EL2, {}",
+                "msr CNTVOFF_EL2, xzr",
+                "isb",
+                in(reg) cnthctl,
+            );
+        }
+    }
+    ScrEl3::read().st(policy.secure_el1_timer).write();
+    log::debug!("Timer access: {policy}");
+}
+
+kernel_test! {
+    fn counter_advances() {
+        let start = counter();
+        assert!(frequency() != 0, "counter frequency not set");
+        let advanced = (0..1_000_000).any(|_| counter() > start);
+        assert!(advanced, "counter stuck at {start}");
+    }
+}
+
+kernel_test! {
+    fn el0_access_is_trapped() {
+        let saved = read_cntkctl();
+        write_cntkctl(saved | CNTKCTL_EL1_EL0_ACCESS);
+        trap_el0_access();
+        let cntkctl = read_cntkctl();
+        write_cntkctl(saved);
+        assert_eq!(cntkctl & CNTKCTL_EL1_EL0_ACCESS, 0, "CNTKCTL_EL1 is {cntkctl:#x}");
+    }
+}
+
+kernel_test! {
+    fn event_stream_period() {
+        // 62.5 MHz: every 512 ticks, 8.2 µs
+        assert_eq!(event_stream_bit(62_500_000), 8);
+        // 1 GHz: every 8192 ticks, 8.2 µs
+        assert_eq!(event_stream_bit(1_000_000_000), 12);
+        // 24 MHz: every 128 ticks, 5.3 µs
+        assert_eq!(event_stream_bit(24_000_000), 6);
+        // Out of range
+        assert_eq!(event_stream_bit(0), 0);
+        assert_eq!(event_stream_bit(u64::MAX), 15);
+
+        let saved = read_cntkctl();
+        let enabled = with_event_stream(read_cntkctl);
+        assert_ne!(enabled & CNTKCTL_EL1_EVNTEN, 0);
+        assert_eq!(read_cntkctl(), saved, "CNTKCTL_EL1 not restored");
+    }
+}
+
+kernel_test! {
+    fn lower_el_access_follows_policy() {
+        let permissive = TimerAccessPolicy {
+            el1_physical_counter: true,
+            el1_physical_timer: true,
+            secure_el1_timer: true,
+        };
+        let trapping = TimerAccessPolicy {
+            el1_physical_counter: false,
+            el1_physical_timer: false,
+            secure_el1_timer: false,
+        };
+        let read_el2 = || {
+            let (cnthctl, cntvoff): (u64, u64);
+            unsafe {
+                asm!(
+                    "mrs {}, CNTHCTL_EL2",
+                    "mrs {}, CNTVOFF_EL2",
+                    out(reg) cnthctl,
+                    out(reg) cntvoff,
+                )
+            };
+            (cnthctl, cntvoff)
+        };
+
+        let scr = ScrEl3::read();
+        let saved = feature::has_el2().then(read_el2);
+        for policy in [permissive, trapping] {
+            configure_lower_el_access(policy);
+            assert_eq!(ScrEl3::read(), scr.st(policy.secure_el1_timer), "with {policy}");
+            if feature::has_el2() {
+                let (cnthctl, cntvoff) = read_el2();
+                let mut expected = 0;
+                if policy.el1_physical_counter {
+                    expected |= CNTHCTL_EL2_EL1PCTEN;
+                }
+                if policy.el1_physical_timer {
+                    expected |= CNTHCTL_EL2_EL1PCEN;
+                }
+                let el1_controls = CNTHCTL_EL2_EL1PCTEN | CNTHCTL_EL2_EL1PCEN;
+                assert_eq!(cnthctl & el1_controls, expected, "with {policy}");
+                assert_eq!(cntvoff, 0);
+            }
+        }
+
+        scr.write();
+        if let Some((cnthctl, cntvoff)) = saved {
+            unsafe {
+                asm!(
+                    "msr CNTHCTL_EL2, {}",
+                    "msr CNTVOFF_EL2, {}",
+                    "isb",
+                    in(reg) cnthctl,
+                    in(reg) cntvoff,
+                )
+            };
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! TLB maintenance for the EL3 translation regime.
+//!
+//! Invalidations are broadcast to the inner shareable domain, and complete before returning.
+
+use core::arch::asm;
+
+/// Invalidates all EL3 TLB entries.
+pub fn invalidate_all_el3() {
+    unsafe { asm!("dsb ishst", "tlbi alle3is", "dsb ish", "isb") };
+}
+
+/// Invalidates the EL3 TLB entries for the page containing `va`.
+///
+/// Descriptor updates must be visible before calling this function, which the leading barrier
+/// takes care of for stores from the calling core.
+pub fn invalidate_va_el3(va: usize) {
+    unsafe {
+        asm!(
+            "dsb ishst",
+            "tlbi vae3is, {page}",
+            "dsb ish",
+            "isb",
+            page = in(reg) va >> 12,
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Stack walking over the AArch64 frame records.
+//!
+//! The monitor is built with frame pointers forced on (see the justfile): each function saves a
+//! frame record, the caller's frame pointer followed by the return address, and points x29 to it.
+//! The records form a chain up to the boot stub, which clears x29 before entering Rust.
+//!
+//! The walker runs on crashes, where the stack may well be corrupted. It only follows records that
+//! lie within the boot stack and move up the stack, and stops after [MAX_DEPTH] frames. The return
+//! addresses are printed raw, symbolization happens offline.
+
+use crate::ktest::kernel_test;
+use crate::memory_layout;
+use core::arch::asm;
+use core::ptr;
+
+/// Maximum number of frames walked.
+const MAX_DEPTH: usize = 32;
+
+/// Size of a frame record: the saved frame pointer and return address.
+const RECORD_SIZE: usize = 16;
+
+/// Calls `f` with the return address of each frame of the chain starting at the frame record `fp`,
+/// innermost first.
+pub fn walk(fp: usize, mut f: impl FnMut(usize)) {
+    let stack = memory_layout::stack();
+    let mut fp = fp;
+    for _ in 0..MAX_DEPTH {
+        let in_stack = fp >= stack.start && fp.saturating_add(RECORD_SIZE) <= stack.end;
+        if fp == 0 || !fp.is_multiple_of(8) || !in_stack {
+            return;
+        }
+        // SAFETY: the record is within the stack, which is always mapped.
+        let (next, lr) = unsafe {
+            (
+                ptr::read_volatile(fp as *const usize),
+                ptr::read_volatile((fp + 8) as *const usize),
+            )
+        };
+        let lr = strip_pac(lr);
+        if lr == 0 {
+            return;
+        }
+        f(lr);
+        // The callers' records are higher up the stack, anything else is a loop or corruption.
+        if next <= fp {
+            return;
+        }
+        fp = next;
+    }
+}
+
+/// Returns the frame pointer of the caller.
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let fp: usize;
+    unsafe { asm!("mov {}, x29", out(reg) fp, options(nomem, nostack, preserves_flags)) };
+    fp
+}
+
+/// Removes the pointer authentication code from a return address, if it was signed.
+///
+/// XPACLRI is in the hint space, and is a NOP without FEAT_PAuth.
+fn strip_pac(addr: usize) -> usize {
+    let stripped: usize;
+    unsafe {
+        asm!(
+            "hint #7",
+            inout("x30") addr => stripped,
+            options(pure, nomem, nostack, preserves_flags)
+        )
+    };
+    stripped
+}
+
+kernel_test! {
+    fn walks_synthetic_chain() {
+        // Three records on the stack, the outermost ending the chain
+        let mut records = [0usize; 6];
+        let base = records.as_mut_ptr() as usize;
+        records.copy_from_slice(&[base + 16, 0x1000, base + 32, 0x2000, 0, 0x3000]);
+        let mut found = [0; 4];
+        let mut count = 0;
+        walk(base, |lr| {
+            found[count] = lr;
+            count += 1;
+        });
+        assert_eq!(&found[..count], &[0x1000, 0x2000, 0x3000]);
+
+        // A record pointing down the stack ends the walk
+        records[2] = base;
+        count = 0;
+        walk(base, |_| count += 1);
+        assert_eq!(count, 2);
+
+        // Records outside of the stack are not read
+        count = 0;
+        walk(memory_layout::text().start, |_| count += 1);
+        walk(0, |_| count += 1);
+        assert_eq!(count, 0);
+    }
+}
+
+kernel_test! {
+    fn walks_nested_calls() {
+        // Each function returns the number of frames in the image seen from the innermost one.
+        // Using the result after the call keeps it from becoming a tail call, which leaves no
+        // record.
+        #[inline(never)]
+        fn first() -> usize {
+            core::hint::black_box(second())
+        }
+        #[inline(never)]
+        fn second() -> usize {
+            core::hint::black_box(third())
+        }
+        #[inline(never)]
+        fn third() -> usize {
+            let text = memory_layout::text();
+            let mut count = 0;
+            walk(frame_pointer(), |lr| {
+                if text.contains(&lr) {
+                    count += 1;
+                }
+            });
+            core::hint::black_box(count)
+        }
+        // The returns into second, first, and this test
+        let count = first();
+        assert!(count >= 3, "only {count} frames walked");
+    }
+}
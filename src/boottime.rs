@@ -0,0 +1,92 @@
+//! Timing of the boot phases.
+//!
+//! [mark] records the generic counter at the end of a boot phase, and [report] prints the duration
+//! of each phase once the boot is done. Marks can be placed anywhere in the boot path, but only on
+//! the boot core.
+
+use crate::arch::timer;
+use crate::ktest::kernel_test;
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use core::{slice, str};
+
+/// Maximum number of marks.
+const MAX_MARKS: usize = 32;
+
+/// Number of marks recorded.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Counter value of each mark.
+static TICKS: [AtomicU64; MAX_MARKS] = [const { AtomicU64::new(0) }; MAX_MARKS];
+/// Name of each mark, as a pointer and a length.
+static NAMES: [(AtomicPtr<u8>, AtomicUsize); MAX_MARKS] =
+    [const { (AtomicPtr::new(core::ptr::null_mut()), AtomicUsize::new(0)) }; MAX_MARKS];
+
+/// Records the end of the boot phase `name`. Marks past the capacity of the table are dropped.
+pub fn mark(name: &'static str) {
+    let ticks = timer::counter();
+    let index = COUNT.load(Ordering::Relaxed);
+    if index >= MAX_MARKS {
+        return;
+    }
+    TICKS[index].store(ticks, Ordering::Relaxed);
+    NAMES[index]
+        .0
+        .store(name.as_ptr().cast_mut(), Ordering::Relaxed);
+    NAMES[index].1.store(name.len(), Ordering::Relaxed);
+    COUNT.store(index + 1, Ordering::Relaxed);
+}
+
+/// Returns the name and counter value of each mark.
+fn marks() -> impl Iterator<Item = (&'static str, u64)> {
+    (0..COUNT.load(Ordering::Relaxed)).map(|index| {
+        let ptr = NAMES[index].0.load(Ordering::Relaxed);
+        let len = NAMES[index].1.load(Ordering::Relaxed);
+        // SAFETY: the pointer and length come from a `&'static str` given to `mark`.
+        let name = unsafe { str::from_utf8_unchecked(slice::from_raw_parts(ptr, len)) };
+        (name, TICKS[index].load(Ordering::Relaxed))
+    })
+}
+
+/// Returns the number of marks recorded.
+pub fn count() -> usize {
+    COUNT.load(Ordering::Relaxed)
+}
+
+/// Returns the time between the first and the last mark, in microseconds.
+pub fn total_us() -> u64 {
+    let mut marks = marks();
+    let Some((_, first)) = marks.next() else {
+        return 0;
+    };
+    let last = marks.last().map_or(first, |(_, ticks)| ticks);
+    ticks_to_us(last - first)
+}
+
+/// Logs the duration of each phase, the time from the previous mark.
+pub fn report() {
+    let Some((_, start)) = marks().next() else {
+        return;
+    };
+    log::info!("Boot time: {} phases, {} us", count() - 1, total_us());
+    log::info!("  {:<20} {:>10} {:>10}", "phase", "delta us", "total us");
+    let mut previous = start;
+    for (name, ticks) in marks().skip(1) {
+        log::info!(
+            "  {name:<20} {:>10} {:>10}",
+            ticks_to_us(ticks - previous),
+            ticks_to_us(ticks - start)
+        );
+        previous = ticks;
+    }
+}
+
+fn ticks_to_us(ticks: u64) -> u64 {
+    ticks.saturating_mul(1_000_000) / timer::frequency().max(1)
+}
+
+kernel_test! {
+    fn marks_are_ordered() {
+        assert_eq!(marks().count(), count());
+        let ticks = || marks().map(|(_, ticks)| ticks);
+        assert!(ticks().zip(ticks().skip(1)).all(|(a, b)| a <= b));
+    }
+}
@@ -0,0 +1,299 @@
+//! Crash reports.
+//!
+//! [dump] prints everything we know about the machine when the monitor gives up, on an unhandled
+//! exception or a panic. The crash may have interrupted the logger, so the report only goes
+//! through the emergency output, which formats on the stack without allocating. [halt] then ends
+//! the run, the same way for all fatal errors.
+
+use crate::arch::exception::ExceptionFrame;
+use crate::arch::mmu::{SctlrEl3, TcrEl3};
+use crate::arch::scr::ScrEl3;
+use crate::arch::{CoreId, esr, timer};
+use crate::ktest::kernel_test;
+use crate::logger::{self, emergency_log};
+use crate::rme::gpt;
+use crate::{STACK_SIZE, backtrace, memory_layout, platform, stack, watchdog};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::{fmt, ptr};
+
+/// Number of bytes dumped on each side of an address.
+const MEMORY_WINDOW: usize = 32;
+
+/// Prints a crash report: the exception and registers from `frame` (if the crash comes from an
+/// exception), the EL3 configuration, the stack usage, the backtrace, the memory around the
+/// faulting code and its stack, and the last lines logged.
+pub fn dump(frame: Option<&ExceptionFrame>, reason: fmt::Arguments) {
+    report(frame, reason, emergency_log);
+}
+
+/// Prints the crash report of [dump] with `print`, one line at a time.
+fn report(frame: Option<&ExceptionFrame>, reason: fmt::Arguments, print: fn(fmt::Arguments)) {
+    print(format_args!("======== Crash: {reason} ========"));
+    print(format_args!(
+        "  CPU {}, uptime {} ms",
+        CoreId::current(),
+        timer::ticks_to_ms(timer::counter())
+    ));
+
+    if let Some(frame) = frame {
+        section(print, "Exception");
+        let esr = esr::decode(frame.esr);
+        print(format_args!("  {esr}"));
+        if esr.far_valid() {
+            print(format_args!("  FAR: {:#018x}", frame.far));
+        }
+        if esr.is_granule_protection_fault() {
+            let (pa, space) = gpt::fault_address();
+            print(format_args!("  MFAR: {pa:#018x} ({space} PA space)"));
+            print(format_args!("  GPT: {}", gpt::lookup(pa)));
+        }
+
+        section(print, "Registers");
+        print_registers(frame, print);
+    }
+
+    section(print, "System registers");
+    print(format_args!("  SCR_EL3:   {}", ScrEl3::read()));
+    print(format_args!("  SCTLR_EL3: {}", SctlrEl3::read()));
+    print(format_args!("  TCR_EL3:   {}", TcrEl3::read()));
+
+    section(print, "Stack");
+    let guard = if stack::guard_intact() {
+        "intact"
+    } else {
+        "overwritten"
+    };
+    print(format_args!(
+        "  Usage: {}/{STACK_SIZE} bytes, guard {guard}",
+        stack::high_watermark()
+    ));
+
+    section(print, "Backtrace");
+    match frame {
+        Some(frame) if interrupted_el(frame) == 3 => {
+            print_return_address(print, 0, frame.elr as usize);
+            print_backtrace(print, 1, frame.x[29] as usize);
+        }
+        Some(_) => print(format_args!("  Exception from a lower EL, not walked")),
+        None => print_backtrace(print, 0, backtrace::frame_pointer()),
+    }
+
+    section(print, "Memory");
+    match frame {
+        Some(frame) if interrupted_el(frame) == 3 => {
+            dump_memory(print, "ELR", frame.elr as usize);
+            dump_memory(print, "SP", interrupted_sp(frame) as usize);
+        }
+        Some(_) => print(format_args!("  Exception from a lower EL, not dumped")),
+        None => dump_memory(print, "SP", current_sp()),
+    }
+
+    section(print, "Recent log");
+    let complete = logger::for_each_recent_line(|line| print(format_args!("  {line}")));
+    if !complete {
+        print(format_args!("  The log history is locked"));
+    }
+
+    print(format_args!("======== End of crash report ========"));
+}
+
+/// Ends the monitor after a fatal error, once it has been reported.
+///
+/// Test runs, and builds with the `panic-exit` feature, exit the emulator with `code` so that
+/// failures don't wait for a timeout. Other builds halt, and the watchdog resets the system if it
+/// is armed.
+pub fn halt(code: u64) -> ! {
+    if cfg!(any(feature = "panic-exit", feature = "run-tests")) {
+        platform::exit_failure_code(code);
+    }
+    emergency_log(format_args!("System halted"));
+    logger::flush_uart();
+    watchdog::wait_for_reset();
+    loop {
+        unsafe { asm!("msr DAIFSet, #0b1111", "wfi") };
+    }
+}
+
+/// Prints the crash report of a panic, with its message and location.
+pub fn dump_panic(info: &PanicInfo) {
+    let message = info.message();
+    match info.location() {
+        Some(location) => dump(None, format_args!("panic at {location}: {message}")),
+        None => dump(None, format_args!("panic: {message}")),
+    }
+}
+
+/// Prints the registers of an exception frame, one line at a time.
+pub fn print_registers(frame: &ExceptionFrame, print: fn(fmt::Arguments)) {
+    print(format_args!(
+        "  ELR: {:#018x}  SPSR: {:#018x}",
+        frame.elr, frame.spsr
+    ));
+    for (i, pair) in frame.x.chunks(2).enumerate() {
+        match pair {
+            [a, b] => print(format_args!(
+                "  x{:<2}: {a:#018x}  x{:<2}: {b:#018x}",
+                2 * i,
+                2 * i + 1
+            )),
+            [a] => print(format_args!("  x{:<2}: {a:#018x}", 2 * i)),
+            _ => unreachable!(),
+        }
+    }
+    print(format_args!(
+        "  SP_EL0: {:#018x}  SP_EL3: {:#018x}",
+        frame.sp_el0,
+        frame.sp_el3()
+    ));
+}
+
+fn section(print: fn(fmt::Arguments), name: &str) {
+    print(format_args!("---- {name} ----"));
+}
+
+/// Returns the EL the exception was taken from, from SPSR.M.
+fn interrupted_el(frame: &ExceptionFrame) -> u64 {
+    (frame.spsr >> 2) & 0b11
+}
+
+/// Returns the stack pointer of the interrupted code, for exceptions taken from EL3.
+fn interrupted_sp(frame: &ExceptionFrame) -> u64 {
+    // SPSR.M[0] selects SP_ELx over SP_EL0
+    if frame.spsr & 1 != 0 {
+        frame.sp_el3()
+    } else {
+        frame.sp_el0
+    }
+}
+
+/// Prints the return addresses of the frame chain starting at `fp`, numbered from `first`.
+fn print_backtrace(print: fn(fmt::Arguments), first: usize, fp: usize) {
+    let mut index = first;
+    backtrace::walk(fp, |addr| {
+        print_return_address(print, index, addr);
+        index += 1;
+    });
+}
+
+/// Prints a code address, with its offset from the start of the image for offline symbolization.
+fn print_return_address(print: fn(fmt::Arguments), index: usize, addr: usize) {
+    let image = memory_layout::image();
+    if image.contains(&addr) {
+        print(format_args!(
+            "  #{index:<2} {addr:#018x} (image + {:#x})",
+            addr - image.start
+        ));
+    } else {
+        print(format_args!(
+            "  #{index:<2} {addr:#018x} (outside of the image)"
+        ));
+    }
+}
+
+fn current_sp() -> usize {
+    let sp: usize;
+    unsafe { asm!("mov {}, sp", out(reg) sp) };
+    sp
+}
+
+/// Prints the memory around `addr`, if it is within the monitor image.
+///
+/// Other addresses might not be mapped, or belong to devices, reading them could fault again.
+fn dump_memory(print: fn(fmt::Arguments), name: &str, addr: usize) {
+    let image = memory_layout::image();
+    let start = (addr & !0xF).saturating_sub(MEMORY_WINDOW);
+    let end = (addr & !0xF).saturating_add(MEMORY_WINDOW);
+    if start < image.start || end > image.end {
+        print(format_args!(
+            "  {name} {addr:#x} is outside of the monitor image, not dumped"
+        ));
+        return;
+    }
+
+    print(format_args!("  Around {name} ({addr:#x}):"));
+    for line in (start..end).step_by(16) {
+        // SAFETY: the range is within the image, which is always mapped.
+        let (a, b) = unsafe {
+            (
+                ptr::read_volatile(line as *const u64),
+                ptr::read_volatile((line + 8) as *const u64),
+            )
+        };
+        print(format_args!("    {line:#018x}: {a:016x} {b:016x}"));
+    }
+}
+
+kernel_test! {
+    fn report_sections_in_order() {
+        use crate::ktest;
+        use core::fmt::Write;
+        use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+        /// The lines delimiting the report and its sections, in order.
+        const SECTIONS: [&str; 9] = [
+            "======== Crash: test fault ========",
+            "---- Exception ----",
+            "---- Registers ----",
+            "---- System registers ----",
+            "---- Stack ----",
+            "---- Backtrace ----",
+            "---- Memory ----",
+            "---- Recent log ----",
+            "======== End of crash report ========",
+        ];
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        static FAR_REPORTED: AtomicBool = AtomicBool::new(false);
+
+        /// The beginning of a line of the report.
+        struct Line {
+            buf: [u8; 64],
+            len: usize,
+        }
+
+        impl Write for Line {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let n = s.len().min(self.buf.len() - self.len);
+                self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+                self.len += n;
+                Ok(())
+            }
+        }
+
+        /// Checks the section lines against SECTIONS as they are printed.
+        fn check(args: fmt::Arguments) {
+            let mut line = Line { buf: [0; 64], len: 0 };
+            let _ = line.write_fmt(args);
+            let line = core::str::from_utf8(&line.buf[..line.len]).unwrap_or("");
+            if line.starts_with("----") || line.starts_with("========") {
+                let next = NEXT.fetch_add(1, Ordering::Relaxed);
+                assert_eq!(SECTIONS.get(next), Some(&line), "section {next} of the report");
+            } else if line.starts_with("  FAR: ") && NEXT.load(Ordering::Relaxed) == 2 {
+                FAR_REPORTED.store(true, Ordering::Relaxed);
+            }
+        }
+
+        // Below the device window, nothing is mapped there
+        let addr = platform::DEVICE_BASE - 0x1000;
+        let fault = ktest::expect_fault(|| unsafe {
+            asm!("ldr {}, [{}]", out(reg) _, in(reg) addr);
+        });
+        let fault = fault.expect("the read didn't fault");
+        // The backtrace starts from the test
+        let mut x = [0; 31];
+        x[29] = backtrace::frame_pointer() as u64;
+        let frame = ExceptionFrame {
+            x,
+            sp_el0: 0,
+            elr: fault.elr,
+            // EL3h
+            spsr: 0b1101,
+            esr: fault.syndrome,
+            far: fault.far,
+            apia: [0; 2],
+        };
+        report(Some(&frame), format_args!("test fault"), check);
+        assert_eq!(NEXT.load(Ordering::Relaxed), SECTIONS.len(), "missing sections");
+        assert!(FAR_REPORTED.load(Ordering::Relaxed), "FAR not in the exception section");
+    }
+}
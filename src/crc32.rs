@@ -0,0 +1,101 @@
+//! CRC-32 checksums, as used by zlib and Ethernet (reflected polynomial 0xedb88320).
+//!
+//! The CRC32 instructions are used when implemented, with a table-based fallback otherwise.
+
+use crate::arch::feature;
+use crate::ktest::kernel_test;
+use core::arch::asm;
+
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+/// Remainders of each byte value, for the software implementation.
+static TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+}
+
+/// Returns the CRC-32 of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let crc = if feature::has_crc32() {
+        update_hardware(!0, data)
+    } else {
+        update_software(!0, data)
+    };
+    !crc
+}
+
+/// Updates `crc` with the table-based implementation.
+fn update_software(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Updates `crc` with the CRC32 instructions, which must be implemented.
+fn update_hardware(mut crc: u32, data: &[u8]) -> u32 {
+    let mut words = data.chunks_exact(8);
+    for word in &mut words {
+        let word = u64::from_le_bytes(word.try_into().unwrap());
+        unsafe {
+            asm!(
+                ".arch_extension crc",
+                "crc32x {crc:w}, {crc:w}, {word}",
+                crc = inout(reg) crc,
+                word = in(reg) word,
+                options(pure, nomem, nostack),
+            )
+        };
+    }
+    for &byte in words.remainder() {
+        unsafe {
+            asm!(
+                ".arch_extension crc",
+                "crc32b {crc:w}, {crc:w}, {byte:w}",
+                crc = inout(reg) crc,
+                byte = in(reg) u32::from(byte),
+                options(pure, nomem, nostack),
+            )
+        };
+    }
+    crc
+}
+
+kernel_test! {
+    fn software_matches_check_value() {
+        // The standard check value, the CRC of the ASCII digits
+        assert_eq!(!update_software(!0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(!update_software(!0, b""), 0);
+    }
+}
+
+kernel_test! {
+    fn hardware_matches_software() {
+        if !feature::has_crc32() {
+            return;
+        }
+        let data: [u8; 61] = core::array::from_fn(|i| (i * 37 + 11) as u8);
+        for len in [0, 1, 7, 8, 9, 61] {
+            assert_eq!(
+                update_hardware(!0, &data[..len]),
+                update_software(!0, &data[..len])
+            );
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Helpers for code handling secrets.
+//!
+//! These helpers avoid data-dependent branches and memory accesses, and rely on
+//! [DIT](crate::arch::dit) for the timing of the instructions themselves.
+
+use crate::ktest::kernel_test;
+use core::hint::black_box;
+
+/// Compares two byte slices in constant time.
+///
+/// The time only depends on the lengths of the slices, not on their content. Slices of different
+/// lengths are never equal.
+#[inline(never)]
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a
+        .iter()
+        .zip(b)
+        .fold(0u8, |diff, (x, y)| black_box(diff | (x ^ y)));
+    diff == 0
+}
+
+kernel_test! {
+    fn ct_eq_compares() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(b"secret", b"secret"));
+        assert!(!ct_eq(b"secret", b"secreT"));
+        assert!(!ct_eq(b"Secret", b"secret"));
+        assert!(!ct_eq(b"secret", b"secret\0"));
+        // Differences in single bits, anywhere in the slices
+        let a = [0x5au8; 64];
+        for i in 0..a.len() * 8 {
+            let mut b = a;
+            b[i / 8] ^= 1 << (i % 8);
+            assert!(!ct_eq(&a, &b));
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Self-hosted debugging with BRK instructions.
+//!
+//! [breakpoint!] stops the monitor with a report of the registers and the stack usage, while
+//! [checkpoint!] reports them and keeps running. Both are handled by the synchronous exception
+//! handler, which tells them apart by the BRK immediate.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// BRK immediate of [breakpoint!].
+pub const BREAKPOINT_IMM: u16 = 0xB000;
+/// BRK immediate of [checkpoint!], the checkpoint ID is in the low bits.
+pub const CHECKPOINT_IMM: u16 = 0xC000;
+/// The largest checkpoint ID.
+pub const CHECKPOINT_ID_MAX: u16 = 0x0FFF;
+
+/// Raised each time a checkpoint is handled.
+pub static CHECKPOINT_REACHED: HandlerFlag = HandlerFlag::new();
+
+/// Stops the monitor with a register dump, then exits with a failure.
+macro_rules! breakpoint {
+    () => {
+        unsafe { core::arch::asm!("brk #{imm}", imm = const $crate::debug::BREAKPOINT_IMM) }
+    };
+}
+pub(crate) use breakpoint;
+
+/// Reports the registers and the stack usage, then keeps running.
+///
+/// The ID is reported along with the checkpoint, it must be a constant up to
+/// [CHECKPOINT_ID_MAX].
+macro_rules! checkpoint {
+    ($id:expr) => {{
+        const ID: u16 = $id;
+        const { assert!(ID <= $crate::debug::CHECKPOINT_ID_MAX, "checkpoint ID too large") };
+        unsafe { core::arch::asm!("brk #{imm}", imm = const $crate::debug::CHECKPOINT_IMM | ID) }
+    }};
+}
+pub(crate) use checkpoint;
+
+/// A flag raised by an exception handler, so that a self-test can check that the handler ran.
+pub struct HandlerFlag(AtomicBool);
+
+impl HandlerFlag {
+    pub const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    /// Raises the flag.
+    pub fn raise(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Lowers the flag, and returns `true` if it was raised.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// The meaning of a BRK immediate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Brk {
+    /// A [breakpoint!].
+    Breakpoint,
+    /// A [checkpoint!], with its ID.
+    Checkpoint(u16),
+    /// A BRK that doesn't come from this module, such as a compiler-generated trap.
+    Other(u16),
+}
+
+impl Brk {
+    /// Decodes a BRK immediate.
+    pub fn decode(imm: u16) -> Self {
+        match imm {
+            BREAKPOINT_IMM => Brk::Breakpoint,
+            _ if imm & !CHECKPOINT_ID_MAX == CHECKPOINT_IMM => {
+                Brk::Checkpoint(imm & CHECKPOINT_ID_MAX)
+            }
+            _ => Brk::Other(imm),
+        }
+    }
+}
+
+impl fmt::Display for Brk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Brk::Breakpoint => write!(f, "Breakpoint"),
+            Brk::Checkpoint(id) => write!(f, "Checkpoint {id}"),
+            Brk::Other(imm) => write!(f, "BRK #{imm:#x}"),
+        }
+    }
+}
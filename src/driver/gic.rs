@@ -0,0 +1,353 @@
+//! Minimal driver for the ARM Generic Interrupt Controller v3 (GICv3).
+//!
+//! The monitor only handles Secure Group 0 interrupts, which are signaled as FIQs and taken to
+//! EL3. The distributor and redistributors are accessed through MMIO, while the CPU interface is
+//! accessed through system registers.
+//!
+//! The other interrupts are configured for the lower ELs from a map provided by the platform, see
+//! [GicV3::configure].
+
+use crate::arch::CoreId;
+use crate::ktest::kernel_test;
+use core::arch::asm;
+use core::ptr;
+
+// Distributor registers
+const GICD_CTLR: usize = 0x0000;
+const GICD_CTLR_ENABLE_GRP0: u32 = 1 << 0;
+const GICD_CTLR_ENABLE_GRP1NS: u32 = 1 << 1;
+const GICD_CTLR_ENABLE_GRP1S: u32 = 1 << 2;
+const GICD_CTLR_ARE_S: u32 = 1 << 4;
+const GICD_CTLR_ARE_NS: u32 = 1 << 5;
+const GICD_CTLR_RWP: u32 = 1 << 31;
+const GICD_IGROUPR: usize = 0x0080;
+const GICD_ISENABLER: usize = 0x0100;
+const GICD_ICENABLER: usize = 0x0180;
+const GICD_IPRIORITYR: usize = 0x0400;
+const GICD_ICFGR: usize = 0x0C00;
+const GICD_IGRPMODR: usize = 0x0D00;
+const GICD_IROUTER: usize = 0x6000;
+
+// Redistributor registers (RD_base frame)
+const GICR_CTLR: usize = 0x0000;
+const GICR_CTLR_RWP: u32 = 1 << 3;
+const GICR_WAKER: usize = 0x0014;
+const GICR_WAKER_PROCESSOR_SLEEP: u32 = 1 << 1;
+const GICR_WAKER_CHILDREN_ASLEEP: u32 = 1 << 2;
+const GICR_TYPER: usize = 0x0008;
+const GICR_TYPER_LAST: u64 = 1 << 4;
+
+// Redistributor registers (SGI_base frame)
+const GICR_SGI_BASE: usize = 0x1_0000;
+const GICR_IGROUPR0: usize = GICR_SGI_BASE + 0x0080;
+const GICR_ISENABLER0: usize = GICR_SGI_BASE + 0x0100;
+const GICR_ICENABLER0: usize = GICR_SGI_BASE + 0x0180;
+const GICR_IPRIORITYR: usize = GICR_SGI_BASE + 0x0400;
+const GICR_ICFGR: usize = GICR_SGI_BASE + 0x0C00;
+const GICR_IGRPMODR0: usize = GICR_SGI_BASE + 0x0D00;
+
+/// Size of the register frames of a single redistributor.
+const GICR_STRIDE: usize = 0x2_0000;
+
+/// The INTID returned by an acknowledge when no interrupt is pending.
+pub const SPURIOUS_INTID: u32 = 1023;
+
+/// The first shared peripheral interrupt, lower INTIDs are SGIs and PPIs.
+const FIRST_SPI: u32 = 32;
+/// The first PPI, lower INTIDs are SGIs.
+const FIRST_PPI: u32 = 16;
+/// The last SPI, INTIDs from 1020 are special.
+const LAST_SPI: u32 = 1019;
+
+/// The interrupt group, which selects the world and exception an interrupt is signaled to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Group {
+    /// Secure Group 0, signaled as FIQs and taken to EL3.
+    G0,
+    /// Secure Group 1, for the secure world.
+    G1S,
+    /// Non-secure Group 1, for the normal world.
+    G1NS,
+}
+
+/// How an interrupt is triggered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Level,
+    Edge,
+}
+
+/// The configuration of an interrupt.
+#[derive(Clone, Copy, Debug)]
+pub struct IrqConfig {
+    pub priority: u8,
+    pub trigger: Trigger,
+    pub group: Group,
+    /// The core an SPI is routed to, the calling core if `None`. Must be `None` for SGIs and
+    /// PPIs, which are configured in the redistributor of the calling core.
+    pub target: Option<CoreId>,
+}
+
+/// A GICv3, accessed through memory-mapped I/O.
+pub struct GicV3 {
+    gicd_base: usize,
+    gicr_base: usize,
+}
+
+impl GicV3 {
+    /// Creates a new GICv3 driver for the given distributor and redistributors base addresses.
+    ///
+    /// # Safety
+    ///
+    /// `gicd_base` and `gicr_base` must be the base addresses of a valid GICv3 distributor and
+    /// redistributor region, and must remain mapped for the lifetime of the returned driver.
+    pub const unsafe fn new(gicd_base: usize, gicr_base: usize) -> Self {
+        Self {
+            gicd_base,
+            gicr_base,
+        }
+    }
+
+    /// Initializes the distributor, enabling affinity routing and all interrupt groups.
+    pub fn init(&self) {
+        // Affinity routing must not change while the groups are enabled.
+        self.write_gicd(GICD_CTLR, 0);
+        self.wait_for_rwp();
+        self.write_gicd(GICD_CTLR, GICD_CTLR_ARE_S | GICD_CTLR_ARE_NS);
+        self.wait_for_rwp();
+        self.write_gicd(
+            GICD_CTLR,
+            GICD_CTLR_ARE_S
+                | GICD_CTLR_ARE_NS
+                | GICD_CTLR_ENABLE_GRP0
+                | GICD_CTLR_ENABLE_GRP1NS
+                | GICD_CTLR_ENABLE_GRP1S,
+        );
+        self.wait_for_rwp();
+    }
+
+    /// Initializes the redistributor and CPU interface of the calling core.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no redistributor matches the affinity of the calling core.
+    pub fn init_cpu(&self) {
+        let rd = self.redistributor();
+
+        // Wake up the redistributor
+        let waker = self.read_gicr(rd + GICR_WAKER);
+        self.write_gicr(rd + GICR_WAKER, waker & !GICR_WAKER_PROCESSOR_SLEEP);
+        while self.read_gicr(rd + GICR_WAKER) & GICR_WAKER_CHILDREN_ASLEEP != 0 {
+            core::hint::spin_loop();
+        }
+
+        // Enable the system register interface, unmask all priorities, and enable Group 0.
+        unsafe {
+            asm!(
+                "msr ICC_SRE_EL3, {sre}",
+                "isb",
+                "msr ICC_PMR_EL1, {pmr}",
+                "msr ICC_IGRPEN0_EL1, {enable}",
+                "isb",
+                sre = in(reg) 0b1111u64, // SRE, DFB, DIB, and Enable
+                pmr = in(reg) 0xFFu64,
+                enable = in(reg) 1u64,
+            );
+        }
+    }
+
+    /// Configures and enables the interrupts of `map`.
+    ///
+    /// SGIs and PPIs are configured for the calling core only. Each interrupt is disabled while
+    /// it is reconfigured.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an INTID is not an SGI, PPI, or SPI, if an SGI is not edge-triggered, or if a
+    /// private interrupt is given a target.
+    pub fn configure(&self, map: &[(u32, IrqConfig)]) {
+        let rd = self.redistributor();
+        for &(intid, config) in map {
+            assert!(intid <= LAST_SPI, "not an SGI, PPI, or SPI: {intid}");
+            assert!(
+                intid >= FIRST_PPI || config.trigger == Trigger::Edge,
+                "SGIs are always edge-triggered: {intid}"
+            );
+            if intid < FIRST_SPI {
+                assert!(
+                    config.target.is_none(),
+                    "private interrupt {intid} has a target"
+                );
+                self.configure_private(rd, intid, config);
+            } else {
+                self.configure_shared(intid, config);
+            }
+        }
+    }
+
+    /// Configures an SGI or PPI in the redistributor at `rd`.
+    fn configure_private(&self, rd: usize, intid: u32, config: IrqConfig) {
+        let bit = 1 << intid;
+        self.write_gicr(rd + GICR_ICENABLER0, bit);
+        self.wait_for_gicr_rwp(rd);
+
+        let (group, modifier) = group_bits(config.group);
+        self.update_gicr(rd + GICR_IGROUPR0, bit, group);
+        self.update_gicr(rd + GICR_IGRPMODR0, bit, modifier);
+        // The configuration of SGIs is read-only
+        if intid >= FIRST_PPI {
+            let (offset, edge_bit) = icfgr_position(intid);
+            self.update_gicr(
+                rd + GICR_ICFGR + offset,
+                edge_bit,
+                config.trigger == Trigger::Edge,
+            );
+        }
+        unsafe {
+            let addr = self.gicr_base + rd + GICR_IPRIORITYR + intid as usize;
+            ptr::write_volatile(addr as *mut u8, config.priority);
+        }
+        self.write_gicr(rd + GICR_ISENABLER0, bit);
+    }
+
+    /// Configures an SPI in the distributor.
+    fn configure_shared(&self, intid: u32, config: IrqConfig) {
+        let word = (intid / 32) as usize * 4;
+        let bit = 1 << (intid % 32);
+        self.write_gicd(GICD_ICENABLER + word, bit);
+        self.wait_for_rwp();
+
+        let (group, modifier) = group_bits(config.group);
+        self.update_gicd(GICD_IGROUPR + word, bit, group);
+        self.update_gicd(GICD_IGRPMODR + word, bit, modifier);
+        let (offset, edge_bit) = icfgr_position(intid);
+        self.update_gicd(
+            GICD_ICFGR + offset,
+            edge_bit,
+            config.trigger == Trigger::Edge,
+        );
+        unsafe {
+            let addr = self.gicd_base + GICD_IPRIORITYR + intid as usize;
+            ptr::write_volatile(addr as *mut u8, config.priority);
+            // IROUTER holds Aff3 in bits [39:32], and Aff2.Aff1.Aff0 in bits [23:0]
+            let packed = config.target.unwrap_or_else(CoreId::current).packed() as u64;
+            let route = (packed & 0xFF_FFFF) | ((packed >> 24) << 32);
+            let addr = self.gicd_base + GICD_IROUTER + 8 * intid as usize;
+            ptr::write_volatile(addr as *mut u64, route);
+        }
+        self.write_gicd(GICD_ISENABLER + word, bit);
+    }
+
+    /// Returns the offset of the redistributor of the calling core.
+    fn redistributor(&self) -> usize {
+        let core = CoreId::current();
+        let affinity = core.packed() as u64;
+
+        let mut offset = 0;
+        loop {
+            let typer =
+                unsafe { ptr::read_volatile((self.gicr_base + offset + GICR_TYPER) as *const u64) };
+            if typer >> 32 == affinity {
+                return offset;
+            }
+            if typer & GICR_TYPER_LAST != 0 {
+                panic!("no GIC redistributor for CPU {core}");
+            }
+            offset += GICR_STRIDE;
+        }
+    }
+
+    fn wait_for_rwp(&self) {
+        while self.read_gicd(GICD_CTLR) & GICD_CTLR_RWP != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn wait_for_gicr_rwp(&self, rd: usize) {
+        while self.read_gicr(rd + GICR_CTLR) & GICR_CTLR_RWP != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Sets or clears the bits of `mask` in a distributor register.
+    fn update_gicd(&self, offset: usize, mask: u32, set: bool) {
+        let value = self.read_gicd(offset);
+        self.write_gicd(offset, if set { value | mask } else { value & !mask });
+    }
+
+    /// Sets or clears the bits of `mask` in a redistributor register.
+    fn update_gicr(&self, offset: usize, mask: u32, set: bool) {
+        let value = self.read_gicr(offset);
+        self.write_gicr(offset, if set { value | mask } else { value & !mask });
+    }
+
+    fn read_gicd(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.gicd_base + offset) as *const u32) }
+    }
+
+    fn write_gicd(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.gicd_base + offset) as *mut u32, value) }
+    }
+
+    fn read_gicr(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.gicr_base + offset) as *const u32) }
+    }
+
+    fn write_gicr(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.gicr_base + offset) as *mut u32, value) }
+    }
+}
+
+/// Returns the values of the IGROUPR and IGRPMODR bits of an interrupt in `group`.
+fn group_bits(group: Group) -> (bool, bool) {
+    match group {
+        Group::G0 => (false, false),
+        Group::G1S => (false, true),
+        Group::G1NS => (true, false),
+    }
+}
+
+/// Returns the offset of the ICFGR register holding the configuration of `intid`, and the mask of
+/// its edge-triggered bit.
+///
+/// ICFGR registers hold two bits per interrupt, 16 interrupts per register. The same offset is
+/// used for the distributor's ICFGR<n> of SPIs and for the redistributor's ICFGR0 (SGIs) and
+/// ICFGR1 (PPIs).
+fn icfgr_position(intid: u32) -> (usize, u32) {
+    let offset = (intid / 16) as usize * 4;
+    // Int_config[1] is the upper bit of each field
+    let edge_bit = 1 << (2 * (intid % 16) + 1);
+    (offset, edge_bit)
+}
+
+// ————————————————————————————— CPU Interface —————————————————————————————— //
+
+/// Acknowledges the highest priority pending Group 0 interrupt and returns its INTID.
+pub fn acknowledge_group0() -> u32 {
+    let intid: u64;
+    unsafe { asm!("mrs {}, ICC_IAR0_EL1", out(reg) intid) };
+    intid as u32
+}
+
+/// Signals the end of the handling of a Group 0 interrupt.
+pub fn end_of_interrupt_group0(intid: u32) {
+    unsafe { asm!("msr ICC_EOIR0_EL1, {}", in(reg) intid as u64) };
+}
+
+kernel_test! {
+    fn icfgr_positions() {
+        let cases = [
+            // First and last PPI, in the redistributor's ICFGR1
+            (16, 0x4, 1),
+            (31, 0x4, 31),
+            // First SPIs, in the distributor's ICFGR2
+            (32, 0x8, 1),
+            (47, 0x8, 31),
+            // Last SPI, in ICFGR63
+            (1019, 0xFC, 23),
+        ];
+        for (intid, offset, bit) in cases {
+            assert_eq!(icfgr_position(intid), (offset, 1 << bit), "INTID {intid}");
+        }
+    }
+}
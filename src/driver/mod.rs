@@ -1 +1,6 @@
+pub mod gic;
+#[cfg(feature = "ns16550")]
+pub mod ns16550;
+#[cfg(not(feature = "ns16550"))]
 pub mod pl011;
+pub mod serial;
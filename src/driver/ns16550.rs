@@ -0,0 +1,165 @@
+//! Minimal driver for NS16550-compatible UARTs.
+//!
+//! Registers are accessed with 8-bit accesses, spaced by `1 << reg_shift` bytes depending on how
+//! the UART is wired.
+
+use super::serial::{self, SerialPort, TxTimeout};
+use crate::ktest::kernel_test;
+use core::ptr;
+
+// Register indices, offsets 0 and 1 are the divisor latch when LCR.DLAB is set
+const RBR_THR_DLL: usize = 0;
+const IER_DLM: usize = 1;
+const FCR: usize = 2;
+const LCR: usize = 3;
+const LSR: usize = 5;
+
+const FCR_ENABLE_AND_RESET: u8 = 0b111;
+const LCR_8N1: u8 = 0b11;
+const LCR_DLAB: u8 = 1 << 7;
+const LSR_DR: u8 = 1 << 0;
+const LSR_THRE: u8 = 1 << 5;
+const LSR_TEMT: u8 = 1 << 6;
+
+/// An NS16550-compatible UART, accessed through memory-mapped I/O.
+pub struct Ns16550 {
+    base: usize,
+    reg_shift: u32,
+    /// The UART clock and baud rate, in Hz, to program on init, or `None` to keep the current
+    /// baud rate.
+    line: Option<(u32, u32)>,
+}
+
+impl Ns16550 {
+    /// Creates a new driver for the UART at the given MMIO base address.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be the base address of a valid 16550 UART whose registers are spaced by
+    /// `1 << reg_shift` bytes, and must remain mapped for the lifetime of the returned driver.
+    pub const unsafe fn new(base: usize, reg_shift: u32, line: Option<(u32, u32)>) -> Self {
+        Self {
+            base,
+            reg_shift,
+            line,
+        }
+    }
+
+    fn read(&self, reg: usize) -> u8 {
+        unsafe { ptr::read_volatile((self.base + (reg << self.reg_shift)) as *const u8) }
+    }
+
+    fn write(&self, reg: usize, value: u8) {
+        unsafe { ptr::write_volatile((self.base + (reg << self.reg_shift)) as *mut u8, value) }
+    }
+}
+
+/// Returns the divisor closest to `clock_hz / (16 * baud)`, the UART sampling each bit 16 times.
+///
+/// The divisor is clamped to what the divisor latch can hold.
+const fn divisor(clock_hz: u32, baud: u32) -> u16 {
+    let rate = 16 * baud as u64;
+    let divisor = (clock_hz as u64 + rate / 2) / rate;
+    if divisor == 0 {
+        1
+    } else if divisor > u16::MAX as u64 {
+        u16::MAX
+    } else {
+        divisor as u16
+    }
+}
+
+impl SerialPort for Ns16550 {
+    fn init(&mut self) {
+        if let Some((clock_hz, baud)) = self.line {
+            let divisor = divisor(clock_hz, baud);
+            self.write(LCR, LCR_DLAB);
+            self.write(RBR_THR_DLL, divisor as u8);
+            self.write(IER_DLM, (divisor >> 8) as u8);
+        }
+        // Also clears DLAB, which could have been left set by the previous stage and would
+        // redirect the data and interrupt enable registers to the divisor latch.
+        self.write(LCR, LCR_8N1);
+        self.write(IER_DLM, 0); // Polling only
+        self.write(FCR, FCR_ENABLE_AND_RESET);
+    }
+
+    fn putc(&self, c: u8) -> Result<(), TxTimeout> {
+        serial::wait_until(|| self.can_write())?;
+        self.write(RBR_THR_DLL, c);
+        Ok(())
+    }
+
+    fn getc(&self) -> Option<u8> {
+        if self.read(LSR) & LSR_DR == 0 {
+            return None;
+        }
+        Some(self.read(RBR_THR_DLL))
+    }
+
+    fn flush(&self) {
+        let _ = serial::wait_until(|| self.read(LSR) & LSR_TEMT != 0);
+    }
+
+    fn can_write(&self) -> bool {
+        self.read(LSR) & LSR_THRE != 0
+    }
+}
+
+kernel_test! {
+    fn divisor_matches_known_rates() {
+        assert_eq!(divisor(1_843_200, 115_200), 1);
+        assert_eq!(divisor(1_843_200, 9600), 12);
+        assert_eq!(divisor(1_843_200, 300), 384);
+        assert_eq!(divisor(3_686_400, 115_200), 2);
+        assert_eq!(divisor(24_000_000, 115_200), 13);
+        assert_eq!(divisor(48_000_000, 115_200), 26);
+        assert_eq!(divisor(48_000_000, 1_500_000), 2);
+        // Out of range for the divisor latch
+        assert_eq!(divisor(1_843_200, 1_500_000), 1);
+        assert_eq!(divisor(100_000_000, 50), u16::MAX);
+    }
+}
+
+kernel_test! {
+    fn registers_on_mock_region() {
+        // Plain memory, where a register keeps the last value written to it
+        #[repr(align(8))]
+        struct Region([u8; 32]);
+
+        let mut region = Region([0xff; 32]);
+        let base = region.0.as_mut_ptr() as usize;
+        let reg = |index: usize| unsafe { ptr::read_volatile((base + (index << 2)) as *const u8) };
+        let set = |index: usize, value| unsafe {
+            ptr::write_volatile((base + (index << 2)) as *mut u8, value)
+        };
+
+        // 24 MHz at 115200 baud, registers spaced by 4 bytes
+        let mut uart = unsafe { Ns16550::new(base, 2, Some((24_000_000, 115_200))) };
+        uart.init();
+        assert_eq!(reg(RBR_THR_DLL), 13, "divisor low byte");
+        assert_eq!(reg(LCR), LCR_8N1, "DLAB is cleared");
+        assert_eq!(reg(IER_DLM), 0, "interrupts are disabled");
+        assert_eq!(reg(FCR), FCR_ENABLE_AND_RESET);
+        // The bytes between the registers are not touched
+        assert_eq!(region.0[1..4], [0xff; 3]);
+
+        // Without a line configuration, the divisor latch is left alone
+        let mut uart = unsafe { Ns16550::new(base, 2, None) };
+        set(RBR_THR_DLL, 0xaa);
+        set(LCR, LCR_DLAB);
+        uart.init();
+        assert_eq!(reg(RBR_THR_DLL), 0xaa);
+        assert_eq!(reg(LCR), LCR_8N1, "DLAB left set by a previous stage is cleared");
+
+        set(LSR, 0);
+        assert!(!uart.can_write());
+        assert_eq!(uart.getc(), None);
+        set(LSR, LSR_THRE | LSR_DR);
+        set(RBR_THR_DLL, b'x');
+        assert!(uart.can_write());
+        assert_eq!(uart.getc(), Some(b'x'));
+        uart.putc(b'y').unwrap();
+        assert_eq!(reg(RBR_THR_DLL), b'y');
+    }
+}
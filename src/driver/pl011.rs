@@ -1,11 +1,18 @@
 //! Minimal driver for the ARM PL011 UART.
 
-use core::fmt;
+use super::serial::{self, SerialPort, TxTimeout};
+use crate::ktest::kernel_test;
 use core::ptr;
 
 const UARTDR: usize = 0x00;
 const UARTFR: usize = 0x18;
+const UARTCR: usize = 0x30;
+const UARTFR_BUSY: u32 = 1 << 3;
+const UARTFR_RXFE: u32 = 1 << 4;
 const UARTFR_TXFF: u32 = 1 << 5;
+const UARTCR_UARTEN: u32 = 1 << 0;
+const UARTCR_TXE: u32 = 1 << 8;
+const UARTCR_RXE: u32 = 1 << 9;
 
 /// A PL011 UART, accessed through memory-mapped I/O.
 pub struct Pl011 {
@@ -23,27 +30,63 @@ impl Pl011 {
         Self { base }
     }
 
-    /// Writes a single byte to the UART, blocking until the TX FIFO has space.
-    pub fn putc(&self, c: u8) {
-        while self.is_tx_busy() {
-            core::hint::spin_loop();
-        }
-        unsafe {
-            ptr::write_volatile((self.base + UARTDR) as *mut u32, c as u32);
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile((self.base + offset) as *mut u32, value) }
+    }
+}
+
+impl SerialPort for Pl011 {
+    fn init(&mut self) {
+        // The baud rate depends on the UART clock, which only the previous stage knows
+        let cr = self.read(UARTCR);
+        self.write(UARTCR, cr | UARTCR_UARTEN | UARTCR_TXE | UARTCR_RXE);
+    }
+
+    fn putc(&self, c: u8) -> Result<(), TxTimeout> {
+        serial::wait_until(|| self.can_write())?;
+        self.write(UARTDR, c as u32);
+        Ok(())
+    }
+
+    fn getc(&self) -> Option<u8> {
+        if self.read(UARTFR) & UARTFR_RXFE != 0 {
+            return None;
         }
+        Some(self.read(UARTDR) as u8)
+    }
+
+    fn flush(&self) {
+        let _ = serial::wait_until(|| self.read(UARTFR) & UARTFR_BUSY == 0);
     }
 
-    fn is_tx_busy(&self) -> bool {
-        let fr = unsafe { ptr::read_volatile((self.base + UARTFR) as *const u32) };
-        fr & UARTFR_TXFF != 0
+    fn can_write(&self) -> bool {
+        self.read(UARTFR) & UARTFR_TXFF == 0
     }
 }
 
-impl fmt::Write for Pl011 {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.bytes() {
-            self.putc(c);
+kernel_test! {
+    fn uart_loopback() {
+        const UARTCR_LBE: u32 = 1 << 7;
+
+        // SAFETY: the secure UART is a PL011 here. The logger is idle while the test runs, and
+        // the UART configuration is restored.
+        let uart = unsafe { Pl011::new(crate::platform::UART1_BASE) };
+        uart.flush();
+        let cr = uart.read(UARTCR);
+        uart.write(UARTCR, cr | UARTCR_LBE);
+        while uart.getc().is_some() {}
+
+        let sent = b"l4sm";
+        let mut received = [0; 4];
+        for (c, slot) in sent.iter().zip(&mut received) {
+            uart.putc(*c).expect("TX timeout");
+            *slot = (0..1_000_000).find_map(|_| uart.getc()).unwrap_or(0);
         }
-        Ok(())
+        uart.write(UARTCR, cr);
+        assert_eq!(&received, sent, "loopback mismatch");
     }
 }
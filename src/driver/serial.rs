@@ -0,0 +1,120 @@
+//! Common interface of the UART drivers.
+
+use crate::ktest::kernel_test;
+use core::fmt;
+
+/// Maximum number of polls of a busy UART before giving up.
+const POLL_LIMIT: usize = 1_000_000;
+
+/// The UART stayed busy for too long, it might be wedged or absent.
+#[derive(Clone, Copy, Debug)]
+pub struct TxTimeout;
+
+/// A UART used as a serial console.
+///
+/// Formatted output is written with `write!` and `writeln!`, which stop at the first byte that
+/// times out.
+pub trait SerialPort {
+    /// Enables the UART, keeping the line configuration of the previous boot stage when possible.
+    fn init(&mut self);
+
+    /// Writes a single byte, waiting until the TX FIFO has space.
+    ///
+    /// Gives up if the FIFO stays full for too long.
+    fn putc(&self, c: u8) -> Result<(), TxTimeout>;
+
+    /// Returns the next received byte, if any.
+    fn getc(&self) -> Option<u8>;
+
+    /// Waits until all the bytes written so far have been sent, or until it takes too long.
+    fn flush(&self);
+
+    /// Returns `true` if the TX FIFO has space for at least one byte.
+    fn can_write(&self) -> bool;
+
+    /// Writes `s`, stopping at the first byte that times out.
+    fn write_str(&self, s: &str) -> Result<(), TxTimeout> {
+        s.bytes().try_for_each(|c| self.putc(c))
+    }
+
+    /// Writes formatted output, for `write!` and `writeln!`.
+    fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result {
+        fmt::write(&mut Writer(self), args)
+    }
+}
+
+/// Adapts a [SerialPort] to [fmt::Write].
+struct Writer<'a, S: ?Sized>(&'a S);
+
+impl<S: SerialPort + ?Sized> fmt::Write for Writer<'_, S> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s).map_err(|_| fmt::Error)
+    }
+}
+
+/// Polls `done` until it returns `true`, giving up after [POLL_LIMIT] polls.
+pub(super) fn wait_until(done: impl Fn() -> bool) -> Result<(), TxTimeout> {
+    for _ in 0..POLL_LIMIT {
+        if done() {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(TxTimeout)
+}
+
+kernel_test! {
+    fn tx_gives_up_on_full_fifo() {
+        use core::cell::Cell;
+
+        /// A TX FIFO that frees up after a number of polls, and then accepts a number of bytes.
+        struct MockFifo {
+            busy_polls: Cell<usize>,
+            accepted: Cell<usize>,
+            capacity: usize,
+        }
+
+        impl SerialPort for MockFifo {
+            fn init(&mut self) {}
+
+            fn putc(&self, _: u8) -> Result<(), TxTimeout> {
+                wait_until(|| self.can_write())?;
+                self.accepted.set(self.accepted.get() + 1);
+                Ok(())
+            }
+
+            fn getc(&self) -> Option<u8> {
+                None
+            }
+
+            fn flush(&self) {}
+
+            fn can_write(&self) -> bool {
+                if self.busy_polls.get() > 0 {
+                    self.busy_polls.set(self.busy_polls.get() - 1);
+                    return false;
+                }
+                self.accepted.get() < self.capacity
+            }
+        }
+
+        let fifo = |busy_polls, capacity| MockFifo {
+            busy_polls: Cell::new(busy_polls),
+            accepted: Cell::new(0),
+            capacity,
+        };
+
+        // Busy for a while, then recovers
+        let slow = fifo(1000, usize::MAX);
+        assert!(slow.putc(b'a').is_ok());
+        // Busy for longer than the limit
+        let stuck = fifo(POLL_LIMIT, usize::MAX);
+        assert!(stuck.putc(b'a').is_err());
+        assert!(stuck.putc(b'a').is_ok(), "the UART recovered");
+        // Writes stop at the first timeout
+        let full = fifo(0, 2);
+        assert!(full.write_str("abcd").is_err());
+        assert_eq!(full.accepted.get(), 2);
+        assert!(write!(full, "{}", 42).is_err());
+    }
+}
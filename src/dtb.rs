@@ -0,0 +1,202 @@
+//! The device tree blob handed over by the previous boot stage.
+//!
+//! The monitor doesn't depend on the device tree: the platform module describes the hardware. The
+//! blob is only validated, so that its memory is kept out of the payloads' way, and used to check
+//! the platform's DRAM size.
+//!
+//! Reference: Devicetree Specification, chapter 5 (Flattened Devicetree Format).
+
+use crate::platform;
+use crate::sync::IrqSafeMutex;
+use core::fmt;
+use core::ops::Range;
+use core::slice;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+/// Oldest version whose layout we understand.
+const FDT_LAST_COMP_VERSION: u32 = 16;
+/// Size of the header, up to `size_dt_struct`.
+const HEADER_SIZE: usize = 40;
+
+// Structure block tokens
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// The validated blob, if any.
+static DTB: IrqSafeMutex<Option<Dtb>> = IrqSafeMutex::new(None);
+
+/// A reason to reject a device tree blob.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DtbError {
+    /// No address was handed over.
+    Null,
+    /// The blob is not 8-byte aligned.
+    Misaligned(usize),
+    /// The blob is not within the DRAM.
+    OutOfDram(usize),
+    /// The blob doesn't start with the FDT magic, with the value found instead.
+    BadMagic(u32),
+    /// The blob is not compatible with version 16 of the format.
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for DtbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DtbError::Null => write!(f, "no device tree"),
+            DtbError::Misaligned(addr) => write!(f, "device tree at {addr:#x} is misaligned"),
+            DtbError::OutOfDram(addr) => write!(f, "device tree at {addr:#x} is not in DRAM"),
+            DtbError::BadMagic(magic) => write!(f, "bad device tree magic {magic:#010x}"),
+            DtbError::UnsupportedVersion(version) => {
+                write!(f, "unsupported device tree version {version}")
+            }
+        }
+    }
+}
+
+/// A device tree blob in memory.
+#[derive(Clone, Copy, Debug)]
+pub struct Dtb {
+    addr: usize,
+    size: usize,
+}
+
+impl Dtb {
+    /// Validates the header of the blob at `addr`.
+    pub fn new(addr: usize) -> Result<Self, DtbError> {
+        if addr == 0 {
+            return Err(DtbError::Null);
+        }
+        if !addr.is_multiple_of(8) {
+            return Err(DtbError::Misaligned(addr));
+        }
+        if !in_dram(addr, HEADER_SIZE) {
+            return Err(DtbError::OutOfDram(addr));
+        }
+
+        // SAFETY: the header is within the DRAM, which is always mapped.
+        let header = unsafe { slice::from_raw_parts(addr as *const u8, HEADER_SIZE) };
+        let magic = read_be32(header, 0).unwrap_or(0);
+        if magic != FDT_MAGIC {
+            return Err(DtbError::BadMagic(magic));
+        }
+        let size = read_be32(header, 4).unwrap_or(0) as usize;
+        if size < HEADER_SIZE || !in_dram(addr, size) {
+            return Err(DtbError::OutOfDram(addr));
+        }
+        let version = read_be32(header, 20).unwrap_or(0);
+        let last_compatible = read_be32(header, 24).unwrap_or(u32::MAX);
+        if version < FDT_LAST_COMP_VERSION || last_compatible > FDT_LAST_COMP_VERSION {
+            return Err(DtbError::UnsupportedVersion(version));
+        }
+        Ok(Dtb { addr, size })
+    }
+
+    /// Returns the physical range of the blob.
+    pub fn region(&self) -> Range<usize> {
+        self.addr..self.addr + self.size
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: the blob was checked to be within the DRAM, which is always mapped.
+        unsafe { slice::from_raw_parts(self.addr as *const u8, self.size) }
+    }
+
+    /// Returns the first range of the first memory node, or `None` if there is none or the
+    /// structure block is malformed.
+    pub fn memory(&self) -> Option<Range<usize>> {
+        let blob = self.bytes();
+        let structure = read_be32(blob, 8)? as usize;
+        let strings = read_be32(blob, 12)? as usize;
+
+        let mut offset = structure;
+        let mut depth: u32 = 0;
+        let mut in_memory_node = false;
+        // Defaults of the specification, for when the root doesn't set them
+        let mut address_cells = 2;
+        let mut size_cells = 1;
+        loop {
+            let token = read_be32(blob, offset)?;
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = read_str(blob, offset)?;
+                    offset = (offset + name.len() + 1).next_multiple_of(4);
+                    depth += 1;
+                    in_memory_node =
+                        depth == 2 && (name == b"memory" || name.starts_with(b"memory@"));
+                }
+                FDT_END_NODE => {
+                    depth = depth.checked_sub(1)?;
+                    in_memory_node = false;
+                }
+                FDT_PROP => {
+                    let len = read_be32(blob, offset)? as usize;
+                    let name = read_str(blob, strings + read_be32(blob, offset + 4)? as usize)?;
+                    let value = blob.get(offset + 8..offset + 8 + len)?;
+                    offset = (offset + 8 + len).next_multiple_of(4);
+                    match (depth, name) {
+                        (1, b"#address-cells") => address_cells = read_be32(value, 0)?,
+                        (1, b"#size-cells") => size_cells = read_be32(value, 0)?,
+                        (2, b"reg") if in_memory_node => {
+                            let base = read_cells(value, 0, address_cells)?;
+                            let size = read_cells(value, address_cells as usize * 4, size_cells)?;
+                            return Some(base..base.checked_add(size)?);
+                        }
+                        _ => {}
+                    }
+                }
+                FDT_NOP => {}
+                // FDT_END, or a malformed token
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Validates and records the device tree blob at `addr`.
+///
+/// Must be called once the MMU is enabled: the blob is read through the DRAM mapping.
+pub fn init(addr: usize) -> Result<Dtb, DtbError> {
+    let dtb = Dtb::new(addr)?;
+    *DTB.lock() = Some(dtb);
+    Ok(dtb)
+}
+
+/// Returns the physical range of the recorded blob, which must not be overwritten.
+pub fn reserved_region() -> Option<Range<usize>> {
+    DTB.lock().as_ref().map(Dtb::region)
+}
+
+/// Returns `true` if `size` bytes from `addr` are within the DRAM.
+fn in_dram(addr: usize, size: usize) -> bool {
+    let dram_end = platform::DRAM_BASE + platform::DRAM_SIZE;
+    addr >= platform::DRAM_BASE && addr.checked_add(size).is_some_and(|end| end <= dram_end)
+}
+
+fn read_be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads a value of one or two cells.
+fn read_cells(bytes: &[u8], offset: usize, cells: u32) -> Option<usize> {
+    match cells {
+        1 => Some(read_be32(bytes, offset)? as usize),
+        2 => {
+            let hi = read_be32(bytes, offset)? as usize;
+            let lo = read_be32(bytes, offset + 4)? as usize;
+            Some((hi << 32) | lo)
+        }
+        _ => None,
+    }
+}
+
+/// Reads a NUL-terminated string, without its terminator.
+fn read_str(bytes: &[u8], offset: usize) -> Option<&[u8]> {
+    let bytes = bytes.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    Some(&bytes[..len])
+}
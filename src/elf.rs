@@ -0,0 +1,357 @@
+//! A minimal ELF64 loader for lower-EL images.
+//!
+//! Only statically linked AArch64 executables are supported: the `PT_LOAD` segments are copied to
+//! their physical addresses, which must lie within the region reserved for the image, and the
+//! rest of each segment (the BSS) is zeroed. Other program headers are ignored.
+//!
+//! Reference: System V ABI, Executable and Linking Format, and the ELF for the Arm 64-bit
+//! Architecture supplement.
+
+use crate::arch::cache;
+use crate::ktest::kernel_test;
+use core::fmt;
+use core::ops::Range;
+use core::ptr;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_AARCH64: u16 = 183;
+const PT_LOAD: u32 = 1;
+
+/// Size of the ELF64 file header.
+const EHDR_SIZE: usize = 64;
+/// Size of an ELF64 program header.
+const PHDR_SIZE: usize = 56;
+
+/// An error in an ELF image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    /// The image ends before a header or segment it describes.
+    Truncated,
+    /// The image doesn't start with the ELF magic.
+    BadMagic,
+    /// The image is not a little-endian ELF64 file.
+    UnsupportedFormat,
+    /// The image is not an AArch64 executable, with its type and machine.
+    NotAArch64Executable { kind: u16, machine: u16 },
+    /// A segment's file size is larger than its memory size.
+    BadSegmentSize { index: usize },
+    /// A segment's alignment is not a power of two, or its address doesn't follow it.
+    Misaligned { index: usize },
+    /// A segment doesn't fit in the region reserved for the image.
+    OutOfBounds { index: usize },
+    /// Two segments overlap in memory.
+    Overlap { first: usize, second: usize },
+    /// The entry point is not within a loadable segment.
+    EntryOutOfSegments { entry: usize },
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ElfError::Truncated => write!(f, "truncated image"),
+            ElfError::BadMagic => write!(f, "not an ELF image"),
+            ElfError::UnsupportedFormat => write!(f, "not a little-endian ELF64 image"),
+            ElfError::NotAArch64Executable { kind, machine } => {
+                write!(
+                    f,
+                    "not an AArch64 executable (type {kind}, machine {machine})"
+                )
+            }
+            ElfError::BadSegmentSize { index } => {
+                write!(f, "segment {index} is larger in the file than in memory")
+            }
+            ElfError::Misaligned { index } => write!(f, "segment {index} is misaligned"),
+            ElfError::OutOfBounds { index } => {
+                write!(f, "segment {index} is out of the reserved region")
+            }
+            ElfError::Overlap { first, second } => {
+                write!(f, "segments {first} and {second} overlap")
+            }
+            ElfError::EntryOutOfSegments { entry } => {
+                write!(f, "entry point {entry:#x} is not in a loadable segment")
+            }
+        }
+    }
+}
+
+/// A loadable segment.
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    /// Offset of the segment in the file.
+    offset: usize,
+    /// Physical address of the segment.
+    paddr: usize,
+    filesz: usize,
+    memsz: usize,
+}
+
+impl Segment {
+    /// Returns the physical range covered by the segment in memory.
+    fn memory(&self) -> Range<usize> {
+        self.paddr..self.paddr + self.memsz
+    }
+}
+
+/// A parsed ELF image.
+pub struct Elf<'a> {
+    image: &'a [u8],
+    entry: usize,
+    phoff: usize,
+    phnum: usize,
+}
+
+impl<'a> Elf<'a> {
+    /// Parses the file header of an ELF image.
+    pub fn parse(image: &'a [u8]) -> Result<Self, ElfError> {
+        if image.len() < EHDR_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if image[..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if image[4] != ELFCLASS64 || image[5] != ELFDATA2LSB {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        let kind = read_u16(image, 16)?;
+        let machine = read_u16(image, 18)?;
+        if kind != ET_EXEC || machine != EM_AARCH64 {
+            return Err(ElfError::NotAArch64Executable { kind, machine });
+        }
+        if read_u16(image, 54)? as usize != PHDR_SIZE {
+            return Err(ElfError::UnsupportedFormat);
+        }
+
+        let elf = Elf {
+            image,
+            entry: read_u64(image, 24)? as usize,
+            phoff: read_u64(image, 32)? as usize,
+            phnum: read_u16(image, 56)? as usize,
+        };
+        // Make sure all the program headers are there
+        let end = elf
+            .phnum
+            .checked_mul(PHDR_SIZE)
+            .and_then(|size| size.checked_add(elf.phoff));
+        if end.is_none_or(|end| end > image.len()) {
+            return Err(ElfError::Truncated);
+        }
+        Ok(elf)
+    }
+
+    /// Returns the loadable segment described by the given program header, if any.
+    fn segment(&self, index: usize) -> Result<Option<Segment>, ElfError> {
+        let phdr = self.phoff + index * PHDR_SIZE;
+        if read_u32(self.image, phdr)? != PT_LOAD {
+            return Ok(None);
+        }
+
+        let segment = Segment {
+            offset: read_u64(self.image, phdr + 8)? as usize,
+            paddr: read_u64(self.image, phdr + 24)? as usize,
+            filesz: read_u64(self.image, phdr + 32)? as usize,
+            memsz: read_u64(self.image, phdr + 40)? as usize,
+        };
+        let align = read_u64(self.image, phdr + 48)? as usize;
+
+        if segment.filesz > segment.memsz {
+            return Err(ElfError::BadSegmentSize { index });
+        }
+        if segment
+            .offset
+            .checked_add(segment.filesz)
+            .is_none_or(|end| end > self.image.len())
+        {
+            return Err(ElfError::Truncated);
+        }
+        // 0 and 1 both mean no alignment constraint
+        if align > 1
+            && (!align.is_power_of_two() || segment.paddr % align != segment.offset % align)
+        {
+            return Err(ElfError::Misaligned { index });
+        }
+        Ok(Some(segment))
+    }
+
+    /// Checks that the loadable segments fit in `region` without overlapping each other, and that
+    /// one of them holds the entry point.
+    fn check_segments(&self, region: &Range<usize>) -> Result<(), ElfError> {
+        let mut entry_loaded = false;
+        for index in 0..self.phnum {
+            let Some(segment) = self.segment(index)? else {
+                continue;
+            };
+            // The image starts with its MMU off, the entry point is a physical address
+            entry_loaded |= segment.memory().contains(&self.entry);
+            let fits = segment
+                .paddr
+                .checked_add(segment.memsz)
+                .is_some_and(|end| segment.paddr >= region.start && end <= region.end);
+            if !fits {
+                return Err(ElfError::OutOfBounds { index });
+            }
+
+            for first in 0..index {
+                let Some(other) = self.segment(first)? else {
+                    continue;
+                };
+                let (a, b) = (segment.memory(), other.memory());
+                if a.start < b.end && b.start < a.end {
+                    return Err(ElfError::Overlap {
+                        first,
+                        second: index,
+                    });
+                }
+            }
+        }
+        if !entry_loaded {
+            return Err(ElfError::EntryOutOfSegments { entry: self.entry });
+        }
+        Ok(())
+    }
+
+    /// Copies the loadable segments to their physical addresses, zeroing their BSS, and returns
+    /// the entry point.
+    ///
+    /// Nothing is written unless all the segments fit in `region`.
+    ///
+    /// # Safety
+    ///
+    /// `region` must be reserved for the image, and not be used by the monitor.
+    pub unsafe fn load(&self, region: Range<usize>) -> Result<usize, ElfError> {
+        self.check_segments(&region)?;
+
+        for index in 0..self.phnum {
+            let Some(segment) = self.segment(index)? else {
+                continue;
+            };
+            log::debug!(
+                "ELF segment {index}: {:#x}-{:#x} ({} bytes from the file)",
+                segment.paddr,
+                segment.paddr + segment.memsz,
+                segment.filesz
+            );
+
+            let dst = segment.paddr as *mut u8;
+            let data = &self.image[segment.offset..segment.offset + segment.filesz];
+            unsafe {
+                ptr::copy_nonoverlapping(data.as_ptr(), dst, segment.filesz);
+                ptr::write_bytes(dst.add(segment.filesz), 0, segment.memsz - segment.filesz);
+            }
+
+            // The image starts with its MMU off, its fetches bypass the caches.
+            cache::clean_dcache_range(segment.paddr, segment.memsz);
+        }
+        cache::invalidate_icache_all();
+
+        Ok(self.entry)
+    }
+}
+
+fn read_u16(image: &[u8], offset: usize) -> Result<u16, ElfError> {
+    read(image, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(image: &[u8], offset: usize) -> Result<u32, ElfError> {
+    read(image, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(image: &[u8], offset: usize) -> Result<u64, ElfError> {
+    read(image, offset).map(u64::from_le_bytes)
+}
+
+/// Reads `N` bytes at the given offset of the image.
+fn read<const N: usize>(image: &[u8], offset: usize) -> Result<[u8; N], ElfError> {
+    image
+        .get(offset..offset.checked_add(N).ok_or(ElfError::Truncated)?)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ElfError::Truncated)
+}
+
+kernel_test! {
+    fn elf_loading() {
+        /// Offset of the segment data in the fixture.
+        const DATA: usize = 256;
+
+        /// Builds an ELF image with the given entry point and segments, as (offset, paddr,
+        /// filesz, memsz, align), with a recognizable pattern as segment data.
+        fn fixture(entry: usize, segments: &[(usize, usize, usize, usize, usize)]) -> [u8; 512] {
+            let mut image = [0; 512];
+            let mut put = |offset: usize, bytes: &[u8]| {
+                image[offset..offset + bytes.len()].copy_from_slice(bytes)
+            };
+            put(0, &ELF_MAGIC);
+            put(4, &[ELFCLASS64, ELFDATA2LSB, 1]);
+            put(16, &ET_EXEC.to_le_bytes());
+            put(18, &EM_AARCH64.to_le_bytes());
+            put(24, &(entry as u64).to_le_bytes());
+            put(32, &(EHDR_SIZE as u64).to_le_bytes());
+            put(54, &(PHDR_SIZE as u16).to_le_bytes());
+            put(56, &(segments.len() as u16).to_le_bytes());
+            for (i, &(offset, paddr, filesz, memsz, align)) in segments.iter().enumerate() {
+                let phdr = EHDR_SIZE + i * PHDR_SIZE;
+                put(phdr, &PT_LOAD.to_le_bytes());
+                for (field, value) in [(8, offset), (24, paddr), (32, filesz), (40, memsz)] {
+                    put(phdr + field, &(value as u64).to_le_bytes());
+                }
+                put(phdr + 48, &(align as u64).to_le_bytes());
+            }
+            for (i, byte) in image.iter_mut().enumerate().skip(DATA) {
+                *byte = i as u8 | 1;
+            }
+            image
+        }
+
+        #[repr(align(4096))]
+        struct Region([u8; 0x2000]);
+        static mut REGION: Region = Region([0; 0x2000]);
+        // SAFETY: only the address is taken
+        let base = unsafe { &raw mut REGION.0 } as usize;
+        let region = base..base + 0x2000;
+        let check = |image: &[u8]| Elf::parse(image).and_then(|elf| elf.check_segments(&region));
+
+        // Program headers, with a BSS in the second segment
+        let image = fixture(base, &[(DATA, base, 16, 16, 16), (DATA, base + 0x1000, 32, 96, 0)]);
+        let elf = Elf::parse(&image).unwrap();
+        assert_eq!(elf.phnum, 2);
+        let segment = elf.segment(1).unwrap().unwrap();
+        assert_eq!(segment.memory(), base + 0x1000..base + 0x1060);
+        assert_eq!((segment.offset, segment.filesz), (DATA, 32));
+
+        // Truncated header, program headers, and segment data
+        assert_eq!(Elf::parse(&image[..EHDR_SIZE - 1]).err(), Some(ElfError::Truncated));
+        assert_eq!(Elf::parse(&image[..EHDR_SIZE + 60]).err(), Some(ElfError::Truncated));
+        let truncated = fixture(base, &[(DATA, base, 512 - DATA + 1, 0x1000, 0)]);
+        assert_eq!(check(&truncated), Err(ElfError::Truncated));
+
+        // Overlapping segments
+        let overlap = fixture(base, &[(DATA, base, 16, 0x100, 0), (DATA, base + 0xF0, 16, 16, 0)]);
+        assert_eq!(check(&overlap), Err(ElfError::Overlap { first: 0, second: 1 }));
+
+        // Misaligned segment, and alignments that aren't powers of two
+        let misaligned = fixture(base, &[(DATA, base + 0x10, 16, 16, 0x1000)]);
+        assert_eq!(check(&misaligned), Err(ElfError::Misaligned { index: 0 }));
+        let odd = fixture(base, &[(DATA, base, 16, 16, 24)]);
+        assert_eq!(check(&odd), Err(ElfError::Misaligned { index: 0 }));
+
+        // Entry point out of the segments
+        let entry = fixture(base + 0x1000, &[(DATA, base, 16, 0x100, 0)]);
+        let expected = ElfError::EntryOutOfSegments { entry: base + 0x1000 };
+        assert_eq!(check(&entry), Err(expected));
+
+        // The BSS is zeroed, the memory after the segment is left alone
+        // SAFETY: the region is only used by this test
+        unsafe { ptr::write_bytes(base as *mut u8, 0xAA, 0x2000) };
+        let loaded = unsafe { elf.load(region.clone()) };
+        assert_eq!(loaded, Ok(base));
+        // SAFETY: the region was just written by the loader
+        let memory = unsafe { core::slice::from_raw_parts(base as *const u8, 0x2000) };
+        assert_eq!(&memory[..16], &image[DATA..DATA + 16]);
+        assert_eq!(&memory[16..32], &[0xAA; 16]);
+        assert_eq!(&memory[0x1000..0x1020], &image[DATA..DATA + 32]);
+        assert!(memory[0x1020..0x1060].iter().all(|&byte| byte == 0));
+        assert!(memory[0x1060..].iter().all(|&byte| byte == 0xAA));
+    }
+}
@@ -0,0 +1,300 @@
+//! The monitor's heap, available with the `alloc` feature.
+//!
+//! A bump allocator over a region of secure RAM reserved by the platform. Freed blocks are pushed
+//! on a free list and reused before bumping further, most recently freed first; memory never goes
+//! back to the bump region. This is good enough for the few long-lived allocations of the boot
+//! path. The core paths of the monitor must keep working without the feature.
+//!
+//! Each block is preceded by a header holding its size, so that a reused block keeps its full
+//! size. A free block stores the address of the next one in its first word.
+
+use crate::ktest::kernel_test;
+use crate::sync::IrqSafeMutex;
+use crate::{memory_layout, platform};
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr::{self, NonNull};
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator;
+
+static HEAP: IrqSafeMutex<Heap> = IrqSafeMutex::new(Heap::EMPTY);
+
+/// Size of the block headers, and minimum size and alignment of the blocks.
+const HEADER_SIZE: usize = 16;
+
+/// Allocation statistics.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    /// Size of the heap region.
+    pub size: usize,
+    /// Bytes taken from the bump region so far, headers and padding included.
+    pub bumped: usize,
+    /// Bytes in live blocks.
+    pub in_use: usize,
+    /// Highest value of `in_use`.
+    pub peak: usize,
+    pub allocations: u64,
+    pub frees: u64,
+    /// Allocations served from the free list.
+    pub reused: u64,
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} bytes in use (peak {}), {}/{} bytes bumped, {} allocations ({} reused), {} frees",
+            self.in_use,
+            self.size,
+            self.peak,
+            self.bumped,
+            self.size,
+            self.allocations,
+            self.reused,
+            self.frees
+        )
+    }
+}
+
+struct Heap {
+    start: usize,
+    /// Next free address of the bump region.
+    next: usize,
+    end: usize,
+    /// Address of the most recently freed block, or 0.
+    free: usize,
+    stats: HeapStats,
+}
+
+impl Heap {
+    const EMPTY: Heap = Heap {
+        start: 0,
+        next: 0,
+        end: 0,
+        free: 0,
+        stats: HeapStats {
+            size: 0,
+            bumped: 0,
+            in_use: 0,
+            peak: 0,
+            allocations: 0,
+            frees: 0,
+            reused: 0,
+        },
+    };
+
+    fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let size = layout.size().max(1).next_multiple_of(HEADER_SIZE);
+        let align = layout.align().max(HEADER_SIZE);
+
+        let block = match self.take_free(size, align) {
+            Some(block) => {
+                self.stats.reused += 1;
+                block
+            }
+            None => self.bump(size, align)?,
+        };
+        self.stats.allocations += 1;
+        self.stats.in_use += block_size(block);
+        self.stats.peak = self.stats.peak.max(self.stats.in_use);
+        NonNull::new(block as *mut u8)
+    }
+
+    /// Unlinks and returns the most recently freed block that fits, if any.
+    fn take_free(&mut self, size: usize, align: usize) -> Option<usize> {
+        let mut link: *mut usize = &mut self.free;
+        loop {
+            // SAFETY: `link` points either to `self.free` or to the first word of a free block.
+            let block = unsafe { *link };
+            if block == 0 {
+                return None;
+            }
+            if block_size(block) >= size && block % align == 0 {
+                // SAFETY: the block is free, its first word holds the next free block.
+                unsafe { *link = *(block as *const usize) };
+                return Some(block);
+            }
+            link = block as *mut usize;
+        }
+    }
+
+    /// Carves a new block out of the bump region.
+    fn bump(&mut self, size: usize, align: usize) -> Option<usize> {
+        let block = self.next.checked_add(HEADER_SIZE)?.next_multiple_of(align);
+        let end = block.checked_add(size)?;
+        if end > self.end {
+            return None;
+        }
+        // SAFETY: the header is within the heap region, which is reserved for the heap.
+        unsafe { ptr::write((block - HEADER_SIZE) as *mut usize, size) };
+        self.stats.bumped += end - self.next;
+        self.next = end;
+        Some(block)
+    }
+
+    /// Pushes a block on the free list.
+    fn dealloc(&mut self, block: usize) {
+        assert!(
+            (self.start..self.next).contains(&block),
+            "freeing {block:#x}, which is not in the heap"
+        );
+        self.stats.frees += 1;
+        self.stats.in_use -= block_size(block);
+        // SAFETY: the block was allocated from the heap, and is no longer used.
+        unsafe { ptr::write(block as *mut usize, self.free) };
+        self.free = block;
+    }
+}
+
+/// Returns the size of a block, from its header.
+fn block_size(block: usize) -> usize {
+    // SAFETY: blocks are always preceded by their header.
+    unsafe { ptr::read((block - HEADER_SIZE) as *const usize) }
+}
+
+/// Hands the heap region reserved by the platform over to the allocator.
+///
+/// Must be called once the MMU is enabled: with the MMU off the heap would be device memory, on
+/// which the unaligned accesses of its users fault.
+pub fn init() {
+    let start = platform::HEAP_BASE;
+    let end = platform::HEAP_BASE + platform::HEAP_SIZE;
+    let image = memory_layout::image();
+    assert!(
+        end <= image.start || image.end <= start,
+        "the heap overlaps the monitor image"
+    );
+
+    let mut heap = HEAP.lock();
+    assert!(heap.end == 0, "the heap is already initialized");
+    heap.start = start;
+    heap.next = start;
+    heap.end = end;
+    heap.stats.size = platform::HEAP_SIZE;
+    log::info!("Heap: {start:#x}-{end:#x}");
+}
+
+/// Allocates a block for `layout`, returns `None` if the heap is exhausted.
+pub fn alloc(layout: Layout) -> Option<NonNull<u8>> {
+    HEAP.lock().alloc(layout)
+}
+
+/// Frees a block.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [alloc], and not be used anymore.
+pub unsafe fn dealloc(ptr: NonNull<u8>) {
+    HEAP.lock().dealloc(ptr.as_ptr() as usize);
+}
+
+/// Returns the allocation statistics.
+pub fn stats() -> HeapStats {
+    HEAP.lock().stats
+}
+
+/// Reports an allocation failure, and panics.
+fn out_of_memory(layout: Layout) -> ! {
+    let report = OutOfMemory {
+        layout,
+        stats: stats(),
+    };
+    log::error!("{report}");
+    panic!("out of memory");
+}
+
+/// The report of an allocation failure.
+struct OutOfMemory {
+    layout: Layout,
+    stats: HeapStats,
+}
+
+impl fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Out of memory allocating {} bytes (align {}), heap: {}",
+            self.layout.size(),
+            self.layout.align(),
+            self.stats
+        )
+    }
+}
+
+struct Allocator;
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match alloc(layout) {
+            Some(ptr) => ptr.as_ptr(),
+            None => out_of_memory(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            unsafe { dealloc(ptr) };
+        }
+    }
+}
+
+kernel_test! {
+    fn heap_over_buffer() {
+        #[repr(align(4096))]
+        struct Buffer([u8; 4096]);
+        static mut BUFFER: Buffer = Buffer([0; 4096]);
+
+        // SAFETY: only the address is taken
+        let start = unsafe { &raw mut BUFFER.0 } as usize;
+        let mut heap = Heap {
+            start,
+            next: start,
+            end: start + 4096,
+            free: 0,
+            stats: HeapStats {
+                size: 4096,
+                ..Heap::EMPTY.stats
+            },
+        };
+        let layout = |size, align| Layout::from_size_align(size, align).unwrap();
+        let mut allocate =
+            |size, align| heap.alloc(layout(size, align)).map(|p| p.as_ptr() as usize);
+
+        // Alignment, the header size is the minimum
+        let small = allocate(1, 1).unwrap();
+        assert_eq!(small % HEADER_SIZE, 0);
+        let aligned = allocate(64, 256).unwrap();
+        assert_eq!(aligned % 256, 0);
+        assert_eq!(block_size(aligned), 64);
+        // The header of the aligned block doesn't overlap the previous block
+        assert!(aligned - HEADER_SIZE >= small + block_size(small));
+
+        // The most recently freed block that fits is reused first
+        let a = allocate(32, 8).unwrap();
+        let b = allocate(32, 8).unwrap();
+        heap.dealloc(a);
+        heap.dealloc(b);
+        let bumped = heap.stats.bumped;
+        assert_eq!(heap.alloc(layout(32, 8)).unwrap().as_ptr() as usize, b);
+        assert_eq!(heap.alloc(layout(16, 8)).unwrap().as_ptr() as usize, a);
+        assert_eq!(heap.stats.reused, 2);
+        assert_eq!(heap.stats.bumped, bumped, "reuse bumped the heap");
+
+        // Out of memory
+        let allocations = heap.stats.allocations;
+        assert!(heap.alloc(layout(4096, 8)).is_none());
+        assert_eq!(heap.stats.allocations, allocations);
+        let report = alloc::format!(
+            "{}",
+            OutOfMemory {
+                layout: layout(4096, 8),
+                stats: heap.stats,
+            }
+        );
+        assert!(
+            report.starts_with("Out of memory allocating 4096 bytes (align 8), heap: "),
+            "{report}"
+        );
+    }
+}
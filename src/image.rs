@@ -0,0 +1,245 @@
+//! The format of the next-stage (BL33) images launched by the monitor.
+//!
+//! An image is a raw binary preceded by a little-endian header:
+//!
+//! | Offset | Size | Field                                           |
+//! |--------|------|-------------------------------------------------|
+//! | 0      | 4    | Magic, "L4IM"                                   |
+//! | 4      | 4    | Format version, 1                               |
+//! | 8      | 8    | Load address                                    |
+//! | 16     | 8    | Entry point, as an offset from the load address |
+//! | 24     | 8    | Length of the binary                            |
+//! | 32     | 4    | CRC-32 of the binary                            |
+//! | 36     | 4    | Reserved, 0                                     |
+//!
+//! The binary follows the header. `tools/mkimage.py` wraps a binary in this format.
+
+use crate::arch::cache;
+use crate::crc32::crc32;
+use crate::ktest::kernel_test;
+use core::fmt;
+use core::ops::Range;
+use core::ptr;
+
+const MAGIC: [u8; 4] = *b"L4IM";
+const VERSION: u32 = 1;
+
+/// Size of the header.
+const HEADER_SIZE: usize = 40;
+
+/// An error in an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageError {
+    /// The image ends before its header or binary.
+    Truncated,
+    /// The image doesn't start with the magic.
+    BadMagic,
+    /// The header is of an unknown version.
+    UnsupportedVersion(u32),
+    /// The entry point is not within the binary.
+    BadEntry(usize),
+    /// The binary doesn't match the CRC of the header.
+    BadChecksum { expected: u32, found: u32 },
+    /// The load range is not entirely within the allowed region.
+    OutOfBounds(usize),
+    /// The load range overlaps a region the image must not overwrite.
+    Overlap(&'static str),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ImageError::Truncated => write!(f, "truncated image"),
+            ImageError::BadMagic => write!(f, "no image magic"),
+            ImageError::UnsupportedVersion(version) => {
+                write!(f, "unsupported image version {version}")
+            }
+            ImageError::BadEntry(offset) => {
+                write!(f, "entry offset {offset:#x} is outside of the binary")
+            }
+            ImageError::BadChecksum { expected, found } => {
+                write!(
+                    f,
+                    "bad CRC, expected {expected:#010x} but found {found:#010x}"
+                )
+            }
+            ImageError::OutOfBounds(addr) => {
+                write!(f, "load address {addr:#x} is out of the allowed region")
+            }
+            ImageError::Overlap(region) => write!(f, "image would overwrite the {region}"),
+        }
+    }
+}
+
+/// A parsed image.
+pub struct Image<'a> {
+    binary: &'a [u8],
+    load_addr: usize,
+    entry_offset: usize,
+    crc: u32,
+}
+
+impl<'a> Image<'a> {
+    /// Parses the header of an image, `bytes` may extend past the binary.
+    ///
+    /// The binary is not checked, see [Image::verify].
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ImageError> {
+        if read::<4>(bytes, 0)? != MAGIC {
+            return Err(ImageError::BadMagic);
+        }
+        let version = u32::from_le_bytes(read(bytes, 4)?);
+        if version != VERSION {
+            return Err(ImageError::UnsupportedVersion(version));
+        }
+        let load_addr = u64::from_le_bytes(read(bytes, 8)?) as usize;
+        let entry_offset = u64::from_le_bytes(read(bytes, 16)?) as usize;
+        let len = u64::from_le_bytes(read(bytes, 24)?) as usize;
+        let crc = u32::from_le_bytes(read(bytes, 32)?);
+
+        let binary = bytes
+            .get(HEADER_SIZE..HEADER_SIZE.checked_add(len).ok_or(ImageError::Truncated)?)
+            .ok_or(ImageError::Truncated)?;
+        if entry_offset >= len {
+            return Err(ImageError::BadEntry(entry_offset));
+        }
+        Ok(Image {
+            binary,
+            load_addr,
+            entry_offset,
+            crc,
+        })
+    }
+
+    /// Returns the size of the image, header included.
+    pub fn size(&self) -> usize {
+        HEADER_SIZE + self.binary.len()
+    }
+
+    /// Returns the range the binary is loaded to.
+    pub fn load_range(&self) -> Range<usize> {
+        self.load_addr..self.load_addr.saturating_add(self.binary.len())
+    }
+
+    /// Checks the binary against the CRC of the header.
+    pub fn verify(&self) -> Result<(), ImageError> {
+        let found = crc32(self.binary);
+        if found != self.crc {
+            return Err(ImageError::BadChecksum {
+                expected: self.crc,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that the load range is within `allowed` and doesn't overlap any of the named
+    /// `reserved` regions.
+    pub fn check_placement(
+        &self,
+        allowed: &Range<usize>,
+        reserved: &[(&'static str, Range<usize>)],
+    ) -> Result<(), ImageError> {
+        let range = self.load_range();
+        let fits = self
+            .load_addr
+            .checked_add(self.binary.len())
+            .is_some_and(|end| self.load_addr >= allowed.start && end <= allowed.end);
+        if !fits {
+            return Err(ImageError::OutOfBounds(self.load_addr));
+        }
+        for (name, region) in reserved {
+            if range.start < region.end && region.start < range.end {
+                return Err(ImageError::Overlap(name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the binary to its load address, and returns the entry point.
+    ///
+    /// # Safety
+    ///
+    /// The load range must have been checked with [Image::check_placement], against all the memory
+    /// used by the monitor and the image itself.
+    pub unsafe fn load(&self) -> usize {
+        log::debug!(
+            "Image binary: {:#x}-{:#x}",
+            self.load_range().start,
+            self.load_range().end
+        );
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.binary.as_ptr(),
+                self.load_addr as *mut u8,
+                self.binary.len(),
+            )
+        };
+        // The image starts with its MMU off, its fetches bypass the caches.
+        cache::clean_dcache_range(self.load_addr, self.binary.len());
+        cache::invalidate_icache_all();
+
+        self.load_addr + self.entry_offset
+    }
+}
+
+/// Reads `N` bytes at the given offset of the image.
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], ImageError> {
+    bytes
+        .get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ImageError::Truncated)
+}
+
+kernel_test! {
+    fn parse_and_verify() {
+        const BINARY: &[u8] = b"\x03\x00\x00\xd4\x00\x00\x00\x14";
+        let mut bytes = [0; HEADER_SIZE + 8];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4..8].copy_from_slice(&VERSION.to_le_bytes());
+        bytes[8..16].copy_from_slice(&0x6000_0000u64.to_le_bytes());
+        bytes[16..24].copy_from_slice(&4u64.to_le_bytes());
+        bytes[24..32].copy_from_slice(&8u64.to_le_bytes());
+        bytes[32..36].copy_from_slice(&crc32(BINARY).to_le_bytes());
+        bytes[HEADER_SIZE..].copy_from_slice(BINARY);
+
+        let image = Image::parse(&bytes).unwrap();
+        assert_eq!(image.size(), bytes.len());
+        assert_eq!(image.load_range(), 0x6000_0000..0x6000_0008);
+        assert_eq!(image.verify(), Ok(()));
+        assert_eq!(
+            Image::parse(&bytes[..bytes.len() - 1]).err(),
+            Some(ImageError::Truncated)
+        );
+
+        bytes[HEADER_SIZE] ^= 1;
+        let image = Image::parse(&bytes).unwrap();
+        assert!(matches!(image.verify(), Err(ImageError::BadChecksum { .. })));
+
+        bytes[16..24].copy_from_slice(&8u64.to_le_bytes());
+        assert_eq!(Image::parse(&bytes).err(), Some(ImageError::BadEntry(8)));
+        bytes[0] = 0;
+        assert_eq!(Image::parse(&bytes).err(), Some(ImageError::BadMagic));
+    }
+}
+
+kernel_test! {
+    fn placement_rejects_overlaps() {
+        let image = Image {
+            binary: &[0; 0x100],
+            load_addr: 0x1000,
+            entry_offset: 0,
+            crc: 0,
+        };
+        let reserved = [("low", 0x0..0x1000), ("high", 0x1100..0x2000)];
+        assert_eq!(image.check_placement(&(0..0x10000), &reserved), Ok(()));
+        assert_eq!(
+            image.check_placement(&(0x1000..0x10ff), &reserved),
+            Err(ImageError::OutOfBounds(0x1000))
+        );
+        let reserved = [("middle", 0x10ff..0x1100)];
+        assert_eq!(
+            image.check_placement(&(0..0x10000), &reserved),
+            Err(ImageError::Overlap("middle"))
+        );
+    }
+}
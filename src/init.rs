@@ -0,0 +1,315 @@
+//! The boot sequence of the primary core, as a chain of phases.
+//!
+//! Each phase is a function taking the token returned by the previous one, so running them out of
+//! order doesn't compile. The tokens can only be created here, and are consumed by the next phase.
+//! Entering a phase twice, for instance from a secondary core accidentally running the primary
+//! path, is caught at runtime by the phase counter and panics.
+//!
+//! The phases run in the order of their dependencies:
+//! - [exceptions]: the EL check, the exception vectors, and the per-CPU state, first so that any
+//!   later fault is reported.
+//! - [early_console]: the logger, then the checks of the image layout.
+//! - [memory]: the CPU configuration that must be in place before the MMU and caches are on, the
+//!   MMU, the DTB, and the heap.
+//! - [interrupts]: the GIC.
+//! - [timers]: the watchdog, which relies on the secure timer interrupt.
+//! - [platform]: the SMCCC services and the checks of the hardware features.
+//! - [late]: the final checks and reports, and the on-target tests.
+//!
+//! Each phase ends with a [boottime] mark named after it.
+
+use crate::arch::{self, ExceptionLevel};
+use crate::driver::gic::GicV3;
+#[cfg(feature = "alloc")]
+use crate::heap;
+#[cfg(feature = "run-tests")]
+use crate::ktest;
+use crate::ktest::kernel_test;
+use crate::{
+    BOOT_ARGS, STACK_SIZE, boottime, dtb, init_check, logger, memory_layout, percpu, platform,
+    profile, smccc, stack, sync, version, watchdog,
+};
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Maximum time between two boot milestones before the watchdog gives up.
+const BOOT_WATCHDOG_MS: u64 = 5000;
+
+/// The last phase entered, or 0 before the boot.
+///
+/// Advanced with a compare-and-swap: of two cores racing into a phase, only one enters it.
+static PHASE: AtomicU8 = AtomicU8::new(0);
+
+/// A boot phase, numbered in boot order from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum Phase {
+    Exceptions = 1,
+    EarlyConsole,
+    Memory,
+    Interrupts,
+    Timers,
+    Platform,
+    Late,
+}
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Exceptions => "exceptions",
+            Phase::EarlyConsole => "early console",
+            Phase::Memory => "memory",
+            Phase::Interrupts => "interrupts",
+            Phase::Timers => "timers",
+            Phase::Platform => "platform",
+            Phase::Late => "late",
+        }
+    }
+}
+
+/// Advances the phase `counter` to `phase`, if it is at the phase right before. Otherwise, returns
+/// the phase the counter is at.
+fn advance(counter: &AtomicU8, phase: Phase) -> Result<(), u8> {
+    counter
+        .compare_exchange(
+            phase as u8 - 1,
+            phase as u8,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        )
+        .map(|_| ())
+}
+
+/// Records the start of `phase`.
+///
+/// # Panics
+///
+/// Panics if the previous phase is not the last one entered.
+fn enter(phase: Phase) {
+    if let Err(last) = advance(&PHASE, phase) {
+        panic!(
+            "Entering the {} boot phase after phase {last}",
+            phase.name()
+        );
+    }
+}
+
+/// Records the end of `phase`.
+fn exit(phase: Phase) {
+    boottime::mark(phase.name());
+}
+
+/// The exception vectors and per-CPU state are set up.
+pub struct Exceptions(());
+/// The logger is available, and the image layout checked.
+pub struct EarlyConsole(());
+/// The MMU is on, and the implementation of the atomic operations is selected.
+pub struct Memory {
+    pauth: bool,
+}
+/// The GIC is configured for the boot core.
+pub struct Interrupts(());
+/// The watchdog is started.
+pub struct Timers(());
+/// The SMCCC services are registered, and the hardware features checked.
+pub struct Platform(());
+/// The boot is complete, the payloads can be entered.
+pub struct Late(());
+
+impl Memory {
+    /// Returns `true` if pointer authentication was initialized, and signing can be enabled.
+    ///
+    /// Signing must be enabled from `main`, which never returns, see [arch::pauth::enable_el3].
+    pub fn pauth_ready(&self) -> bool {
+        self.pauth
+    }
+}
+
+/// Checks the EL, and installs the exception vectors.
+pub fn exceptions() -> Exceptions {
+    boottime::mark("entry");
+    // Accessing any EL3 register would fault if we were started at a lower EL.
+    let el = arch::current_el();
+    if el != ExceptionLevel::El3 {
+        early_failure(format_args!(
+            "l4sm must run at EL3, but was started at {el:?}. Is QEMU running with secure=on?"
+        ));
+    }
+    enter(Phase::Exceptions);
+
+    if let Err(err) = arch::exception::install() {
+        early_failure(format_args!("{err}"));
+    }
+    arch::scr::ScrEl3::BASELINE.write();
+    // SErrors are routed to EL3 explicitly, they would otherwise go to the lower ELs. They are
+    // only unmasked now that the vectors can handle them.
+    arch::serror::init();
+    arch::serror::unmask();
+    if cfg!(feature = "fpsimd") {
+        arch::fpsimd::enable();
+    } else {
+        arch::fpsimd::disable();
+    }
+    percpu::init();
+    exit(Phase::Exceptions);
+    Exceptions(())
+}
+
+/// Starts the logger, and checks that the image was loaded and laid out as expected.
+pub fn early_console(_: Exceptions) -> EarlyConsole {
+    enter(Phase::EarlyConsole);
+    logger::init();
+    log::info!("{}", version::Banner);
+    if !init_check::run() {
+        log::error!("The data or BSS section is not initialized, check the linker script");
+        platform::exit_failure();
+    }
+    log::info!("Running at {:?}", arch::current_el());
+    if let Err(err) = memory_layout::validate() {
+        panic!("Invalid memory layout: {err}");
+    }
+    exit(Phase::EarlyConsole);
+    EarlyConsole(())
+}
+
+/// Configures the CPU, enables the MMU, and sets up the memory handed over by the previous stage.
+pub fn memory(_: EarlyConsole) -> Memory {
+    enter(Phase::Memory);
+    arch::errata::apply_all();
+    arch::pmu::init();
+    arch::dit::enable();
+    let pauth = cfg!(feature = "pauth") && arch::pauth::init();
+    log::info!(
+        "FP/SIMD: {}",
+        if arch::fpsimd::is_enabled() {
+            "enabled"
+        } else {
+            "trapped"
+        }
+    );
+    {
+        let _scope = profile::scope("mmu");
+        arch::mmu::init();
+    }
+    // Before the MMU is on, memory is Device, where LSE atomics are not guaranteed to be
+    // supported. The locks taken until now used LL/SC.
+    sync::atomics::init();
+    check_dtb();
+    #[cfg(feature = "alloc")]
+    heap::init();
+    exit(Phase::Memory);
+    Memory { pauth }
+}
+
+/// Configures the GIC and the monitor's interrupts.
+pub fn interrupts(_: Memory) -> Interrupts {
+    enter(Phase::Interrupts);
+    // SAFETY: the base addresses are defined in the platform module for the target platform.
+    let gic = unsafe { GicV3::new(platform::GICD_BASE, platform::GICR_BASE) };
+    {
+        let _scope = profile::scope("gic");
+        gic.init();
+        gic.init_cpu();
+        gic.configure(platform::INTERRUPT_MAP);
+    }
+    exit(Phase::Interrupts);
+    Interrupts(())
+}
+
+/// Starts the watchdog.
+pub fn timers(_: Interrupts) -> Timers {
+    enter(Phase::Timers);
+    {
+        let _scope = profile::scope("watchdog");
+        watchdog::start(BOOT_WATCHDOG_MS);
+    }
+    exit(Phase::Timers);
+    Timers(())
+}
+
+/// Registers the SMCCC services, and checks the hardware features.
+pub fn platform(_: Timers) -> Platform {
+    enter(Phase::Platform);
+    smccc::init();
+    {
+        let _scope = profile::scope("log features");
+        arch::feature::log_features();
+    }
+    let (_, entropy) = arch::rand::random_u64();
+    log::info!("Entropy source: {entropy:?}");
+    watchdog::feed();
+
+    if !arch::feature::has_rme() {
+        panic!("Hardware does not support RME");
+    }
+
+    #[cfg(feature = "inject-serror")]
+    arch::serror::inject_for_test();
+    exit(Phase::Platform);
+    Platform(())
+}
+
+/// Runs the final checks, reports the boot, and runs the on-target tests.
+pub fn late(_: Platform) -> Late {
+    enter(Phase::Late);
+    stack::assert_not_overflowed();
+    log::info!(
+        "Boot stack usage: {}/{STACK_SIZE} bytes",
+        stack::high_watermark()
+    );
+    exit(Phase::Late);
+    profile::report();
+    boottime::report();
+    #[cfg(feature = "run-tests")]
+    ktest::run_all();
+    #[cfg(feature = "alloc")]
+    log::info!("Heap: {}", heap::stats());
+    Late(())
+}
+
+/// Validates the device tree handed over in x0, and checks the DRAM size against it.
+fn check_dtb() {
+    let addr = BOOT_ARGS[0].load(Ordering::Relaxed) as usize;
+    let dtb = match dtb::init(addr) {
+        Ok(dtb) => dtb,
+        Err(err) => {
+            log::info!("DTB: {err}, using the built-in platform description");
+            return;
+        }
+    };
+    let region = dtb.region();
+    log::info!("DTB: {:#x}-{:#x}", region.start, region.end);
+    match dtb.memory() {
+        Some(memory) if memory.len() != platform::DRAM_SIZE => log::warn!(
+            "DTB: DRAM is {} bytes, but the platform expects {}",
+            memory.len(),
+            platform::DRAM_SIZE
+        ),
+        Some(memory) => log::debug!("DTB: DRAM {:#x}-{:#x}", memory.start, memory.end),
+        None => log::warn!("DTB: no memory node"),
+    }
+}
+
+/// Reports a failure that happened before the logger and exception vectors are set up, and
+/// exits.
+fn early_failure(args: fmt::Arguments) -> ! {
+    logger::early_print(format_args!("{args}\n"));
+    platform::exit_failure();
+}
+
+kernel_test! {
+    fn phases_run_in_order() {
+        let counter = AtomicU8::new(0);
+        assert_eq!(advance(&counter, Phase::Exceptions), Ok(()));
+        assert_eq!(advance(&counter, Phase::EarlyConsole), Ok(()));
+        // Entering a phase again, as a second core running the primary path would
+        let current = Phase::EarlyConsole as u8;
+        assert_eq!(advance(&counter, Phase::EarlyConsole), Err(current));
+        // Skipping a phase, or re-entering the boot
+        assert_eq!(advance(&counter, Phase::Interrupts), Err(current));
+        assert_eq!(advance(&counter, Phase::Exceptions), Err(current));
+        assert_eq!(advance(&AtomicU8::new(u8::MAX), Phase::Exceptions), Err(u8::MAX));
+        // The tests run during the last phase
+        assert_eq!(PHASE.load(Ordering::Relaxed), Phase::Late as u8);
+    }
+}
@@ -0,0 +1,93 @@
+//! Boot-time checks that the data and BSS sections were set up.
+//!
+//! A linker script that misses input sections, or an image loaded without its data, only shows up
+//! as corrupted state much later. Sentinel statics catch that right at boot: a BSS array that must
+//! read as zero, a data array with a known pattern, and a canary linked at the very end of the BSS
+//! that must both be zeroed and end where the boot stub stops zeroing.
+
+use crate::ktest::kernel_test;
+use crate::memory_layout;
+use core::ptr;
+use core::sync::atomic::AtomicU64;
+
+const DATA_PATTERN: [u64; 4] = [
+    0x0123_4567_89ab_cdef,
+    0xfedc_ba98_7654_3210,
+    0x5a5a_5a5a_a5a5_a5a5,
+    0xdead_beef_cafe_f00d,
+];
+
+/// Never written, must read as zero.
+static BSS_SENTINEL: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+/// Never written, must hold `DATA_PATTERN`.
+static DATA_SENTINEL: [AtomicU64; 4] = [
+    AtomicU64::new(DATA_PATTERN[0]),
+    AtomicU64::new(DATA_PATTERN[1]),
+    AtomicU64::new(DATA_PATTERN[2]),
+    AtomicU64::new(DATA_PATTERN[3]),
+];
+
+/// Aligned to the BSS zeroing stride, so that it ends exactly on the end of the BSS.
+#[repr(align(16))]
+struct Canary([AtomicU64; 2]);
+
+/// Never written, must read as zero. The linker script sorts the BSS by name, placing the section
+/// last.
+#[unsafe(link_section = ".bss.~l4sm_canary")]
+static BSS_CANARY: Canary = Canary([const { AtomicU64::new(0) }; 2]);
+
+/// Runs the checks, logging the result of each of them. Returns `false` if any failed.
+///
+/// Must run before anything else could write to the sentinels' memory, and is only meaningful
+/// right after boot.
+pub fn run() -> bool {
+    let checks: [(_, fn() -> bool); 3] = [
+        ("BSS zeroed", bss_zeroed),
+        ("data loaded", data_loaded),
+        ("BSS canary", canary_ok),
+    ];
+    let mut ok = true;
+    for (name, check) in checks {
+        if check() {
+            log::debug!("Init check: {name}: pass");
+        } else {
+            log::error!("Init check: {name}: FAIL");
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// Reads the sentinels with volatile loads, the compiler could otherwise assume they still hold
+/// their initial values.
+fn read(words: &[AtomicU64]) -> impl Iterator<Item = u64> + '_ {
+    words
+        .iter()
+        .map(|word| unsafe { ptr::read_volatile(word.as_ptr()) })
+}
+
+fn bss_zeroed() -> bool {
+    read(&BSS_SENTINEL).all(|word| word == 0)
+}
+
+fn data_loaded() -> bool {
+    read(&DATA_SENTINEL).eq(DATA_PATTERN)
+}
+
+fn canary_ok() -> bool {
+    let end = (&raw const BSS_CANARY) as usize + size_of::<Canary>();
+    if end != memory_layout::bss().end {
+        log::error!(
+            "BSS canary ends at {end:#x}, but the BSS at {:#x}",
+            memory_layout::bss().end
+        );
+        return false;
+    }
+    read(&BSS_CANARY.0).all(|word| word == 0)
+}
+
+kernel_test! {
+    fn sections_initialized() {
+        assert!(run());
+    }
+}
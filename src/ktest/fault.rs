@@ -0,0 +1,70 @@
+//! Deliberate faults.
+//!
+//! A test checks that an access faults by running it under [expect_fault]. Instead of crashing,
+//! the synchronous exception handler then reports the fault and resumes after the faulting
+//! instruction, like it does for a checkpoint.
+
+use crate::arch::esr::{self, EsrInfo};
+use crate::arch::exception::ExceptionFrame;
+use crate::logger::emergency_log;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Set while a fault is expected, cleared by the handler once it happened.
+static EXPECTED: AtomicBool = AtomicBool::new(false);
+/// The syndrome, fault address, and faulting instruction of the last expected fault.
+static ESR: AtomicU64 = AtomicU64::new(0);
+static FAR: AtomicU64 = AtomicU64::new(0);
+static ELR: AtomicU64 = AtomicU64::new(0);
+
+/// A fault caught by [expect_fault].
+#[derive(Clone, Copy, Debug)]
+pub struct Fault {
+    pub esr: EsrInfo,
+    /// The raw value of `ESR_EL3`.
+    pub syndrome: u64,
+    /// The fault address, only meaningful if the syndrome says it is valid.
+    pub far: u64,
+    /// The address of the faulting instruction.
+    pub elr: u64,
+}
+
+/// Runs `f`, which is expected to take a synchronous exception at EL3, and returns the first one,
+/// or `None` if `f` didn't fault.
+///
+/// The faulting instruction is skipped, `f` should fault in inline assembly so that it is clear
+/// which instruction that is.
+pub fn expect_fault(f: impl FnOnce()) -> Option<Fault> {
+    EXPECTED.store(true, Ordering::SeqCst);
+    f();
+    if EXPECTED.swap(false, Ordering::SeqCst) {
+        return None;
+    }
+    let syndrome = ESR.load(Ordering::SeqCst);
+    Some(Fault {
+        esr: esr::decode(syndrome),
+        syndrome,
+        far: FAR.load(Ordering::SeqCst),
+        elr: ELR.load(Ordering::SeqCst),
+    })
+}
+
+/// Handles a synchronous exception taken from EL3 if a fault was expected.
+///
+/// Returns `true` if the exception was handled, ELR then points to the instruction following the
+/// faulting one.
+pub fn catch_fault(frame: &mut ExceptionFrame) -> bool {
+    if !EXPECTED.load(Ordering::SeqCst) {
+        return false;
+    }
+    ESR.store(frame.esr, Ordering::SeqCst);
+    FAR.store(frame.far, Ordering::SeqCst);
+    ELR.store(frame.elr, Ordering::SeqCst);
+    EXPECTED.store(false, Ordering::SeqCst);
+    emergency_log(format_args!(
+        "  Expected fault at {:#x}: {}",
+        frame.elr,
+        esr::decode(frame.esr)
+    ));
+    frame.elr += 4;
+    true
+}
@@ -0,0 +1,54 @@
+//! On-target tests, run under QEMU.
+//!
+//! Tests are registered with [kernel_test!] next to the code they exercise, and are only compiled
+//! with the `run-tests` feature. The monitor then runs them at the end of the boot instead of
+//! entering the payloads, and exits with the number of failed tests, see [run_all].
+
+#[cfg(feature = "run-tests")]
+mod fault;
+#[cfg(feature = "run-tests")]
+mod runner;
+
+#[cfg(feature = "run-tests")]
+pub use fault::{catch_fault, expect_fault};
+
+#[cfg(feature = "run-tests")]
+pub use runner::{Abort, Test, abort_current, is_running, run_all};
+
+/// Registers an on-target test.
+///
+/// The test fails if it panics or doesn't return in time. With `#[should_panic]`, it instead
+/// fails unless it panics. Locks held by a failed test are never released, tests should not hold
+/// any for long.
+///
+/// ```ignore
+/// kernel_test! {
+///     fn counter_advances() {
+///         assert!(timer::counter() > 0);
+///     }
+/// }
+/// ```
+macro_rules! kernel_test {
+    (fn $name:ident() $body:block) => {
+        $crate::ktest::kernel_test!(@register false, $name, $body);
+    };
+    (#[should_panic] fn $name:ident() $body:block) => {
+        $crate::ktest::kernel_test!(@register true, $name, $body);
+    };
+    (@register $should_panic:literal, $name:ident, $body:block) => {
+        #[cfg(feature = "run-tests")]
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = ".l4sm_tests")]
+            static TEST: $crate::ktest::Test = $crate::ktest::Test {
+                name: concat!(module_path!(), "::", stringify!($name)),
+                should_panic: $should_panic,
+                func: {
+                    extern "C" fn $name() $body
+                    $name
+                },
+            };
+        };
+    };
+}
+pub(crate) use kernel_test;
@@ -0,0 +1,162 @@
+//! The test runner.
+//!
+//! Without unwinding, a failed test can't return. Instead, the runner saves its callee-saved
+//! registers, stack pointer, and interrupt masks before each test, and the panic handler and the
+//! watchdog restore them to abandon the test, much like `setjmp` and `longjmp`.
+
+use crate::ktest::kernel_test;
+use crate::logger::emergency_log;
+use crate::{platform, watchdog};
+use core::arch::naked_asm;
+use core::fmt;
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+unsafe extern "C" {
+    static _tests_start: u8;
+    static _tests_end: u8;
+}
+
+/// Time a test has to complete.
+const TIMEOUT_MS: u64 = 1000;
+
+/// Number of words saved before running a test: x19 to x30, SP, and DAIF.
+const CONTEXT_WORDS: usize = 14;
+
+/// Set while a test runs.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+/// The state to restore when abandoning a test.
+static CONTEXT: [AtomicU64; CONTEXT_WORDS] = [const { AtomicU64::new(0) }; CONTEXT_WORDS];
+
+/// A registered test, see [kernel_test!](super::kernel_test).
+pub struct Test {
+    pub name: &'static str,
+    /// The test passes only if it panics.
+    pub should_panic: bool,
+    /// The test itself, called from assembly.
+    pub func: extern "C" fn(),
+}
+
+/// Runs all the registered tests, and exits with the number of failures.
+pub fn run_all() -> ! {
+    // SAFETY: the linker script places the test descriptors, and only them, between the symbols.
+    let tests = unsafe {
+        let start = &raw const _tests_start as *const Test;
+        let end = &raw const _tests_end as *const Test;
+        slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+
+    // The output bypasses the logger, which a failed test may have left locked
+    emergency_log(format_args!("Running {} tests", tests.len()));
+    let mut failed = 0;
+    for test in tests {
+        watchdog::arm(TIMEOUT_MS);
+        RUNNING.store(true, Ordering::SeqCst);
+        // SAFETY: the context is only restored while the test runs, from deeper in the stack.
+        let result = unsafe { run_guarded(test.func, CONTEXT.as_ptr()) };
+        let passed = if test.should_panic {
+            result == Abort::Panic as u64
+        } else {
+            result == 0
+        };
+        RUNNING.store(false, Ordering::SeqCst);
+        watchdog::disarm();
+
+        let status = if passed { "ok" } else { "FAILED" };
+        emergency_log(format_args!("test {} ... {status}", test.name));
+        if !passed {
+            failed += 1;
+        }
+    }
+
+    emergency_log(format_args!(
+        "Test result: {} passed, {failed} failed",
+        tests.len() as u64 - failed
+    ));
+    if failed == 0 {
+        platform::exit_success();
+    }
+    platform::exit_failure_code(failed);
+}
+
+/// Returns `true` if a test is running.
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+/// Why a test was abandoned, as returned by [run_guarded].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Abort {
+    Panic = 1,
+    Timeout = 2,
+}
+
+/// Abandons the running test for `reason`, and resumes the runner. Returns if no test is running.
+///
+/// Can be called from an exception handler, the exception is abandoned along with the test.
+pub fn abort_current(abort: Abort, reason: fmt::Arguments) {
+    if !RUNNING.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    emergency_log(format_args!("  {reason}"));
+    // SAFETY: the context was saved by the runner before starting the test, which is still
+    // running deeper in the stack.
+    unsafe { resume(CONTEXT.as_ptr(), abort as u64) };
+}
+
+/// Saves the context in `context`, and calls `func`. Returns 0 once `func` returns, or the code
+/// given to [resume].
+///
+/// # Safety
+///
+/// `context` must point to [CONTEXT_WORDS] words.
+#[unsafe(naked)]
+unsafe extern "C" fn run_guarded(func: extern "C" fn(), context: *const AtomicU64) -> u64 {
+    naked_asm!(
+        "stp x19, x20, [x1, #0]",
+        "stp x21, x22, [x1, #16]",
+        "stp x23, x24, [x1, #32]",
+        "stp x25, x26, [x1, #48]",
+        "stp x27, x28, [x1, #64]",
+        "stp x29, x30, [x1, #80]",
+        "mov x2, sp",
+        "mrs x3, DAIF",
+        "stp x2, x3, [x1, #96]",
+        "stp x29, x30, [sp, #-16]!",
+        "mov x29, sp",
+        "blr x0",
+        "ldp x29, x30, [sp], #16",
+        "mov x0, #0",
+        "ret",
+    );
+}
+
+/// Restores the context saved by [run_guarded], which then returns `code`.
+///
+/// # Safety
+///
+/// The context must have been saved by a call to [run_guarded] which has not returned yet.
+#[unsafe(naked)]
+unsafe extern "C" fn resume(context: *const AtomicU64, code: u64) -> ! {
+    naked_asm!(
+        "ldp x19, x20, [x0, #0]",
+        "ldp x21, x22, [x0, #16]",
+        "ldp x23, x24, [x0, #32]",
+        "ldp x25, x26, [x0, #48]",
+        "ldp x27, x28, [x0, #64]",
+        "ldp x29, x30, [x0, #80]",
+        "ldp x2, x3, [x0, #96]",
+        "mov sp, x2",
+        "msr DAIF, x3",
+        "mov x0, x1",
+        "ret",
+    );
+}
+
+kernel_test! {
+    #[should_panic]
+    fn panics_fail_the_test() {
+        panic!("boom {}", 42);
+    }
+}
@@ -1,17 +1,25 @@
-//! Logging backend that writes to the secure world PL011 UART.
+//! Logging backend that writes to the secure world UART, a PL011 or an NS16550 depending on the
+//! platform configuration (see [SerialPort]).
 
-use crate::driver::pl011::Pl011;
-use crate::platform;
+use crate::driver::serial::SerialPort;
+use crate::ktest::kernel_test;
+use crate::percpu;
+use crate::platform::{self, Uart};
+use crate::sync::{IrqSafeMutex, critical_section};
+use core::fmt;
 use core::fmt::Write;
 use core::sync::atomic::{AtomicBool, Ordering};
 use log::Level;
-use spin::Mutex;
 
-// SAFETY: the base address is defined in the platform module for the target platform and used
-// solely for logging through the logger.
-static UART1: Mutex<Pl011> = Mutex::new(unsafe { Pl011::new(platform::UART1_BASE) });
+// SAFETY: the UART is used solely for logging through the logger, other users bypass the lock only
+// when it can't be taken.
+static UART1: IrqSafeMutex<Uart> = IrqSafeMutex::new(unsafe { platform::secure_uart() });
 static LOGGER: Logger = Logger;
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
+/// Set when the UART stopped accepting bytes, logs are dropped until it recovers.
+static UART_STUCK: AtomicBool = AtomicBool::new(false);
+/// The last lines logged, for crash reports.
+static HISTORY: IrqSafeMutex<History> = IrqSafeMutex::new(History::new());
 
 /// Initializes the logger.
 ///
@@ -23,10 +31,68 @@ pub fn init() {
         !INITIALIZED.swap(true, Ordering::Relaxed),
         "logger already initialized"
     );
+    let mut uart = UART1.lock();
+    uart.init();
+    // Nothing reads the UART yet, drop what was received before boot
+    while uart.getc().is_some() {}
+    drop(uart);
+
     log::set_logger(&LOGGER).unwrap();
     log::set_max_level(log::LevelFilter::Trace);
 }
 
+/// Writes an error message directly to the UART, bypassing the logger lock.
+///
+/// This is meant for exception handlers and the panic handler, where the lock might be held by
+/// the interrupted code. Each message is written in one burst, but output from concurrent CPUs
+/// can still interleave.
+pub fn emergency_log(args: fmt::Arguments) {
+    let mut buf = FmtBuf::new();
+    let _ = write!(
+        buf,
+        "[{}] {}{}",
+        level_display(Level::Error),
+        CpuPrefix,
+        args
+    );
+    buf.flush_line();
+}
+
+/// Writes directly to the UART, independently of the logger.
+///
+/// Unlike the logger, this can be used before [init] is called.
+pub fn early_print(args: fmt::Arguments) {
+    let mut buf = FmtBuf::new();
+    let _ = buf.write_fmt(args);
+    buf.flush();
+}
+
+/// Waits until the UART sent out everything written so far, bypassing the logger lock.
+pub fn flush_uart() {
+    // SAFETY: flushing only polls the UART status.
+    unsafe { platform::secure_uart() }.flush();
+}
+
+/// Calls `f` on the last lines logged, from the oldest to the most recent.
+///
+/// Meant for crash reports: returns `false` without waiting if the history is locked, which
+/// happens if the crash interrupted the logger.
+pub fn for_each_recent_line(mut f: impl FnMut(&str)) -> bool {
+    let Some(history) = HISTORY.try_lock() else {
+        return false;
+    };
+    for i in 0..history.count {
+        let index = (history.next + HISTORY_LINES - history.count + i) % HISTORY_LINES;
+        f(history.lines[index].as_str());
+    }
+    true
+}
+
+/// Returns `true` if the logger has been initialized.
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::Relaxed)
+}
+
 // ————————————————————————————————— Logger ————————————————————————————————— //
 
 pub struct Logger;
@@ -38,17 +104,162 @@ impl log::Log for Logger {
 
     fn log(&self, record: &log::Record) {
         if self.enabled(record.metadata()) {
-            let mut uart = UART1.lock();
-            let _ = writeln!(
+            HISTORY.lock().push(record.level(), record.args());
+            let uart = UART1.lock();
+            if UART_STUCK.load(Ordering::Relaxed) && !uart.can_write() {
+                // Don't wait for a stuck UART on every message
+                return;
+            }
+            let result = writeln!(
                 uart,
-                "[{}] {}",
+                "[{}] {}{}",
                 level_display(record.level()),
+                CpuPrefix,
                 record.args()
             );
+            UART_STUCK.store(result.is_err(), Ordering::Relaxed);
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        UART1.lock().flush();
+    }
+}
+
+/// Formats as the index of the calling CPU, before the messages.
+struct CpuPrefix;
+
+impl fmt::Display for CpuPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Nothing to show for the messages logged before the per-CPU data is set up
+        match percpu::try_current() {
+            Some(percpu) => write!(f, "CPU{}: ", percpu.index()),
+            None => Ok(()),
+        }
+    }
+}
+
+// ———————————————————————————————— History ————————————————————————————————— //
+
+/// Number of lines kept in the history.
+const HISTORY_LINES: usize = 16;
+
+/// A ring buffer of the last lines logged.
+struct History {
+    lines: [FmtBuf; HISTORY_LINES],
+    /// Index of the line to overwrite next.
+    next: usize,
+    /// Number of lines held.
+    count: usize,
+}
+
+impl History {
+    const fn new() -> Self {
+        Self {
+            lines: [const { FmtBuf::new() }; HISTORY_LINES],
+            next: 0,
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, level: Level, args: &fmt::Arguments) {
+        let line = &mut self.lines[self.next];
+        *line = FmtBuf::new();
+        let _ = write!(line, "[{level}] {args}");
+        self.next = (self.next + 1) % HISTORY_LINES;
+        self.count = (self.count + 1).min(HISTORY_LINES);
+    }
+}
+
+// ——————————————————————————— Formatting Buffer ———————————————————————————— //
+
+/// Size of a [FmtBuf], in bytes.
+const FMT_BUF_SIZE: usize = 256;
+/// Appended to the output of a truncated [FmtBuf].
+const TRUNCATION_MARKER: &str = "...";
+
+/// A fixed-size buffer to format a message before writing it to the UART at once.
+///
+/// Formatting straight to the UART leaves gaps between the pieces of a message, in which
+/// interrupts can run and print their own output. Messages that don't fit are truncated.
+struct FmtBuf {
+    buf: [u8; FMT_BUF_SIZE],
+    len: usize,
+    truncated: bool,
+}
+
+impl FmtBuf {
+    const fn new() -> Self {
+        Self {
+            buf: [0; FMT_BUF_SIZE],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// Returns the content of the buffer.
+    fn as_str(&self) -> &str {
+        // SAFETY: only whole UTF-8 characters are copied into the buffer.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    /// Writes the content of the buffer to the UART, with interrupts masked.
+    fn flush(&self) {
+        self.write_to_uart("");
+    }
+
+    /// Writes the content of the buffer and a newline to the UART, with interrupts masked.
+    ///
+    /// The newline is kept even if the content was truncated.
+    fn flush_line(&self) {
+        self.write_to_uart("\n");
+    }
+
+    fn write_to_uart(&self, end: &str) {
+        // SAFETY: this is the same UART as the logger, we only give up on mutual exclusion.
+        let uart = unsafe { platform::secure_uart() };
+        critical_section(|_| {
+            for piece in self.output(end) {
+                if uart.write_str(piece).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Returns the pieces written out, in order: the content, the truncation marker if needed,
+    /// and `end`.
+    fn output<'a>(&'a self, end: &'a str) -> [&'a str; 3] {
+        let marker = if self.truncated {
+            TRUNCATION_MARKER
+        } else {
+            ""
+        };
+        [self.as_str(), marker, end]
+    }
+}
+
+impl fmt::Write for FmtBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            // Later pieces could still fit in the space left, but wouldn't follow the cut one
+            return Ok(());
+        }
+        let available = FMT_BUF_SIZE - self.len;
+        let mut end = s.len();
+        if end > available {
+            // Keep as much as fits, without splitting a character
+            end = available;
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.truncated = true;
+        }
+        self.buf[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        // Formatting goes on, truncation is reported on output
+        Ok(())
+    }
 }
 
 fn level_display(level: Level) -> &'static str {
@@ -61,3 +272,39 @@ fn level_display(level: Level) -> &'static str {
         Level::Trace => "\x1b[35;1mTrace\x1b[0m",
     }
 }
+
+kernel_test! {
+    fn fmt_buf_truncates() {
+        let filled = |len| {
+            let mut buf = FmtBuf::new();
+            for _ in 0..len {
+                let _ = buf.write_str(" ");
+            }
+            buf
+        };
+        let mut buf = filled(FMT_BUF_SIZE - 2);
+        let _ = buf.write_str("abc");
+        assert!(buf.truncated);
+        assert!(buf.as_str().ends_with(" ab"));
+        // Characters are not split, and nothing is appended after the cut in the space left
+        let mut buf = filled(FMT_BUF_SIZE - 1);
+        let _ = buf.write_str("é");
+        let _ = buf.write_str("x");
+        assert!(buf.truncated);
+        assert_eq!(buf.len, FMT_BUF_SIZE - 1);
+    }
+}
+
+kernel_test! {
+    fn fmt_buf_keeps_newline() {
+        let mut buf = FmtBuf::new();
+        let _ = buf.write_str("short");
+        assert_eq!(buf.output("\n"), ["short", "", "\n"]);
+        for _ in 0..FMT_BUF_SIZE {
+            let _ = buf.write_str("long");
+        }
+        let [content, marker, end] = buf.output("\n");
+        assert_eq!(content.len(), FMT_BUF_SIZE);
+        assert_eq!((marker, end), (TRUNCATION_MARKER, "\n"));
+    }
+}
@@ -1,36 +1,82 @@
 #![no_std]
 #![no_main]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 mod arch;
+mod backtrace;
+mod boottime;
+mod crash;
+mod crc32;
+// Nothing handles secrets yet, the helpers are only built for their tests until then
+#[cfg(feature = "run-tests")]
+mod crypto_util;
+mod debug;
 mod driver;
+mod dtb;
+mod elf;
+#[cfg(feature = "alloc")]
+mod heap;
+mod image;
+mod init;
+mod init_check;
+mod ktest;
 mod logger;
+mod memory_layout;
+mod payload;
+mod percpu;
 mod platform;
+mod profile;
+mod rme;
+mod smccc;
+mod stack;
+mod sync;
+mod version;
+mod watchdog;
 
-use core::arch::global_asm;
+use core::arch::{asm, global_asm};
+use core::sync::atomic::AtomicU64;
 
 const STACK_SIZE: usize = 16 * 1024;
 
+/// The x0 to x3 registers handed over by the previous boot stage, saved by `_start`.
+static BOOT_ARGS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+/// The only type of dynamic relocation of the image, linked as a static PIE.
+const R_AARCH64_RELATIVE: u32 = 1027;
+
 // ———————————————————————————— Rust Entry Point ———————————————————————————— //
 
 #[unsafe(no_mangle)]
 fn main() -> ! {
-    logger::init();
-    log::info!("Hello, world!");
-    arch::feature::log_features();
-
-    if !arch::feature::has_rme() {
-        panic!("Hardware does not support RME");
+    let exceptions = init::exceptions();
+    let console = init::early_console(exceptions);
+    let memory = init::memory(console);
+    if memory.pauth_ready() {
+        // SAFETY: main never returns, and is not called by Rust code.
+        unsafe { arch::pauth::enable_el3() };
     }
+    let interrupts = init::interrupts(memory);
+    let timers = init::timers(interrupts);
+    let platform = init::platform(timers);
+    let booted = init::late(platform);
 
-    platform::exit_success();
+    // The watchdog stays armed: the payload is expected to power the system off.
+    payload::enter_next_stage(booted);
 }
 
 // ————————————————————————————— Panic Handler —————————————————————————————— //
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    log::error!("{info}");
-    platform::exit_failure();
+    #[cfg(feature = "run-tests")]
+    ktest::abort_current(ktest::Abort::Panic, format_args!("{info}"));
+    // Nothing else runs on this core from now on
+    unsafe { asm!("msr DAIFSet, #0b1111") };
+    // The logger might be locked by the panicking code, the report bypasses it
+    crash::dump_panic(info);
+    crash::halt(platform::EXIT_PANIC);
 }
 
 // —————————————————————————— Assembly Entry Point —————————————————————————— //
@@ -47,9 +93,43 @@ _start:
     // Mask all exceptions (Debug, SError, IRQ, FIQ) inherited from previous boot stage.
     msr DAIFSet, #0xf
 
+    // Keep the arguments of the previous boot stage until the BSS is cleared.
+    mov x19, x0
+    mov x20, x1
+    mov x21, x2
+    mov x22, x3
+
+    // The image may run from another address than the one it was linked at. The load offset is
+    // the difference between the runtime address of _start (PC-relative) and its link address
+    // (from the literal pool, which holds its link-time value until relocated).
+    adr x0, _start
+    ldr x1, =_start
+    sub x23, x0, x1
+    // ADRP-based addressing only survives page-aligned offsets
+    tst x23, #0xfff
+    b.ne boot_hang
+
+    // Apply the R_AARCH64_RELATIVE relocations, before anything reads an absolute address.
+    adrp x0, _rela_start
+    add x0, x0, :lo12:_rela_start
+    adrp x1, _rela_end
+    add x1, x1, :lo12:_rela_end
+relocate_loop:
+    cmp x0, x1
+    b.hs relocate_done
+    ldp x2, x3, [x0], #16           // r_offset, r_info
+    ldr x4, [x0], #8                // r_addend
+    cmp w3, #{r_aarch64_relative}
+    b.ne boot_hang
+    add x4, x4, x23
+    str x4, [x2, x23]
+    b relocate_loop
+relocate_done:
+
     // Set up the stack.
     // The stack grows downward, so sp = _stack_start + STACK_SIZE.
-    ldr x0, =_stack_start
+    adrp x0, _stack_start
+    add x0, x0, :lo12:_stack_start
     ldr x1, ={stack_size}
     add x1, x0, x1
     mov sp, x1
@@ -64,8 +144,10 @@ stack_fill_loop:
 stack_fill_done:
 
     // Zero-out the BSS section.
-    ldr x0, =_bss_start
-    ldr x1, =_bss_stop
+    adrp x0, _bss_start
+    add x0, x0, :lo12:_bss_start
+    adrp x1, _bss_stop
+    add x1, x1, :lo12:_bss_stop
 zero_bss_loop:
     cmp x0, x1
     b.hs zero_bss_done
@@ -73,10 +155,28 @@ zero_bss_loop:
     b zero_bss_loop
 zero_bss_done:
 
-    // Jump into Rust code.
+    adrp x0, {boot_args}
+    add x0, x0, :lo12:{boot_args}
+    stp x19, x20, [x0]
+    stp x21, x22, [x0, #16]
+    adrp x0, {load_offset}
+    str x23, [x0, :lo12:{load_offset}]
+
+    // Jump into Rust code, which checks the current EL. No EL3 register may be accessed before.
+    // The null frame pointer ends the chain of frame records.
+    mov x29, xzr
+    mov x30, xzr
     b {main}
+
+    // The image can't be relocated, and there is no way to report it yet.
+boot_hang:
+    wfe
+    b boot_hang
 "#,
     main = sym main,
+    boot_args = sym BOOT_ARGS,
+    load_offset = sym memory_layout::LOAD_OFFSET,
+    r_aarch64_relative = const R_AARCH64_RELATIVE,
     stack_size = const STACK_SIZE,
-    stack_pattern = const 0x0BAD_BED0_0BAD_BED0_u64,
+    stack_pattern = const stack::PATTERN,
 );
@@ -0,0 +1,167 @@
+//! The layout of the monitor image, as defined by the linker script.
+//!
+//! The linker script exports the boundaries of each section as symbols, this module turns them
+//! into address ranges. [validate] checks the assumptions the rest of the monitor makes about
+//! them: the MMU maps the text, rodata, and data with distinct permissions at page granularity,
+//! and the boot stub zeroes the BSS 16 bytes at a time.
+//!
+//! The image is position independent: the boot stub relocates it to wherever it was loaded. The
+//! symbols are referenced PC-relative, so the ranges are always the runtime addresses.
+
+use crate::STACK_SIZE;
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{AtomicIsize, Ordering};
+
+unsafe extern "C" {
+    static _image_start: u8;
+    static _text_end: u8;
+    static _rodata_start: u8;
+    static _rodata_end: u8;
+    static _data_start: u8;
+    static _bss_start: u8;
+    static _bss_stop: u8;
+    static _stack_start: u8;
+}
+
+/// Difference between the load and link addresses of the image, written by the boot stub.
+pub static LOAD_OFFSET: AtomicIsize = AtomicIsize::new(0);
+
+const PAGE_SIZE: usize = 0x1000;
+/// Alignment of the BSS bounds, the boot stub clears the BSS with 16-byte stores.
+const BSS_ALIGN: usize = 16;
+
+/// Returns the difference between the load and link addresses of the image.
+pub fn load_offset() -> isize {
+    LOAD_OFFSET.load(Ordering::Relaxed)
+}
+
+/// Returns the executable code, starting with the entry point.
+pub fn text() -> Range<usize> {
+    symbol(&raw const _image_start)..symbol(&raw const _text_end)
+}
+
+/// Returns the read-only data, embedded payloads included.
+pub fn rodata() -> Range<usize> {
+    symbol(&raw const _rodata_start)..symbol(&raw const _rodata_end)
+}
+
+/// Returns the initialized writable data.
+pub fn data() -> Range<usize> {
+    symbol(&raw const _data_start)..symbol(&raw const _bss_start)
+}
+
+/// Returns the zero-initialized data.
+pub fn bss() -> Range<usize> {
+    symbol(&raw const _bss_start)..symbol(&raw const _bss_stop)
+}
+
+/// Returns the boot stack.
+pub fn stack() -> Range<usize> {
+    let start = symbol(&raw const _stack_start);
+    start..start + STACK_SIZE
+}
+
+/// Returns the whole image, stack included.
+pub fn image() -> Range<usize> {
+    symbol(&raw const _image_start)..stack().end
+}
+
+fn symbol(symbol: *const u8) -> usize {
+    symbol as usize
+}
+
+/// A violated assumption about the layout.
+#[derive(Clone, Copy, Debug)]
+pub enum LayoutError {
+    /// A section doesn't start on the required boundary.
+    Misaligned {
+        section: &'static str,
+        addr: usize,
+        align: usize,
+    },
+    /// A section ends before it starts.
+    Inverted { section: &'static str },
+    /// A section overlaps the next one, or comes after it.
+    Overlap {
+        first: &'static str,
+        second: &'static str,
+    },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            LayoutError::Misaligned {
+                section,
+                addr,
+                align,
+            } => write!(f, "{section} bound {addr:#x} is not aligned to {align:#x}"),
+            LayoutError::Inverted { section } => write!(f, "{section} ends before it starts"),
+            LayoutError::Overlap { first, second } => {
+                write!(f, "{first} overlaps {second}, or comes after it")
+            }
+        }
+    }
+}
+
+/// Checks the alignment and ordering of the sections, logging each of them.
+pub fn validate() -> Result<(), LayoutError> {
+    let start = image().start;
+    log::debug!(
+        "Image loaded at {start:#x}, linked at {:#x}",
+        start.wrapping_sub(load_offset() as usize)
+    );
+    let sections = [
+        ("text", text()),
+        ("rodata", rodata()),
+        ("data", data()),
+        ("bss", bss()),
+        ("stack", stack()),
+    ];
+    for (section, range) in &sections {
+        log::debug!(
+            "{section:<6} {:#x}-{:#x} ({} bytes)",
+            range.start,
+            range.end,
+            range.len()
+        );
+        if range.end < range.start {
+            return Err(LayoutError::Inverted { section });
+        }
+    }
+
+    // Sections mapped with their own permissions, and the stack, start on a page
+    for (section, addr) in [
+        ("text", text().start),
+        ("rodata", rodata().start),
+        ("data", data().start),
+        ("stack", stack().start),
+    ] {
+        check_aligned(section, addr, PAGE_SIZE)?;
+    }
+    check_aligned("bss", bss().start, BSS_ALIGN)?;
+    check_aligned("bss", bss().end, BSS_ALIGN)?;
+
+    for pair in sections.windows(2) {
+        let [(first, a), (second, b)] = pair else {
+            unreachable!()
+        };
+        if a.end > b.start {
+            return Err(LayoutError::Overlap { first, second });
+        }
+    }
+    Ok(())
+}
+
+fn check_aligned(section: &'static str, addr: usize, align: usize) -> Result<(), LayoutError> {
+    if addr.is_multiple_of(align) {
+        Ok(())
+    } else {
+        Err(LayoutError::Misaligned {
+            section,
+            addr,
+            align,
+        })
+    }
+}
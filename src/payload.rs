@@ -0,0 +1,299 @@
+//! The lower-EL payloads: a next-stage image, or the test payloads embedded in the monitor image.
+
+use crate::arch::context::{self, World};
+use crate::arch::{self, ExceptionLevel, cache, feature};
+use crate::elf::Elf;
+use crate::image::{Image, ImageError};
+use crate::{dtb, init, platform, stack, watchdog};
+use core::arch::global_asm;
+use core::ops::Range;
+use core::{ptr, slice};
+
+unsafe extern "C" {
+    static _ns_payload_start: u8;
+    static _ns_payload_end: u8;
+    static _secure_payload_start: u8;
+    static _secure_payload_end: u8;
+    static _realm_payload_start: u8;
+    static _realm_payload_end: u8;
+}
+
+/// Enters the next-stage image if there is one, or the test payloads otherwise.
+///
+/// A corrupt image is reported and the monitor idles, rather than running something else.
+pub fn enter_next_stage(_: init::Late) -> ! {
+    let image = match find_image() {
+        Ok(Some(image)) => image,
+        Ok(None) => {
+            log::info!(
+                "No next-stage image at {:#x}, running the test payloads",
+                platform::BL33_IMAGE_BASE
+            );
+            enter_test_payloads();
+        }
+        Err(err) => {
+            log::error!("Invalid next-stage image: {err}");
+            idle();
+        }
+    };
+    log::info!(
+        "Next-stage image at {:#x} ({} bytes)",
+        platform::BL33_IMAGE_BASE,
+        image.size()
+    );
+    let entry = match verify_image(&image).and_then(|()| load_image(&image)) {
+        Ok(entry) => entry,
+        Err(err) => {
+            log::error!("Can not start the next-stage image: {err}");
+            idle();
+        }
+    };
+
+    // Unlike the test payloads, the next stage doesn't know about the watchdog
+    watchdog::disarm();
+    let el = if feature::has_el2() {
+        ExceptionLevel::El2
+    } else {
+        ExceptionLevel::El1
+    };
+    let dtb = dtb::reserved_region().map_or(0, |dtb| dtb.start);
+    arch::enter_lower_el(entry, dtb, el, World::NonSecure);
+}
+
+/// Looks for a next-stage image at its platform-defined address.
+///
+/// Returns `None` if there is no image magic there.
+fn find_image() -> Result<Option<Image<'static>>, ImageError> {
+    // SAFETY: the image slot is in the DRAM, which is always mapped, and not used by the monitor.
+    let slot = unsafe {
+        slice::from_raw_parts(
+            platform::BL33_IMAGE_BASE as *const u8,
+            platform::BL33_IMAGE_MAX_SIZE,
+        )
+    };
+    match Image::parse(slot) {
+        Ok(image) => Ok(Some(image)),
+        Err(ImageError::BadMagic) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Checks the next-stage image against its CRC.
+fn verify_image(image: &Image) -> Result<(), ImageError> {
+    image.verify()?;
+    log::debug!("Next-stage image CRC verified");
+    Ok(())
+}
+
+/// Copies the next-stage image to its load address, and returns its entry point.
+///
+/// The image must be in the non-secure DRAM, and not overwrite the DTB, the realm payload, or
+/// its own slot.
+fn load_image(image: &Image) -> Result<usize, ImageError> {
+    let slot = platform::BL33_IMAGE_BASE..platform::BL33_IMAGE_BASE + image.size();
+    let realm =
+        platform::REALM_PAYLOAD_BASE..platform::REALM_PAYLOAD_BASE + platform::REALM_PAYLOAD_SIZE;
+    let dtb = dtb::reserved_region().unwrap_or(0..0);
+    image.check_placement(
+        &(platform::DRAM_BASE..platform::DRAM_BASE + platform::DRAM_SIZE),
+        &[("image slot", slot), ("realm payload", realm), ("DTB", dtb)],
+    )?;
+
+    let range = image.load_range();
+    log::info!(
+        "Loading next-stage image at {:#x}-{:#x}",
+        range.start,
+        range.end
+    );
+    // SAFETY: the placement was checked against the memory in use.
+    Ok(unsafe { image.load() })
+}
+
+/// Waits for interrupts forever, when there is nothing to run.
+fn idle() -> ! {
+    log::warn!("Nothing to run, idling");
+    loop {
+        watchdog::idle_wait();
+        // The interrupts taken while idling ran on the monitor stack
+        stack::assert_not_overflowed();
+    }
+}
+
+/// Loads the test payloads and enters the realm one at EL1.
+///
+/// The realm payload immediately yields to the non-secure payload, which in turn starts the
+/// secure payload the first time it yields.
+fn enter_test_payloads() -> ! {
+    // SAFETY: the linker script places each payload between its start and end symbols.
+    let (ns_payload, secure_payload, realm_payload) = unsafe {
+        (
+            section(&raw const _ns_payload_start, &raw const _ns_payload_end),
+            section(
+                &raw const _secure_payload_start,
+                &raw const _secure_payload_end,
+            ),
+            section(
+                &raw const _realm_payload_start,
+                &raw const _realm_payload_end,
+            ),
+        )
+    };
+
+    load(secure_payload, platform::SECURE_PAYLOAD_BASE);
+    context::init(World::Secure, platform::SECURE_PAYLOAD_BASE, 0);
+    load(ns_payload, platform::NS_PAYLOAD_BASE);
+    context::init(World::NonSecure, platform::NS_PAYLOAD_BASE, 0);
+    let realm_entry = load_elf(
+        realm_payload,
+        platform::REALM_PAYLOAD_BASE..platform::REALM_PAYLOAD_BASE + platform::REALM_PAYLOAD_SIZE,
+    );
+    arch::enter_lower_el(realm_entry, 0, ExceptionLevel::El1, World::Realm);
+}
+
+/// Copies a payload to its load address.
+fn load(payload: &[u8], base: usize) {
+    log::info!("Loading payload ({} bytes) at {base:#x}", payload.len());
+    check_not_reserved(base..base + payload.len());
+
+    // SAFETY: the load addresses are reserved for payloads and not used by the monitor.
+    unsafe { ptr::copy_nonoverlapping(payload.as_ptr(), base as *mut u8, payload.len()) };
+
+    // The payloads start with their MMU off, their fetches bypass the caches.
+    cache::clean_dcache_range(base, payload.len());
+    cache::invalidate_icache_all();
+}
+
+/// Loads an ELF payload within the given region, and returns its entry point.
+///
+/// # Panics
+///
+/// Panics if the payload is not a valid ELF image, or doesn't fit in the region.
+fn load_elf(payload: &[u8], region: Range<usize>) -> usize {
+    log::info!(
+        "Loading ELF payload ({} bytes) in {:#x}-{:#x}",
+        payload.len(),
+        region.start,
+        region.end
+    );
+
+    check_not_reserved(region.clone());
+
+    // SAFETY: the region is reserved for the payload and not used by the monitor.
+    Elf::parse(payload)
+        .and_then(|elf| unsafe { elf.load(region) })
+        .unwrap_or_else(|err| panic!("Invalid ELF payload: {err}"))
+}
+
+/// Checks that a payload doesn't overwrite memory handed over by the previous boot stage.
+fn check_not_reserved(region: Range<usize>) {
+    if let Some(reserved) = dtb::reserved_region() {
+        assert!(
+            region.end <= reserved.start || reserved.end <= region.start,
+            "payload at {:#x}-{:#x} overlaps the DTB",
+            region.start,
+            region.end
+        );
+    }
+}
+
+/// Returns the bytes between two linker symbols.
+///
+/// # Safety
+///
+/// `start` and `end` must delimit a valid region of the image.
+unsafe fn section(start: *const u8, end: *const u8) -> &'static [u8] {
+    unsafe { slice::from_raw_parts(start, end.offset_from(start) as usize) }
+}
+
+// The test payloads.
+//
+// The realm payload yields to the non-secure payload right away, and is never resumed. It is
+// wrapped in a hand-made ELF image, with a page of BSS after the code.
+//
+// The non-secure payload queries the SMCCC version, then yields twice to the secure payload,
+// checking that its registers are preserved across world switches, and finally powers the system
+// off. The secure payload does the same checks each time it is resumed. On failure, the payloads
+// spin forever.
+//
+// Payloads are copied before being executed, they must be position independent.
+global_asm!(
+    r#"
+.pushsection .payload.ns, "a"
+.balign 4
+    // SMCCC_VERSION, expects 1.2
+    movz w0, #0x8000, lsl #16
+    smc #0
+    movz w1, #0x1, lsl #16
+    movk w1, #0x2
+    cmp w0, w1
+    b.ne 1f
+
+    movz x19, #0x4e53               // Marker
+    mov x21, #2                     // Number of round trips
+2:
+    movz w0, #0x8700, lsl #16       // L4SM_YIELD
+    smc #0
+    cbnz x0, 1f
+    movz x20, #0x4e53
+    cmp x19, x20
+    b.ne 1f
+    subs x21, x21, #1
+    b.ne 2b
+
+    // PSCI SYSTEM_OFF
+    movz w0, #0x8400, lsl #16
+    movk w0, #0x8
+    smc #0
+
+1:
+    wfe
+    b 1b
+.popsection
+
+.pushsection .payload.secure, "a"
+.balign 4
+    movz x19, #0x5345               // Marker
+2:
+    movz w0, #0x8700, lsl #16       // L4SM_YIELD
+    smc #0
+    cbnz x0, 1f
+    movz x20, #0x5345
+    cmp x19, x20
+    b.eq 2b
+
+1:
+    wfe
+    b 1b
+.popsection
+
+.pushsection .payload.realm, "a"
+.balign 8
+l4sm_realm_elf:
+    // ELF header: ELFCLASS64, ELFDATA2LSB, EV_CURRENT
+    .byte 0x7f, 0x45, 0x4c, 0x46, 2, 1, 1, 0
+    .quad 0
+    .hword 2, 183                   // ET_EXEC, EM_AARCH64
+    .word 1                         // EV_CURRENT
+    .quad {realm_base}              // Entry point
+    .quad l4sm_realm_phdr - l4sm_realm_elf
+    .quad 0                         // No section headers
+    .word 0                         // Flags
+    .hword 64, 56, 1, 64, 0, 0      // Header sizes and counts
+l4sm_realm_phdr:
+    .word 1, 5                      // PT_LOAD, readable and executable
+    .quad l4sm_realm_code - l4sm_realm_elf
+    .quad {realm_base}, {realm_base}
+    .quad l4sm_realm_code_end - l4sm_realm_code
+    .quad l4sm_realm_code_end - l4sm_realm_code + 0x1000
+    .quad 8                         // Alignment
+l4sm_realm_code:
+    movz w0, #0x8700, lsl #16       // L4SM_YIELD
+    smc #0
+1:
+    wfe
+    b 1b
+l4sm_realm_code_end:
+.popsection
+"#,
+    realm_base = const platform::REALM_PAYLOAD_BASE,
+);
@@ -0,0 +1,124 @@
+//! Per-CPU data, reachable through `TPIDR_EL3`.
+//!
+//! Each core stores the address of its own [PerCpu] block in `TPIDR_EL3` when it boots, and can
+//! then access it without taking a lock. Fields are only written by their owning core, but other
+//! cores may read them (e.g. for diagnostics): they are atomics accessed with relaxed ordering,
+//! which is enough given the single writer.
+
+use crate::arch::CoreId;
+use crate::ktest::kernel_test;
+use crate::platform;
+use core::arch::asm;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+/// The per-CPU data of all CPUs, indexed by linear CPU index.
+static PER_CPU: [PerCpu; platform::MAX_CPUS] = [const { PerCpu::new() }; platform::MAX_CPUS];
+
+/// Number of CPUs that booted, minus the ones turned off.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// Initializes the per-CPU data of the calling CPU, and makes it reachable through `TPIDR_EL3`.
+///
+/// Must be called on each CPU when it boots, before any other function of this module. The CPU
+/// then counts as online.
+pub fn init() {
+    let core = CoreId::current();
+    let index = core
+        .linear_index()
+        .unwrap_or_else(|| panic!("CPU {core} out of the platform topology"));
+    let percpu = &PER_CPU[index];
+    percpu.index.store(index, Ordering::Relaxed);
+    percpu.core.store(core.packed(), Ordering::Relaxed);
+    unsafe { asm!("msr TPIDR_EL3, {}", in(reg) percpu as *const PerCpu) };
+    ONLINE.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts the calling CPU as offline, unless it is the last one online. Returns `false` in that
+/// case.
+pub fn go_offline() -> bool {
+    ONLINE
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |online| {
+            (online > 1).then(|| online - 1)
+        })
+        .is_ok()
+}
+
+/// Returns the per-CPU data of the calling CPU.
+///
+/// # Panics
+///
+/// Panics if [init] has not been called on this CPU.
+pub fn current() -> &'static PerCpu {
+    try_current().expect("per-CPU data not initialized")
+}
+
+/// Returns the per-CPU data of the calling CPU, or `None` if [init] has not been called on this
+/// CPU yet.
+pub fn try_current() -> Option<&'static PerCpu> {
+    let ptr: usize;
+    unsafe { asm!("mrs {}, TPIDR_EL3", out(reg) ptr) };
+
+    // TPIDR_EL3 has an unknown reset value, check that it does point to one of the blocks.
+    let offset = ptr.wrapping_sub(PER_CPU.as_ptr() as usize);
+    let index = offset / size_of::<PerCpu>();
+    (index < platform::MAX_CPUS && offset.is_multiple_of(size_of::<PerCpu>()))
+        .then(|| &PER_CPU[index])
+}
+
+/// The data private to a CPU.
+#[repr(C)]
+pub struct PerCpu {
+    /// The linear index of the CPU.
+    index: AtomicUsize,
+    /// The affinity of the CPU, packed as by [CoreId::packed].
+    core: AtomicU32,
+    /// Number of SMCs handled by the CPU.
+    smc_count: AtomicU64,
+}
+
+impl PerCpu {
+    const fn new() -> Self {
+        Self {
+            index: AtomicUsize::new(0),
+            core: AtomicU32::new(0),
+            smc_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the linear index of the CPU.
+    pub fn index(&self) -> usize {
+        self.index.load(Ordering::Relaxed)
+    }
+
+    /// Returns the affinity of the CPU.
+    pub fn core(&self) -> CoreId {
+        CoreId::from_packed(self.core.load(Ordering::Relaxed))
+    }
+
+    /// Records a new SMC, and returns the number of SMCs handled so far (including this one).
+    pub fn count_smc(&self) -> u64 {
+        // Only the owning CPU writes the counter, a load and a store are enough.
+        let count = self.smc_count.load(Ordering::Relaxed) + 1;
+        self.smc_count.store(count, Ordering::Relaxed);
+        count
+    }
+}
+
+kernel_test! {
+    fn current_block_matches_core() {
+        // QEMU virt numbers the cores by Aff0
+        for index in 0..platform::MAX_CPUS {
+            let core = CoreId::from_mpidr(index as u64);
+            assert_eq!(core.linear_index(), Some(index), "CPU {core}");
+        }
+        let core = CoreId::from_mpidr(platform::MAX_CPUS as u64);
+        assert_eq!(core.linear_index(), None);
+
+        let core = CoreId::current();
+        let percpu = current();
+        assert_eq!(Some(percpu.index()), core.linear_index());
+        assert_eq!(percpu.core(), core);
+        assert!(core::ptr::eq(percpu, &PER_CPU[percpu.index()]));
+    }
+}
@@ -3,45 +3,281 @@
 //! All hardware-specific values (base addresses, memory layout, etc.) should be defined in this
 //! module to make porting to a new platform straightforward.
 
+use crate::arch::esr;
+use crate::arch::exception::ExceptionFrame;
+use crate::arch::timer::TimerAccessPolicy;
+use crate::arch::{CoreId, Topology};
+use crate::driver::gic::{Group, IrqConfig, Trigger};
+#[cfg(feature = "ns16550")]
+use crate::driver::ns16550::Ns16550;
+#[cfg(not(feature = "ns16550"))]
+use crate::driver::pl011::Pl011;
+use crate::ktest::kernel_test;
+use crate::logger::{self, emergency_log};
 use core::arch::asm;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-/// Base address of the secure world PL011 UART (UART1).
+/// Base address of the secure world UART (UART1).
 pub const UART1_BASE: usize = 0x0904_0000;
 
+/// Spacing of the 16550 registers, as a shift of the register index.
+#[cfg(feature = "ns16550")]
+const NS16550_REG_SHIFT: u32 = 2;
+
+/// The driver of the secure world UART.
+#[cfg(not(feature = "ns16550"))]
+pub type Uart = Pl011;
+/// The driver of the secure world UART.
+#[cfg(feature = "ns16550")]
+pub type Uart = Ns16550;
+
+/// Returns a driver for the secure world UART.
+///
+/// # Safety
+///
+/// The driver doesn't synchronize accesses to the UART, concurrent users must coordinate.
+pub const unsafe fn secure_uart() -> Uart {
+    #[cfg(not(feature = "ns16550"))]
+    let uart = unsafe { Pl011::new(UART1_BASE) };
+    // Keep the baud rate set up by the previous stage
+    #[cfg(feature = "ns16550")]
+    let uart = unsafe { Ns16550::new(UART1_BASE, NS16550_REG_SHIFT, None) };
+    uart
+}
+
+/// Access of the lower ELs to the counters and timers, applied when entering them.
+pub const LOWER_EL_TIMER_ACCESS: TimerAccessPolicy = TimerAccessPolicy {
+    el1_physical_counter: true,
+    el1_physical_timer: true,
+    secure_el1_timer: false,
+};
+
+/// Base address of the GICv3 distributor.
+pub const GICD_BASE: usize = 0x0800_0000;
+
+/// Base address of the GICv3 redistributors.
+pub const GICR_BASE: usize = 0x080A_0000;
+
+/// Start of the MMIO window holding the GIC, UARTs, and other on-chip devices.
+pub const DEVICE_BASE: usize = 0x0800_0000;
+
+/// Size of the device MMIO window.
+pub const DEVICE_SIZE: usize = 0x0600_0000;
+
+/// Base address of the secure RAM, which holds the monitor image.
+pub const SECURE_RAM_BASE: usize = 0x0e00_0000;
+
+/// Size of the secure RAM.
+pub const SECURE_RAM_SIZE: usize = 0x0100_0000;
+
+/// Base address of the DRAM.
+pub const DRAM_BASE: usize = 0x4000_0000;
+
+/// Size of the DRAM, must match the memory size given to QEMU.
+pub const DRAM_SIZE: usize = 1204 * 1024 * 1024;
+
+/// Non-secure DRAM address where the non-secure payload is loaded.
+pub const NS_PAYLOAD_BASE: usize = 0x6000_0000;
+
+/// Secure RAM address where the secure payload is loaded.
+pub const SECURE_PAYLOAD_BASE: usize = 0x0e40_0000;
+
+/// DRAM address where the realm payload is loaded.
+pub const REALM_PAYLOAD_BASE: usize = 0x6100_0000;
+
+/// Size of the DRAM region reserved for the realm payload.
+pub const REALM_PAYLOAD_SIZE: usize = 0x0100_0000;
+
+/// Non-secure DRAM address where the previous boot stage (or QEMU's generic loader) places the
+/// next-stage image, see the `image` module.
+pub const BL33_IMAGE_BASE: usize = 0x6200_0000;
+
+/// Maximum size of the next-stage image, header included.
+pub const BL33_IMAGE_MAX_SIZE: usize = 0x0200_0000;
+
+/// Secure RAM address of the heap, between the monitor image and the secure payload.
+#[cfg(feature = "alloc")]
+pub const HEAP_BASE: usize = 0x0e30_0000;
+
+/// Size of the heap.
+#[cfg(feature = "alloc")]
+pub const HEAP_SIZE: usize = 0x0010_0000;
+
+/// An address of the device window with nothing behind it (in the platform bus), accesses to it
+/// cause an external abort.
+#[cfg(feature = "inject-serror")]
+pub const ABORT_TEST_ADDR: usize = 0x0d00_0000;
+
+/// Interrupt ID of the secure physical timer (PPI 13).
+pub const SECURE_TIMER_INTID: u32 = 29;
+
+/// Interrupt IDs of the non-secure EL1 physical and virtual timers (PPIs 14 and 11).
+const NS_PHYSICAL_TIMER_INTID: u32 = 30;
+const VIRTUAL_TIMER_INTID: u32 = 27;
+
+const fn irq(priority: u8, trigger: Trigger, group: Group) -> IrqConfig {
+    IrqConfig {
+        priority,
+        trigger,
+        group,
+        target: None,
+    }
+}
+
+/// The interrupts configured by the monitor, on each core.
+///
+/// The secure timer drives the watchdog. The lower-EL timers are given to the normal world, the
+/// GIC would otherwise signal them to EL3 as Group 0 interrupts.
+pub const INTERRUPT_MAP: &[(u32, IrqConfig)] = &[
+    (SECURE_TIMER_INTID, irq(0x10, Trigger::Level, Group::G0)),
+    // SGIs 8 to 15 are reserved for the secure world
+    (8, irq(0x20, Trigger::Edge, Group::G1S)),
+    (9, irq(0x20, Trigger::Edge, Group::G1S)),
+    (10, irq(0x20, Trigger::Edge, Group::G1S)),
+    (11, irq(0x20, Trigger::Edge, Group::G1S)),
+    (12, irq(0x20, Trigger::Edge, Group::G1S)),
+    (13, irq(0x20, Trigger::Edge, Group::G1S)),
+    (14, irq(0x20, Trigger::Edge, Group::G1S)),
+    (15, irq(0x20, Trigger::Edge, Group::G1S)),
+    (
+        NS_PHYSICAL_TIMER_INTID,
+        irq(0x80, Trigger::Level, Group::G1NS),
+    ),
+    (VIRTUAL_TIMER_INTID, irq(0x80, Trigger::Level, Group::G1NS)),
+];
+
+/// Maximum number of CPUs supported on the platform.
+pub const MAX_CPUS: usize = 8;
+
+/// The layout of the cores.
+///
+/// QEMU assigns Aff0 within clusters of 16 CPUs when using a GICv3, without multithreading.
+const TOPOLOGY: Topology = Topology {
+    multithreaded: false,
+    threads_per_core: 1,
+    cores_per_cluster: 16,
+    clusters: MAX_CPUS.div_ceil(16),
+};
+
+/// Returns the linear index of a core, between 0 and `MAX_CPUS`.
+///
+/// Returns `None` for cores outside of the platform topology.
+pub fn linear_index(core: CoreId) -> Option<usize> {
+    TOPOLOGY
+        .linear_index(core)
+        .filter(|&index| index < MAX_CPUS)
+}
+
+/// Exit code of a generic failure.
+const EXIT_FAILURE: u64 = 1;
+/// Exit code when an SError is taken.
+pub const EXIT_SERROR: u64 = 2;
+/// Exit code after a panic, distinct from the number of failed tests of a test run.
+pub const EXIT_PANIC: u64 = 101;
+/// Exit code after an unhandled exception.
+pub const EXIT_FAULT: u64 = 102;
+
 /// Exits the emulator with a success.
 pub fn exit_success() -> ! {
-    semihosting_exit(true);
+    semihosting_exit(0);
 }
 
 /// Exits the emulator with a failure.
 pub fn exit_failure() -> ! {
-    semihosting_exit(false);
+    semihosting_exit(EXIT_FAILURE);
 }
 
+/// Exits the emulator with a failure, reporting a specific exit code.
+pub fn exit_failure_code(code: u64) -> ! {
+    semihosting_exit(code);
+}
+
+/// Set once an exit started.
+static EXITING: AtomicBool = AtomicBool::new(false);
+/// Set while the semihosting call of an exit runs.
+static IN_SEMIHOSTING_CALL: AtomicBool = AtomicBool::new(false);
+
 /// Exits via ARM semihosting.
-fn semihosting_exit(success: bool) -> ! {
+///
+/// Without semihosting, the call is UNDEFINED: the sync handler skips it (see
+/// [skip_failed_semihosting]), and we print an exit marker and spin.
+fn semihosting_exit(code: u64) -> ! {
     // ARM semihosting constants.
     const SYS_EXIT: u64 = 0x18;
     const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
 
-    let code = if success { 0u64 } else { 1u64 };
-    let params: [u64; 2] = [ADP_STOPPED_APPLICATION_EXIT, code];
-    unsafe {
-        asm!(
-            "hlt #0xf000",
-            in("x0") SYS_EXIT,
-            in("x1") params.as_ptr(),
-            // No "options(noreturn)" here in case semihosting is not enabled
-        );
+    // Don't cut the last messages short
+    logger::flush_uart();
+
+    // A fault or the watchdog can exit again while we spin, only the first exit tries semihosting
+    let reentered = EXITING.swap(true, Ordering::SeqCst);
+    if !reentered {
+        let params: [u64; 2] = [ADP_STOPPED_APPLICATION_EXIT, code];
+        IN_SEMIHOSTING_CALL.store(true, Ordering::SeqCst);
+        unsafe {
+            asm!(
+                "hlt #0xf000",
+                in("x0") SYS_EXIT,
+                in("x1") params.as_ptr(),
+                // No "options(noreturn)" here in case semihosting is not enabled
+            );
+        }
+        IN_SEMIHOSTING_CALL.store(false, Ordering::SeqCst);
     }
 
-    // Semihosting is not enabled, let's spin here forever.
-    if success {
-        log::info!("Exit success, spinning forever...")
-    } else {
-        log::info!("Exit failure, spinning forever...")
+    // Semihosting is not enabled, let's spin here forever. The logger may be locked by the code
+    // that failed, it is bypassed.
+    let status = if code == 0 { "success" } else { "failure" };
+    match exit_marker(logger::is_initialized(), reentered) {
+        ExitMarker::Log => emergency_log(format_args!("Exit {status} ({code}), spinning forever")),
+        ExitMarker::Early => logger::early_print(format_args!("EXIT {status} ({code})\n")),
+        ExitMarker::None => {}
     }
+    logger::flush_uart();
     loop {
         core::hint::spin_loop();
     }
 }
+
+/// Resumes after the semihosting call of an exit if it is UNDEFINED, because semihosting is not
+/// enabled.
+///
+/// Must be called on synchronous exceptions taken from EL3. Returns `true` if the exception was
+/// the failed call, ELR then points to the next instruction.
+pub fn skip_failed_semihosting(frame: &mut ExceptionFrame) -> bool {
+    // UNDEFINED instructions are reported with the unknown exception class
+    if esr::decode(frame.esr).ec != 0 || !IN_SEMIHOSTING_CALL.swap(false, Ordering::SeqCst) {
+        return false;
+    }
+    frame.elr += 4;
+    true
+}
+
+/// How an exit that semihosting didn't handle is reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExitMarker {
+    /// As a log line, bypassing the logger lock.
+    Log,
+    /// Raw on the UART, before the logger is initialized.
+    Early,
+    /// Not at all, the exit in progress reports it.
+    None,
+}
+
+/// Returns how to report an exit that semihosting didn't handle, so that it is reported once.
+fn exit_marker(logger_initialized: bool, reentered: bool) -> ExitMarker {
+    match (reentered, logger_initialized) {
+        (true, _) => ExitMarker::None,
+        (false, true) => ExitMarker::Log,
+        (false, false) => ExitMarker::Early,
+    }
+}
+
+kernel_test! {
+    fn exit_marker_printed_once() {
+        assert_eq!(exit_marker(true, false), ExitMarker::Log);
+        assert_eq!(exit_marker(false, false), ExitMarker::Early);
+        assert_eq!(exit_marker(true, true), ExitMarker::None);
+        assert_eq!(exit_marker(false, true), ExitMarker::None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Lightweight profiling of code sections.
+//!
+//! A [Scope] measures the time between its creation and its drop, in cycles when there is a PMU
+//! and in ticks of the generic counter in any case. Measurements are accumulated per scope name in
+//! a fixed-size table, and printed by [report].
+
+use crate::arch::{pmu, timer};
+use crate::sync::IrqSafeMutex;
+
+/// Maximum number of distinct scope names.
+const MAX_ENTRIES: usize = 32;
+
+/// The accumulated measurements, one entry per scope name.
+static ENTRIES: IrqSafeMutex<[Entry; MAX_ENTRIES]> =
+    IrqSafeMutex::new([const { Entry::EMPTY }; MAX_ENTRIES]);
+
+/// The measurements of a scope name.
+#[derive(Clone, Copy)]
+struct Entry {
+    name: &'static str,
+    count: u64,
+    /// Total cycles, only counted with a PMU.
+    cycles: u64,
+    /// Total ticks of the generic counter.
+    ticks: u64,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        name: "",
+        count: 0,
+        cycles: 0,
+        ticks: 0,
+    };
+}
+
+/// Starts measuring a scope, the measurement is recorded when the returned guard is dropped.
+pub fn scope(name: &'static str) -> Scope {
+    Scope {
+        name,
+        start_cycles: pmu::cycles(),
+        start_ticks: timer::counter(),
+    }
+}
+
+/// A scope being measured, see [scope].
+#[must_use = "the scope ends when the guard is dropped"]
+pub struct Scope {
+    name: &'static str,
+    start_cycles: Option<u64>,
+    start_ticks: u64,
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let ticks = timer::counter() - self.start_ticks;
+        let cycles = match (self.start_cycles, pmu::cycles()) {
+            (Some(start), Some(end)) => end - start,
+            _ => 0,
+        };
+
+        let mut entries = ENTRIES.lock();
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.count == 0 || entry.name == self.name);
+        match entry {
+            Some(entry) => {
+                entry.name = self.name;
+                entry.count += 1;
+                entry.cycles += cycles;
+                entry.ticks += ticks;
+            }
+            None => log::warn!("Profiling table full, dropping scope '{}'", self.name),
+        }
+    }
+}
+
+/// Logs the measurements of all scopes, from the most expensive.
+pub fn report() {
+    // Sort a copy, to avoid logging with the lock held
+    let mut entries = *ENTRIES.lock();
+    entries.sort_unstable_by_key(|entry| core::cmp::Reverse((entry.cycles, entry.ticks)));
+
+    let ticks_per_us = (timer::frequency() / 1_000_000).max(1);
+    let has_cycles = pmu::cycles().is_some();
+    log::info!("Profile:");
+    for entry in entries.iter().filter(|entry| entry.count > 0) {
+        let us = entry.ticks / ticks_per_us;
+        if has_cycles {
+            log::info!(
+                "  {:<20} {:>4}x {:>12} cycles {us:>8} us",
+                entry.name,
+                entry.count,
+                entry.cycles
+            );
+        } else {
+            log::info!("  {:<20} {:>4}x {us:>8} us", entry.name, entry.count);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Granule Protection Table (GPT) inspection.
+//!
+//! The GPT assigns each physical granule to the PA spaces allowed to access it. This module only
+//! reads the table, as configured in `GPCCR_EL3` and `GPTBR_EL3`, to help debugging granule
+//! protection faults.
+//!
+//! Reference: Arm DDI 0615, Realm Management Extension.
+
+use super::PaSpace;
+use crate::ktest::kernel_test;
+use crate::platform;
+use core::arch::asm;
+use core::{fmt, ptr};
+
+// GPCCR_EL3 fields
+const GPCCR_PPS_MASK: u64 = 0b111;
+const GPCCR_PGS_SHIFT: u64 = 14;
+const GPCCR_GPC: u64 = 1 << 16;
+const GPCCR_L0GPTSZ_SHIFT: u64 = 20;
+
+// GPT descriptors
+const DESC_TYPE_MASK: u64 = 0xF;
+const DESC_BLOCK: u64 = 0b0001;
+const DESC_TABLE: u64 = 0b0011;
+const DESC_BLOCK_GPI_SHIFT: u64 = 4;
+const DESC_TABLE_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// What the GPT says about a granule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GptEntry {
+    /// Granule protection checks are disabled.
+    Disabled,
+    /// The address is beyond the protected physical address size.
+    OutOfRange,
+    /// The level 0 descriptor covering the address is invalid.
+    Invalid,
+    /// A descriptor is outside of the memory mapped at EL3, with its address.
+    Unmapped { desc: u64 },
+    /// The granule protection information, from a level 0 block or a level 1 table.
+    Gpi { gpi: u8, level: u8 },
+}
+
+/// Looks up the GPT entry of the granule containing `pa`.
+///
+/// The descriptors are only read if they are in secure RAM or DRAM, which are mapped at EL3: a
+/// corrupted GPTBR_EL3 or table descriptor could point anywhere.
+pub fn lookup(pa: u64) -> GptEntry {
+    let gpccr = gpccr_el3();
+    if gpccr & GPCCR_GPC == 0 {
+        return GptEntry::Disabled;
+    }
+
+    let pps_bits = match gpccr & GPCCR_PPS_MASK {
+        0b000 => 32,
+        0b001 => 36,
+        0b010 => 40,
+        0b011 => 42,
+        0b100 => 44,
+        0b101 => 48,
+        _ => 52,
+    };
+    if pa >> pps_bits != 0 {
+        return GptEntry::OutOfRange;
+    }
+
+    let pgs_bits = match (gpccr >> GPCCR_PGS_SHIFT) & 0b11 {
+        0b00 => 12,
+        0b10 => 14,
+        _ => 16,
+    };
+    let l0_bits = match (gpccr >> GPCCR_L0GPTSZ_SHIFT) & 0xF {
+        0b0000 => 30,
+        0b0100 => 34,
+        0b0110 => 36,
+        _ => 39,
+    };
+
+    let l0_base = (gptbr_el3() & 0xFF_FFFF_FFFF) << 12;
+    let l0_desc = match read_desc(l0_base + (pa >> l0_bits) * 8) {
+        Ok(desc) => desc,
+        Err(entry) => return entry,
+    };
+    match l0_desc & DESC_TYPE_MASK {
+        DESC_BLOCK => GptEntry::Gpi {
+            gpi: ((l0_desc >> DESC_BLOCK_GPI_SHIFT) & 0xF) as u8,
+            level: 0,
+        },
+        DESC_TABLE => {
+            // Each level 1 descriptor holds the 4-bit GPIs of 16 granules
+            let granule = (pa & ((1 << l0_bits) - 1)) >> pgs_bits;
+            let l1_desc = match read_desc((l0_desc & DESC_TABLE_ADDR_MASK) + (granule / 16) * 8) {
+                Ok(desc) => desc,
+                Err(entry) => return entry,
+            };
+            GptEntry::Gpi {
+                gpi: ((l1_desc >> ((granule % 16) * 4)) & 0xF) as u8,
+                level: 1,
+            }
+        }
+        _ => GptEntry::Invalid,
+    }
+}
+
+impl fmt::Display for GptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            GptEntry::Disabled => write!(f, "GPC disabled"),
+            GptEntry::OutOfRange => write!(f, "out of the protected range"),
+            GptEntry::Invalid => write!(f, "invalid level 0 descriptor"),
+            GptEntry::Unmapped { desc } => write!(f, "descriptor at {desc:#x} unmapped"),
+            GptEntry::Gpi { gpi, level } => {
+                let access = match gpi {
+                    0b0000 => "no access",
+                    0b1000 => "Secure",
+                    0b1001 => "Non-secure",
+                    0b1010 => "Root",
+                    0b1011 => "Realm",
+                    0b1111 => "any access",
+                    _ => "reserved",
+                };
+                write!(f, "GPI {gpi:#06b} ({access}), level {level}")
+            }
+        }
+    }
+}
+
+/// Returns the physical address and PA space of the last granule protection fault, from
+/// `MFAR_EL3`.
+pub fn fault_address() -> (u64, PaSpace) {
+    let mfar: u64;
+    // MFAR_EL3, encoded explicitly so that the assembler doesn't require FEAT_RME
+    unsafe { asm!("mrs {}, S3_6_C6_C0_5", out(reg) mfar) };
+    let nse = mfar & (1 << 63) != 0;
+    let ns = mfar & (1 << 62) != 0;
+    (mfar & 0x00FF_FFFF_FFFF_F000, PaSpace::from_nse_ns(nse, ns))
+}
+
+fn gpccr_el3() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, S3_6_C2_C1_6", out(reg) value) };
+    value
+}
+
+fn gptbr_el3() -> u64 {
+    let value: u64;
+    unsafe { asm!("mrs {}, S3_6_C2_C1_4", out(reg) value) };
+    value
+}
+
+/// Reads the descriptor at `addr`, if it is in memory mapped at EL3.
+fn read_desc(addr: u64) -> Result<u64, GptEntry> {
+    let secure_ram =
+        platform::SECURE_RAM_BASE..platform::SECURE_RAM_BASE + platform::SECURE_RAM_SIZE;
+    let dram = platform::DRAM_BASE..platform::DRAM_BASE + platform::DRAM_SIZE;
+    if !secure_ram.contains(&(addr as usize)) && !dram.contains(&(addr as usize)) {
+        return Err(GptEntry::Unmapped { desc: addr });
+    }
+    // SAFETY: secure RAM and DRAM are mapped at EL3, and descriptor addresses are aligned.
+    Ok(unsafe { ptr::read_volatile(addr as *const u64) })
+}
+
+kernel_test! {
+    fn reports_access_to_realm_granule() {
+        use crate::arch::feature;
+        use crate::ktest;
+
+        /// A level 0 table covering 4 GiB, 1 GiB per entry.
+        #[repr(C, align(4096))]
+        struct Level0([u64; 4]);
+        /// A level 1 table covering 1 GiB of 64 KiB granules, 16 per entry.
+        #[repr(C, align(8192))]
+        struct Level1([u64; 1024]);
+
+        const GPI_REALM: u64 = 0b1011;
+        const GPI_ANY: u64 = 0b1111;
+        const PPS_4GB: u64 = 0b000;
+        const PGS_64KB: u64 = 0b01 << GPCCR_PGS_SHIFT;
+        // Walks are inner shareable, write-back cacheable, like the mappings of the monitor
+        const GPCCR_WALK_ATTRS: u64 = (0b01 << 8) | (0b01 << 10) | (0b11 << 12);
+
+        if !feature::has_rme() || gpccr_el3() & GPCCR_GPC != 0 {
+            log::info!("No RME, or the GPT is already in use, skipping");
+            return;
+        }
+        if (gpccr_el3() >> GPCCR_L0GPTSZ_SHIFT) & 0xF != 0 {
+            log::info!("Level 0 GPT entries don't cover 1 GiB, skipping");
+            return;
+        }
+
+        // Every granule is accessible, except for a realm one in the second GiB, where DRAM starts
+        let addr = platform::REALM_PAYLOAD_BASE;
+        assert_eq!(addr >> 30, 1);
+        static mut LEVEL0: Level0 = Level0([0; 4]);
+        static mut LEVEL1: Level1 = Level1([u64::MAX; 1024]);
+        let block_any = (GPI_ANY << DESC_BLOCK_GPI_SHIFT) | DESC_BLOCK;
+        let granule = (addr & ((1 << 30) - 1)) >> 16;
+        let shift = (granule % 16) * 4;
+        // SAFETY: only this test uses the tables, and the GPT is disabled.
+        let level0 = unsafe {
+            let (level0, level1) = (&raw mut LEVEL0, &raw mut LEVEL1);
+            (*level1).0[granule / 16] = !(0xF << shift) | (GPI_REALM << shift);
+            (*level0).0 = [block_any, level1 as u64 | DESC_TABLE, block_any, block_any];
+            level0 as u64
+        };
+
+        let set_gpt = |gptbr: u64, gpccr: u64| unsafe {
+            asm!(
+                "dsb sy",
+                "msr S3_6_C2_C1_4, {gptbr}",
+                "msr S3_6_C2_C1_6, {gpccr}",
+                "isb",
+                "sys #6, c8, c7, #4", // TLBI PAALL
+                "dsb sy",
+                "isb",
+                gptbr = in(reg) gptbr,
+                gpccr = in(reg) gpccr,
+            );
+        };
+        let (saved_gptbr, saved_gpccr) = (gptbr_el3(), gpccr_el3());
+        set_gpt(level0 >> 12, PPS_4GB | PGS_64KB | GPCCR_WALK_ATTRS | GPCCR_GPC);
+        let entry = lookup(addr as u64);
+        let fault = ktest::expect_fault(|| unsafe {
+            asm!("ldr {}, [{}]", out(reg) _, in(reg) addr);
+        });
+        let (mfar, space) = fault_address();
+        set_gpt(saved_gptbr, saved_gpccr);
+
+        assert_eq!(entry, GptEntry::Gpi { gpi: GPI_REALM as u8, level: 1 });
+        let fault = fault.expect("the read of a realm granule didn't fault");
+        assert!(
+            fault.esr.is_granule_protection_fault(),
+            "unexpected fault: {}",
+            fault.esr
+        );
+        // The monitor accesses memory through the secure PA space
+        assert_eq!((mfar, space), (addr as u64, PaSpace::Secure));
+    }
+}
+
+kernel_test! {
+    fn descriptors_outside_memory_are_not_read() {
+        static DESC: u64 = 0x1234_5671;
+        let addr = &raw const DESC as u64;
+        assert_eq!(read_desc(addr), Ok(0x1234_5671));
+        // The UART, and an address beyond the DRAM
+        let uart = platform::UART1_BASE as u64;
+        assert_eq!(read_desc(uart), Err(GptEntry::Unmapped { desc: uart }));
+        let beyond = (platform::DRAM_BASE + platform::DRAM_SIZE) as u64;
+        assert_eq!(read_desc(beyond), Err(GptEntry::Unmapped { desc: beyond }));
+    }
+}
@@ -0,0 +1,37 @@
+//! Realm Management Extension (RME) support.
+
+pub mod gpt;
+
+use core::fmt;
+
+/// A physical address space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaSpace {
+    Secure,
+    NonSecure,
+    Root,
+    Realm,
+}
+
+impl PaSpace {
+    /// Returns the PA space selected by a pair of NSE and NS bits.
+    pub fn from_nse_ns(nse: bool, ns: bool) -> Self {
+        match (nse, ns) {
+            (false, false) => PaSpace::Secure,
+            (false, true) => PaSpace::NonSecure,
+            (true, false) => PaSpace::Root,
+            (true, true) => PaSpace::Realm,
+        }
+    }
+}
+
+impl fmt::Display for PaSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PaSpace::Secure => "Secure",
+            PaSpace::NonSecure => "Non-secure",
+            PaSpace::Root => "Root",
+            PaSpace::Realm => "Realm",
+        })
+    }
+}
@@ -0,0 +1,100 @@
+//! The Arm Architecture Service (SMCCC version, feature discovery, and CPU workarounds).
+
+use super::{FunctionId, NOT_SUPPORTED, SUCCESS, status};
+use crate::arch::errata;
+use crate::arch::midr::{CORTEX_A57, CORTEX_A72, Midr, NEOVERSE_N1};
+use crate::ktest::kernel_test;
+
+const SMCCC_VERSION: u32 = 0x8000_0000;
+const SMCCC_ARCH_FEATURES: u32 = 0x8000_0001;
+const SMCCC_ARCH_WORKAROUND_1: u32 = 0x8000_8000;
+const SMCCC_ARCH_WORKAROUND_2: u32 = 0x8000_7FFF;
+const SMCCC_ARCH_WORKAROUND_3: u32 = 0x8000_3FFF;
+
+/// The functions implemented by this service, as reported by `SMCCC_ARCH_FEATURES`.
+const IMPLEMENTED: [u32; 5] = [
+    SMCCC_VERSION,
+    SMCCC_ARCH_FEATURES,
+    SMCCC_ARCH_WORKAROUND_1,
+    SMCCC_ARCH_WORKAROUND_2,
+    SMCCC_ARCH_WORKAROUND_3,
+];
+
+/// The implemented SMCCC version: 1.2.
+const VERSION: u64 = (1 << 16) | 2;
+
+/// Returned by `SMCCC_ARCH_FEATURES` for a workaround that the calling PE does not need.
+const WORKAROUND_NOT_REQUIRED: i64 = 1;
+
+pub(super) fn handle(function: FunctionId, args: &[u64]) -> [u64; 4] {
+    if !IMPLEMENTED.contains(&function.0) {
+        return status(NOT_SUPPORTED);
+    }
+
+    match function.0 {
+        SMCCC_VERSION => [VERSION, 0, 0, 0],
+        SMCCC_ARCH_FEATURES => status(features(args[0] as u32)),
+        // The mitigations are not implemented, a call succeeds only when the PE doesn't need it
+        workaround => match features(workaround) {
+            NOT_SUPPORTED => status(NOT_SUPPORTED),
+            _ => status(SUCCESS),
+        },
+    }
+}
+
+/// Implements `SMCCC_ARCH_FEATURES` for the given function ID.
+fn features(function: u32) -> i64 {
+    if !IMPLEMENTED.contains(&function) {
+        return NOT_SUPPORTED;
+    }
+
+    match function {
+        SMCCC_ARCH_WORKAROUND_1 | SMCCC_ARCH_WORKAROUND_2 | SMCCC_ARCH_WORKAROUND_3 => {
+            if workaround_required(function) {
+                // We do not implement the mitigations done on each call
+                NOT_SUPPORTED
+            } else {
+                WORKAROUND_NOT_REQUIRED
+            }
+        }
+        _ => SUCCESS,
+    }
+}
+
+/// Returns `true` if the calling PE needs the firmware mitigation of the given workaround.
+fn workaround_required(workaround: u32) -> bool {
+    let midr = Midr::read();
+    match workaround {
+        // CVE-2017-5715, branch target injection
+        SMCCC_ARCH_WORKAROUND_1 => midr.is_any(&[CORTEX_A57, CORTEX_A72]),
+        // Speculative store bypass, unless it is disabled for good at boot
+        SMCCC_ARCH_WORKAROUND_2 => {
+            midr.is_any(&[CORTEX_A57, CORTEX_A72]) && !errata::is_active(errata::CVE_2018_3639)
+        }
+        // CVE-2022-23960, branch history injection
+        _ => midr.is_any(&[CORTEX_A57, CORTEX_A72, NEOVERSE_N1]),
+    }
+}
+
+kernel_test! {
+    fn calls_match_discovery() {
+        let workarounds = [
+            SMCCC_ARCH_WORKAROUND_1,
+            SMCCC_ARCH_WORKAROUND_2,
+            SMCCC_ARCH_WORKAROUND_3,
+        ];
+        for workaround in workarounds {
+            let discovered = features(workaround);
+            let result = handle(FunctionId(workaround), &[0; 17])[0] as i64;
+            if discovered == NOT_SUPPORTED {
+                assert_eq!(result, NOT_SUPPORTED, "{workaround:#x}");
+            } else {
+                assert_eq!(discovered, WORKAROUND_NOT_REQUIRED, "{workaround:#x}");
+                assert_eq!(result, SUCCESS, "{workaround:#x}");
+            }
+        }
+        assert_eq!(features(0x8000_0002), NOT_SUPPORTED);
+        assert_eq!(handle(FunctionId(0x8000_0002), &[0; 17])[0] as i64, NOT_SUPPORTED);
+        assert_eq!(features(SMCCC_VERSION), SUCCESS);
+    }
+}
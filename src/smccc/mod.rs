@@ -0,0 +1,174 @@
+//! SMC dispatcher following the Arm SMC Calling Convention (SMCCC).
+//!
+//! Lower ELs request services from the monitor through SMCs. The function ID in `w0` selects the
+//! service by its owning entity, and the registered handler for that entity computes the results
+//! returned in `x0` to `x3`.
+//!
+//! Reference: Arm DEN 0028, SMC Calling Convention.
+
+mod arch;
+mod psci;
+mod vendor;
+
+use crate::arch::exception::ExceptionFrame;
+use crate::ktest::kernel_test;
+use crate::sync::SpinLock;
+use crate::{percpu, watchdog};
+
+/// Success.
+pub const SUCCESS: i64 = 0;
+/// The function is not supported, or the function ID is invalid.
+pub const NOT_SUPPORTED: i64 = -1;
+
+/// A service handler.
+///
+/// Handlers receive the arguments in `x1` to `x17` and return the results for `x0` to `x3`.
+pub type Handler = fn(function: FunctionId, args: &[u64]) -> [u64; 4];
+
+/// The registered handlers, indexed by owning entity number.
+static SERVICES: SpinLock<[Option<Handler>; 64]> = SpinLock::new([None; 64]);
+
+/// Registers the built-in services.
+pub fn init() {
+    register(Owner::Arch, arch::handle);
+    register(Owner::StandardSecure, psci::handle);
+    register(Owner::VendorEl3Monitor, vendor::handle);
+}
+
+/// Registers the handler for the services of the given owning entity.
+///
+/// # Panics
+///
+/// Panics if a handler is already registered for that owner.
+pub fn register(owner: Owner, handler: Handler) {
+    let mut services = SERVICES.lock();
+    let slot = &mut services[owner as usize];
+    assert!(
+        slot.is_none(),
+        "SMC handler already registered for {owner:?}"
+    );
+    *slot = Some(handler);
+}
+
+/// Dispatches the SMC whose arguments are held in the exception frame and writes the results
+/// back into the frame.
+///
+/// `imm` is the immediate of the SMC instruction, only reported in the trace: SMCCC calls use
+/// `SMC #0`, and the function ID alone selects the service.
+pub fn dispatch(frame: &mut ExceptionFrame, imm: u16) {
+    let function = FunctionId(frame.arg(0) as u32);
+
+    let handler = if function.is_valid() {
+        // Release the lock before calling the handler
+        let services = SERVICES.lock();
+        services[function.owner() as usize]
+    } else {
+        None
+    };
+
+    // Serving calls is the monitor's main work, the watchdog is fed by each of them
+    watchdog::feed();
+    let percpu = percpu::current();
+    let count = percpu.count_smc();
+    log::trace!(
+        "SMC #{imm} {:#010x} (#{count} on CPU {})",
+        function.0,
+        percpu.core()
+    );
+    match handler {
+        Some(handler) => {
+            let results = handler(function, &frame.x[1..18]);
+            for (n, value) in results.into_iter().enumerate() {
+                frame.set_ret(n, value);
+            }
+        }
+        None => frame.set_ret(0, NOT_SUPPORTED as u64),
+    }
+}
+
+/// Returns the results of a call that only returns a status code.
+pub fn status(code: i64) -> [u64; 4] {
+    [code as u64, 0, 0, 0]
+}
+
+// —————————————————————————————— Function IDs —————————————————————————————— //
+
+/// An SMCCC function identifier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FunctionId(pub u32);
+
+impl FunctionId {
+    /// Returns `true` for fast calls, `false` for yielding calls.
+    pub fn is_fast(self) -> bool {
+        self.0 & (1 << 31) != 0
+    }
+
+    /// Returns the owning entity number.
+    pub fn owner(self) -> u8 {
+        ((self.0 >> 24) & 0x3F) as u8
+    }
+
+    /// Returns the function number within the owning entity.
+    pub fn number(self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Returns `true` if the function ID is well formed and uses a supported calling convention.
+    ///
+    /// Only fast calls are supported, and bits 23:17 must be zero (bit 16 is the SVE hint).
+    fn is_valid(self) -> bool {
+        self.is_fast() && self.0 & 0x00FE_0000 == 0
+    }
+}
+
+/// SMCCC owning entities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Owner {
+    Arch = 0,
+    StandardSecure = 4,
+    VendorEl3Monitor = 7,
+}
+
+kernel_test! {
+    fn dispatch_follows_smccc() {
+        // Dispatches a call with the given function ID and arguments, returns x0 to x3
+        let call = |function: u32, x1: u64, x2: u64| {
+            let mut x = [0; 31];
+            x[..3].copy_from_slice(&[function as u64, x1, x2]);
+            // Results that the dispatcher doesn't write would show up as this value
+            x[3] = 0xDEAD;
+            let mut frame = ExceptionFrame {
+                x,
+                sp_el0: 0,
+                elr: 0,
+                spsr: 0,
+                esr: 0,
+                far: 0,
+                apia: [0; 2],
+            };
+            dispatch(&mut frame, 0);
+            [frame.x[0], frame.x[1], frame.x[2], frame.x[3]]
+        };
+        let not_supported = NOT_SUPPORTED as u64;
+
+        // SMCCC_VERSION is 1.2
+        assert_eq!(call(0x8000_0000, 0, 0)[0], 0x1_0002);
+        // SiP service, not registered
+        assert_eq!(call(0x8200_0000, 0, 0)[0], not_supported);
+        // Yielding SMCCC_VERSION
+        assert_eq!(call(0x0000_0000, 0, 0)[0], not_supported);
+        // SMCCC_VERSION with one of bits 23:17 set
+        for reserved in 17..=23 {
+            assert_eq!(call(0x8000_0000 | (1 << reserved), 0, 0)[0], not_supported);
+        }
+
+        // Vendor BUILD_INFO, with an out of range selector and then offset
+        let invalid_parameter = -3i64 as u64;
+        assert_eq!(call(0x8700_0001, 5, 0)[0], invalid_parameter);
+        assert_eq!(call(0x8700_0001, u64::MAX, 0)[0], invalid_parameter);
+        let length = call(0x8700_0001, 0, 0)[0];
+        assert_eq!(length, crate::version::VERSION.len() as u64);
+        assert_eq!(call(0x8700_0001, 0, length)[..2], [length, 0]);
+        assert_eq!(call(0x8700_0001, 0, length + 1)[0], invalid_parameter);
+    }
+}
@@ -0,0 +1,45 @@
+//! Power State Coordination Interface (PSCI).
+//!
+//! Only the calls needed to shut down the system and turn cores off are implemented for now.
+
+use super::{FunctionId, NOT_SUPPORTED, status};
+use crate::arch::{CoreId, park};
+use crate::ktest::kernel_test;
+use crate::{percpu, platform, watchdog};
+
+const CPU_OFF: u32 = 0x8400_0002;
+const SYSTEM_OFF: u32 = 0x8400_0008;
+
+/// The call is not allowed.
+const DENIED: i64 = -3;
+
+pub(super) fn handle(function: FunctionId, _args: &[u64]) -> [u64; 4] {
+    match function.0 {
+        CPU_OFF => cpu_off(),
+        SYSTEM_OFF => {
+            log::info!("PSCI SYSTEM_OFF");
+            watchdog::disarm();
+            platform::exit_success();
+        }
+        _ => status(NOT_SUPPORTED),
+    }
+}
+
+/// Turns the calling core off, parking it until it is turned back on.
+///
+/// The last core online can't be turned off, the system would hang: SYSTEM_OFF must be used
+/// instead.
+fn cpu_off() -> [u64; 4] {
+    if !percpu::go_offline() {
+        return status(DENIED);
+    }
+    log::info!("PSCI CPU_OFF on CPU {}", CoreId::current());
+    park::park_secondary();
+}
+
+kernel_test! {
+    fn last_core_stays_on() {
+        // Secondary cores are never started, the boot core is the only one online
+        assert_eq!(handle(FunctionId(CPU_OFF), &[]), status(DENIED));
+    }
+}
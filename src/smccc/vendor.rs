@@ -0,0 +1,72 @@
+//! The l4sm vendor-specific EL3 monitor service.
+
+use super::{FunctionId, NOT_SUPPORTED, SUCCESS, status};
+use crate::arch::context;
+use crate::version;
+
+/// Yields to the other world, the call returns once the caller is resumed.
+const YIELD: u16 = 0x0000;
+/// Returns a slice of one of the build identification strings.
+const BUILD_INFO: u16 = 0x0001;
+/// Returns the UID of the service.
+const UID: u16 = 0xFF01;
+/// Returns the revision of the service.
+const REVISION: u16 = 0xFF03;
+
+/// The service UID: 6b3c5f0e-9a41-4d2b-8c77-14a5e2d0f3b9.
+const L4SM_UID: [u32; 4] = [0x0e5f_3c6b, 0x2b4d_419a, 0xa514_778c, 0xb9f3_d0e2];
+
+const REVISION_MAJOR: u64 = 0;
+const REVISION_MINOR: u64 = 2;
+
+/// SMCCC status for invalid arguments.
+const INVALID_PARAMETER: i64 = -3;
+
+/// The strings returned by `BUILD_INFO`, indexed by the selector in x1.
+const BUILD_INFO_STRINGS: [&str; 5] = [
+    version::VERSION,
+    version::COMMIT,
+    version::PROFILE,
+    version::RUSTC,
+    version::BUILD_DATE,
+];
+
+pub(super) fn handle(function: FunctionId, args: &[u64]) -> [u64; 4] {
+    match function.number() {
+        YIELD => {
+            if context::request_switch() {
+                status(SUCCESS)
+            } else {
+                // The other world is not running
+                status(NOT_SUPPORTED)
+            }
+        }
+        BUILD_INFO => build_info(args[0], args[1]),
+        UID => L4SM_UID.map(u64::from),
+        REVISION => [REVISION_MAJOR, REVISION_MINOR, 0, 0],
+        _ => status(NOT_SUPPORTED),
+    }
+}
+
+/// Returns the length of the string `selector` in x0, and up to 24 of its bytes from `offset`
+/// packed little-endian in x1-x3, zero-padded.
+///
+/// Selectors: 0 version, 1 commit, 2 profile, 3 compiler version, 4 build date. Callers read
+/// longer strings with successive calls.
+fn build_info(selector: u64, offset: u64) -> [u64; 4] {
+    let Some(string) = BUILD_INFO_STRINGS.get(selector as usize) else {
+        return status(INVALID_PARAMETER);
+    };
+    let bytes = string.as_bytes();
+    let Some(rest) = bytes.get(offset as usize..) else {
+        return status(INVALID_PARAMETER);
+    };
+
+    let mut results = [bytes.len() as u64, 0, 0, 0];
+    for (register, chunk) in results[1..].iter_mut().zip(rest.chunks(8)) {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        *register = u64::from_le_bytes(word);
+    }
+    results
+}
@@ -0,0 +1,80 @@
+//! Stack usage measurement and overflow detection.
+//!
+//! The boot stub fills the stack with [PATTERN] before anything runs on it, so words that still
+//! hold the pattern have never been used. The bottom words of the stack act as a guard: they are
+//! only overwritten once the stack overflowed, or is about to.
+
+use crate::ktest::kernel_test;
+use crate::logger::emergency_log;
+use crate::{STACK_SIZE, debug, memory_layout};
+use core::arch::asm;
+use core::ptr;
+
+/// The pattern the stack is filled with at boot.
+pub const PATTERN: u64 = 0x0BAD_BED0_0BAD_BED0;
+
+/// Number of words at the bottom of the stack that must never be used.
+const GUARD_WORDS: usize = 16;
+
+/// Returns the maximum number of bytes used on the stack so far.
+pub fn high_watermark() -> usize {
+    let unused = (0..STACK_SIZE / 8)
+        .take_while(|&i| word(i) == PATTERN)
+        .count();
+    STACK_SIZE - unused * 8
+}
+
+/// Returns `true` if at least `bytes` are left below the current stack pointer.
+pub fn remaining_at_least(bytes: usize) -> bool {
+    let sp: usize;
+    unsafe { asm!("mov {}, sp", out(reg) sp) };
+    sp.saturating_sub(memory_layout::stack().start) >= bytes
+}
+
+/// Returns `true` if the guard words at the bottom of the stack still hold the pattern.
+pub fn guard_intact() -> bool {
+    (0..GUARD_WORDS).all(|i| word(i) == PATTERN)
+}
+
+/// Stops at a breakpoint if the stack overflowed into its guard words.
+///
+/// A breakpoint rather than a panic, as the panic handler needs a lot more stack than the
+/// breakpoint handler.
+pub fn assert_not_overflowed() {
+    if !guard_intact() || !remaining_at_least(GUARD_WORDS * 8) {
+        emergency_log(format_args!("Stack overflow"));
+        debug::breakpoint!();
+    }
+}
+
+/// Reads the `index`-th word from the bottom of the stack.
+fn word(index: usize) -> u64 {
+    let bottom = memory_layout::stack().start as *const u64;
+    // SAFETY: the stack is STACK_SIZE bytes long, and `index` is always below STACK_SIZE / 8.
+    unsafe { ptr::read_volatile(bottom.add(index)) }
+}
+
+kernel_test! {
+    fn deeper_call_raises_high_watermark() {
+        /// Uses the stack down to `depth` bytes below its top.
+        #[inline(never)]
+        fn use_stack(depth: usize) {
+            let mut page = [0u8; 256];
+            core::hint::black_box(&mut page);
+            let sp: usize;
+            unsafe { asm!("mov {}, sp", out(reg) sp) };
+            if memory_layout::stack().end - sp < depth && remaining_at_least(1024) {
+                use_stack(depth);
+            }
+            core::hint::black_box(&mut page);
+        }
+
+        let before = high_watermark();
+        assert!(guard_intact());
+        assert!(before < STACK_SIZE / 2, "{before} bytes of stack already used");
+        use_stack(before + 1024);
+        let after = high_watermark();
+        assert!(after >= before + 1024, "high-watermark went from {before} to {after} bytes");
+        assert!(guard_intact());
+    }
+}
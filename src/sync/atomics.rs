@@ -0,0 +1,326 @@
+//! Atomic read-modify-write operations, implemented with either LL/SC or LSE instructions.
+//!
+//! Cores without the Large System Extensions (FEAT_LSE) only have load-exclusive/store-exclusive
+//! (LL/SC) loops, which can starve under contention. Cores with LSE have single atomic
+//! instructions instead. The implementation is selected once at boot by [init], before that the
+//! LL/SC implementation is used as it works on every core.
+//!
+//! The [SpinLock](super::SpinLock) acquires its lock with [swap] and [compare_exchange], so that
+//! the locks, and the [IrqSafeMutex](super::IrqSafeMutex) built on them, use the selected
+//! implementation too. An [Event](super::Event) is signaled with [fetch_or].
+//!
+//! The `atomics-llsc` and `atomics-lse` features force either implementation, for testing.
+
+use crate::arch::feature;
+use crate::ktest::kernel_test;
+use core::arch::asm;
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+#[cfg(all(feature = "atomics-llsc", feature = "atomics-lse"))]
+compile_error!("the atomics-llsc and atomics-lse features are mutually exclusive");
+
+/// An implementation of the atomic operations.
+struct Ops {
+    name: &'static str,
+    fetch_add: unsafe fn(*mut u64, u64) -> u64,
+    fetch_or: unsafe fn(*mut u64, u64) -> u64,
+    swap: unsafe fn(*mut u64, u64) -> u64,
+    /// Returns the previous value, the exchange happened if it is the expected one.
+    compare_exchange: unsafe fn(*mut u64, u64, u64) -> u64,
+}
+
+static LLSC: Ops = Ops {
+    name: "LL/SC",
+    fetch_add: fetch_add_llsc,
+    fetch_or: fetch_or_llsc,
+    swap: swap_llsc,
+    compare_exchange: compare_exchange_llsc,
+};
+
+static LSE: Ops = Ops {
+    name: "LSE",
+    fetch_add: fetch_add_lse,
+    fetch_or: fetch_or_lse,
+    swap: swap_lse,
+    compare_exchange: compare_exchange_lse,
+};
+
+/// The selected implementation.
+static SELECTED: AtomicPtr<Ops> = AtomicPtr::new(&LLSC as *const Ops as *mut Ops);
+
+/// Selects the implementation of the atomic operations.
+///
+/// LSE is used if the core implements it, unless a feature forces the implementation.
+///
+/// # Panics
+///
+/// Panics if LSE is forced but not implemented.
+pub fn init() {
+    let use_lse = if cfg!(feature = "atomics-llsc") {
+        false
+    } else if cfg!(feature = "atomics-lse") {
+        assert!(feature::has_lse(), "LSE atomics forced but not implemented");
+        true
+    } else {
+        feature::has_lse()
+    };
+
+    let ops = if use_lse { &LSE } else { &LLSC };
+    SELECTED.store(ops as *const Ops as *mut Ops, Ordering::Relaxed);
+    log::info!("Atomics: {}", ops.name);
+}
+
+/// Adds `value` to `atomic`, and returns the previous value.
+///
+/// The operation has acquire and release semantics.
+pub fn fetch_add(atomic: &AtomicU64, value: u64) -> u64 {
+    // SAFETY: the pointer comes from a valid atomic.
+    unsafe { (selected().fetch_add)(atomic.as_ptr(), value) }
+}
+
+/// Sets the bits of `value` in `atomic`, and returns the previous value.
+///
+/// The operation has acquire and release semantics.
+pub fn fetch_or(atomic: &AtomicU64, value: u64) -> u64 {
+    // SAFETY: the pointer comes from a valid atomic.
+    unsafe { (selected().fetch_or)(atomic.as_ptr(), value) }
+}
+
+/// Stores `value` into `atomic`, and returns the previous value.
+///
+/// The operation has acquire and release semantics.
+pub fn swap(atomic: &AtomicU64, value: u64) -> u64 {
+    // SAFETY: the pointer comes from a valid atomic.
+    unsafe { (selected().swap)(atomic.as_ptr(), value) }
+}
+
+/// Stores `new` into `atomic` if it holds `current`. Returns the previous value, as `Ok` if it was
+/// `current`.
+///
+/// The operation has acquire and release semantics, whether it succeeds or not.
+pub fn compare_exchange(atomic: &AtomicU64, current: u64, new: u64) -> Result<u64, u64> {
+    // SAFETY: the pointer comes from a valid atomic.
+    let old = unsafe { (selected().compare_exchange)(atomic.as_ptr(), current, new) };
+    if old == current { Ok(old) } else { Err(old) }
+}
+
+fn selected() -> &'static Ops {
+    // SAFETY: SELECTED always points to one of the static implementations.
+    unsafe { &*SELECTED.load(Ordering::Relaxed) }
+}
+
+// —————————————————————————————————— LL/SC —————————————————————————————————— //
+
+/// # Safety
+///
+/// `ptr` must be valid and 8-byte aligned.
+unsafe fn fetch_add_llsc(ptr: *mut u64, value: u64) -> u64 {
+    let old: u64;
+    unsafe {
+        asm!(
+            "2:",
+            "ldaxr {old}, [{ptr}]",
+            "add {new}, {old}, {value}",
+            "stlxr {status:w}, {new}, [{ptr}]",
+            "cbnz {status:w}, 2b",
+            ptr = in(reg) ptr,
+            value = in(reg) value,
+            old = out(reg) old,
+            new = out(reg) _,
+            status = out(reg) _,
+            options(nostack),
+        );
+    }
+    old
+}
+
+/// # Safety
+///
+/// `ptr` must be valid and 8-byte aligned.
+unsafe fn fetch_or_llsc(ptr: *mut u64, value: u64) -> u64 {
+    let old: u64;
+    unsafe {
+        asm!(
+            "2:",
+            "ldaxr {old}, [{ptr}]",
+            "orr {new}, {old}, {value}",
+            "stlxr {status:w}, {new}, [{ptr}]",
+            "cbnz {status:w}, 2b",
+            ptr = in(reg) ptr,
+            value = in(reg) value,
+            old = out(reg) old,
+            new = out(reg) _,
+            status = out(reg) _,
+            options(nostack),
+        );
+    }
+    old
+}
+
+/// # Safety
+///
+/// `ptr` must be valid and 8-byte aligned.
+unsafe fn swap_llsc(ptr: *mut u64, value: u64) -> u64 {
+    let old: u64;
+    unsafe {
+        asm!(
+            "2:",
+            "ldaxr {old}, [{ptr}]",
+            "stlxr {status:w}, {value}, [{ptr}]",
+            "cbnz {status:w}, 2b",
+            ptr = in(reg) ptr,
+            value = in(reg) value,
+            old = out(reg) old,
+            status = out(reg) _,
+            options(nostack),
+        );
+    }
+    old
+}
+
+/// # Safety
+///
+/// `ptr` must be valid and 8-byte aligned.
+unsafe fn compare_exchange_llsc(ptr: *mut u64, current: u64, new: u64) -> u64 {
+    let old: u64;
+    // On a mismatch, CLREX disarms the exclusive monitor armed by the load
+    unsafe {
+        asm!(
+            "2:",
+            "ldaxr {old}, [{ptr}]",
+            "cmp {old}, {current}",
+            "b.ne 3f",
+            "stlxr {status:w}, {new}, [{ptr}]",
+            "cbnz {status:w}, 2b",
+            "b 4f",
+            "3:",
+            "clrex",
+            "4:",
+            ptr = in(reg) ptr,
+            current = in(reg) current,
+            new = in(reg) new,
+            old = out(reg) old,
+            status = out(reg) _,
+            options(nostack),
+        );
+    }
+    old
+}
+
+// ——————————————————————————————————— LSE ——————————————————————————————————— //
+
+/// # Safety
+///
+/// `ptr` must be valid and 8-byte aligned, and the core must implement LSE.
+unsafe fn fetch_add_lse(ptr: *mut u64, value: u64) -> u64 {
+    let old: u64;
+    unsafe {
+        // The target doesn't assume LSE, enable the instructions for this block only
+        asm!(
+            ".arch_extension lse",
+            "ldaddal {value}, {old}, [{ptr}]",
+            ptr = in(reg) ptr,
+            value = in(reg) value,
+            old = out(reg) old,
+            options(nostack),
+        );
+    }
+    old
+}
+
+/// # Safety
+///
+/// `ptr` must be valid and 8-byte aligned, and the core must implement LSE.
+unsafe fn swap_lse(ptr: *mut u64, value: u64) -> u64 {
+    let old: u64;
+    unsafe {
+        asm!(
+            ".arch_extension lse",
+            "swpal {value}, {old}, [{ptr}]",
+            ptr = in(reg) ptr,
+            value = in(reg) value,
+            old = out(reg) old,
+            options(nostack),
+        );
+    }
+    old
+}
+
+/// # Safety
+///
+/// `ptr` must be valid and 8-byte aligned, and the core must implement LSE.
+unsafe fn fetch_or_lse(ptr: *mut u64, value: u64) -> u64 {
+    let old: u64;
+    unsafe {
+        asm!(
+            ".arch_extension lse",
+            "ldsetal {value}, {old}, [{ptr}]",
+            ptr = in(reg) ptr,
+            value = in(reg) value,
+            old = out(reg) old,
+            options(nostack),
+        );
+    }
+    old
+}
+
+/// # Safety
+///
+/// `ptr` must be valid and 8-byte aligned, and the core must implement LSE.
+unsafe fn compare_exchange_lse(ptr: *mut u64, current: u64, new: u64) -> u64 {
+    let old: u64;
+    unsafe {
+        asm!(
+            ".arch_extension lse",
+            "casal {old}, {new}, [{ptr}]",
+            ptr = in(reg) ptr,
+            new = in(reg) new,
+            old = inout(reg) current => old,
+            options(nostack),
+        );
+    }
+    old
+}
+
+kernel_test! {
+    fn both_implementations_work() {
+        let implementations: &[&Ops] = if feature::has_lse() {
+            &[&LLSC, &LSE]
+        } else {
+            &[&LLSC]
+        };
+        for ops in implementations {
+            let atomic = AtomicU64::new(40);
+            // SAFETY: the pointer comes from a valid atomic, LSE is only used when implemented.
+            unsafe {
+                assert_eq!((ops.fetch_add)(atomic.as_ptr(), 2), 40, "{}", ops.name);
+                assert_eq!((ops.swap)(atomic.as_ptr(), 7), 42, "{}", ops.name);
+                assert_eq!((ops.fetch_add)(atomic.as_ptr(), u64::MAX), 7, "{}", ops.name);
+                assert_eq!((ops.fetch_or)(atomic.as_ptr(), 0b1001), 6, "{}", ops.name);
+                // A failed exchange leaves the value alone
+                assert_eq!((ops.compare_exchange)(atomic.as_ptr(), 6, 1), 15, "{}", ops.name);
+                assert_eq!((ops.compare_exchange)(atomic.as_ptr(), 15, 3), 15, "{}", ops.name);
+            }
+            assert_eq!(atomic.load(Ordering::Relaxed), 3, "{}", ops.name);
+        }
+    }
+}
+
+kernel_test! {
+    fn selection_follows_features() {
+        let expected = if cfg!(feature = "atomics-llsc") {
+            "LL/SC"
+        } else if cfg!(feature = "atomics-lse") || feature::has_lse() {
+            "LSE"
+        } else {
+            "LL/SC"
+        };
+        assert_eq!(selected().name, expected);
+
+        let atomic = AtomicU64::new(1);
+        assert_eq!(compare_exchange(&atomic, 1, 2), Ok(1));
+        assert_eq!(compare_exchange(&atomic, 1, 3), Err(2));
+        assert_eq!(fetch_or(&atomic, 4), 2);
+        assert_eq!(atomic.load(Ordering::Relaxed), 6);
+    }
+}
@@ -0,0 +1,312 @@
+//! Synchronization primitives that are safe to use with interrupts enabled.
+//!
+//! A plain spinlock deadlocks if an interrupt handler tries to take a lock held by the code it
+//! interrupted. The primitives in this module mask IRQs and FIQs for as long as the lock is held,
+//! and then restore the exact previous mask, so that they can be nested and used before
+//! interrupts are ever enabled. The [SpinLock] they are built on doesn't, and is only meant for
+//! locks never taken from interrupt handlers.
+//!
+//! Waiting is done with WFE rather than by spinning hot, producers wake waiters up with SEV. Waits
+//! bounded by a deadline also enable the timer event stream, which wakes them up to check the
+//! counter.
+
+pub mod atomics;
+
+use crate::arch::timer;
+use crate::ktest::kernel_test;
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Runs `f` with IRQs and FIQs masked.
+pub fn critical_section<R>(f: impl FnOnce(&CriticalSection) -> R) -> R {
+    let daif = mask_interrupts();
+    let result = f(&CriticalSection {
+        _private: PhantomData,
+    });
+    restore_interrupts(daif);
+    result
+}
+
+/// A token proving that interrupts are masked.
+pub struct CriticalSection {
+    _private: PhantomData<*const ()>,
+}
+
+// ——————————————————————————————— Spinlocks ———————————————————————————————— //
+
+/// A spinlock, acquired with the atomic operations selected at boot (see [atomics]).
+///
+/// Contended acquisitions wait with WFE, releases wake the waiters up.
+pub struct SpinLock<T> {
+    locked: AtomicU64,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: the lock grants exclusive access to the value.
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicU64::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires the lock, waiting for the holder to release it.
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        while atomics::swap(&self.locked, 1) != 0 {
+            spin_wait_until(|| self.locked.load(Ordering::Relaxed) == 0);
+        }
+        SpinLockGuard { lock: self }
+    }
+
+    /// Tries to acquire the lock without waiting.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        // Unlike a swap, a failed exchange doesn't write to the lock
+        atomics::compare_exchange(&self.locked, 0, 1)
+            .ok()
+            .map(|_| SpinLockGuard { lock: self })
+    }
+}
+
+/// A guard granting access to the content of a [SpinLock], which is released on drop.
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds the lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(0, Ordering::Release);
+        wake_all();
+    }
+}
+
+/// A spinlock that masks interrupts while held.
+pub struct IrqSafeMutex<T> {
+    inner: SpinLock<T>,
+}
+
+impl<T> IrqSafeMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: SpinLock::new(value),
+        }
+    }
+
+    /// Masks interrupts and acquires the lock, interrupts are restored when the guard is dropped.
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, T> {
+        let daif = mask_interrupts();
+        IrqSafeMutexGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            daif,
+        }
+    }
+
+    /// Tries to acquire the lock without spinning, for code that can't wait for the holder, such
+    /// as fatal error reporting.
+    pub fn try_lock(&self) -> Option<IrqSafeMutexGuard<'_, T>> {
+        let daif = mask_interrupts();
+        match self.inner.try_lock() {
+            Some(guard) => Some(IrqSafeMutexGuard {
+                guard: ManuallyDrop::new(guard),
+                daif,
+            }),
+            None => {
+                restore_interrupts(daif);
+                None
+            }
+        }
+    }
+}
+
+/// A guard granting access to the content of an [IrqSafeMutex].
+pub struct IrqSafeMutexGuard<'a, T> {
+    guard: ManuallyDrop<SpinLockGuard<'a, T>>,
+    /// DAIF before the lock was acquired.
+    daif: u64,
+}
+
+impl<T> Deref for IrqSafeMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqSafeMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqSafeMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // The lock must be released before interrupts are unmasked.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        restore_interrupts(self.daif);
+    }
+}
+
+// ———————————————————————————————— Waiting ————————————————————————————————— //
+
+/// Waits until `cond` returns `true`, sleeping between checks.
+///
+/// The wait relies on the producer calling [wake_all] after making `cond` true, exception returns
+/// also wake the waiter up. A wake-up between a check and the sleep is not lost: it sets the event
+/// register, and the sleep returns immediately.
+pub fn spin_wait_until(cond: impl Fn() -> bool) {
+    while !cond() {
+        unsafe { asm!("wfe") };
+    }
+}
+
+/// Wakes up all the cores waiting in [spin_wait_until].
+///
+/// Memory writes done before the call are visible to the woken up cores.
+pub fn wake_all() {
+    // The DSB orders the preceding writes before the event
+    unsafe { asm!("dsb ish", "sev") };
+}
+
+/// A one-shot event, that cores can wait for until it is signaled.
+pub struct Event {
+    /// Non-zero once signaled.
+    signaled: AtomicU64,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Self {
+            signaled: AtomicU64::new(0),
+        }
+    }
+
+    /// Signals the event, waking up the waiters.
+    ///
+    /// Memory writes done before signaling are visible to the cores that observe the event.
+    pub fn signal(&self) {
+        atomics::fetch_or(&self.signaled, 1);
+        wake_all();
+    }
+
+    /// Returns `true` if the event has been signaled.
+    pub fn is_signaled(&self) -> bool {
+        self.signaled.load(Ordering::Acquire) != 0
+    }
+
+    /// Waits until the event is signaled.
+    // Nothing waits without a deadline until secondary cores are released, only built for the
+    // tests until then
+    #[cfg(feature = "run-tests")]
+    pub fn wait(&self) {
+        spin_wait_until(|| self.is_signaled());
+    }
+
+    /// Waits until the event is signaled, or until the counter reaches `deadline`. Returns `true`
+    /// if the event was signaled.
+    ///
+    /// The event stream wakes the sleep up regularly, the deadline is missed by about 10 µs at
+    /// most.
+    pub fn wait_until(&self, deadline: u64) -> bool {
+        timer::with_event_stream(|| {
+            spin_wait_until(|| self.is_signaled() || timer::counter() >= deadline)
+        });
+        self.is_signaled()
+    }
+}
+
+// ————————————————————————————— Interrupt Mask ————————————————————————————— //
+
+/// Masks IRQs and FIQs, and returns the previous value of DAIF.
+fn mask_interrupts() -> u64 {
+    let daif: u64;
+    unsafe { asm!("mrs {}, DAIF", "msr DAIFSet, #0b0011", out(reg) daif) };
+    daif
+}
+
+/// Restores a value of DAIF returned by [mask_interrupts].
+fn restore_interrupts(daif: u64) {
+    unsafe { asm!("msr DAIF, {}", in(reg) daif) };
+}
+
+kernel_test! {
+    fn spinlock_excludes() {
+        let lock = SpinLock::new(1);
+        let mut guard = lock.lock();
+        *guard += 1;
+        assert!(lock.try_lock().is_none());
+        drop(guard);
+        let guard = lock.try_lock().expect("lock not released");
+        assert_eq!(*guard, 2);
+    }
+}
+
+kernel_test! {
+    fn event_wait_is_bounded() {
+        let event = Event::new();
+        let start = timer::counter();
+        let one_ms = timer::frequency() / 1000;
+        assert!(!event.wait_until(start + one_ms));
+        let elapsed = timer::counter() - start;
+        assert!(elapsed >= one_ms && elapsed < 50 * one_ms, "waited {elapsed} ticks");
+
+        event.signal();
+        event.wait();
+        assert!(event.wait_until(0));
+    }
+}
+
+kernel_test! {
+    fn nested_guards_restore_daif() {
+        // DAIF.I and DAIF.F
+        const IRQ_FIQ: u64 = 0b11 << 6;
+        let read_daif = || {
+            let daif: u64;
+            unsafe { asm!("mrs {}, DAIF", out(reg) daif) };
+            daif
+        };
+        let outer = IrqSafeMutex::new(());
+        let inner = IrqSafeMutex::new(());
+
+        let saved = read_daif();
+        for initial in [saved | IRQ_FIQ, saved & !IRQ_FIQ] {
+            restore_interrupts(initial);
+            let first = outer.lock();
+            assert_eq!(read_daif() & IRQ_FIQ, IRQ_FIQ);
+            critical_section(|_| {
+                let second = inner.lock();
+                drop(second);
+                // Still in the critical section
+                assert_eq!(read_daif() & IRQ_FIQ, IRQ_FIQ);
+            });
+            // Still holding the first lock
+            assert_eq!(read_daif() & IRQ_FIQ, IRQ_FIQ);
+            drop(first);
+            assert_eq!(read_daif(), initial, "DAIF not restored");
+        }
+        restore_interrupts(saved);
+    }
+}
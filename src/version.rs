@@ -0,0 +1,28 @@
+//! Identification of the monitor build, embedded by the build script.
+
+use core::fmt;
+
+/// Version of the crate.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short hash of the commit the monitor was built from, suffixed with "-dirty" if the working
+/// tree had uncommitted changes, "unknown" outside of a git checkout.
+pub const COMMIT: &str = env!("L4SM_GIT_COMMIT");
+/// Cargo profile, "debug" or "release".
+pub const PROFILE: &str = env!("L4SM_PROFILE");
+/// Version of the compiler.
+pub const RUSTC: &str = env!("L4SM_RUSTC_VERSION");
+/// Build date, YYYY-MM-DD in UTC.
+pub const BUILD_DATE: &str = env!("L4SM_BUILD_DATE");
+
+/// The one-line identification of the build, e.g.
+/// `l4sm 0.1.0 (a1b2c3d-dirty, release, rustc 1.80.0, 2024-06-01)`.
+pub struct Banner;
+
+impl fmt::Display for Banner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "l4sm {VERSION} ({COMMIT}, {PROFILE}, rustc {RUSTC}, {BUILD_DATE})"
+        )
+    }
+}
@@ -0,0 +1,271 @@
+//! A watchdog built on the secure physical timer.
+//!
+//! Once started, the watchdog must be fed at least once per interval, otherwise the timer
+//! interrupt fires and the watchdog bites: it prints a crash report of the interrupted context and
+//! exits with a failure. This keeps a hung monitor from stalling automated runs until their global
+//! timeout.
+//!
+//! Who feeds the watchdog is set by [POLICY]. By default a core waiting in the idle loop counts as
+//! alive. With the `watchdog-explicit-feed` feature only explicit calls to [feed] do, so that a
+//! core idling while it should be handling calls is detected too.
+
+use crate::arch::exception::ExceptionFrame;
+use crate::arch::scr::ScrEl3;
+use crate::arch::{dit, timer};
+use crate::crash;
+#[cfg(feature = "run-tests")]
+use crate::driver::gic;
+#[cfg(feature = "run-tests")]
+use crate::ktest;
+use crate::ktest::kernel_test;
+use crate::logger::emergency_log;
+use crate::platform;
+use crate::sync::{Event, critical_section};
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Who is responsible for feeding the watchdog.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedPolicy {
+    /// Waiting in the idle loop counts as feeding.
+    Idle,
+    /// Only explicit calls to [feed] count.
+    Explicit,
+}
+
+/// The feed policy, selected by the `watchdog-explicit-feed` feature.
+pub const POLICY: FeedPolicy = if cfg!(feature = "watchdog-explicit-feed") {
+    FeedPolicy::Explicit
+} else {
+    FeedPolicy::Idle
+};
+
+/// How long to wait for the test interrupt of the secure timer, which is set to fire after 1 ms.
+const FIRST_INTERRUPT_TIMEOUT_MS: u64 = 100;
+
+/// The watchdog interval, in counter ticks.
+static INTERVAL: AtomicU64 = AtomicU64::new(0);
+/// The counter value at which the watchdog expires, or 0 when disarmed.
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+/// Signaled by the first timer interrupt.
+static FIRST_INTERRUPT: Event = Event::new();
+/// Whether DIT was set in the interrupt handler, recorded for the test interrupt of [init].
+static DIT_IN_HANDLER: AtomicBool = AtomicBool::new(false);
+/// Set while the boot core waits for interrupts in the idle loop.
+static IDLING: AtomicBool = AtomicBool::new(false);
+/// Set once the system halted after a fatal error, the watchdog then only resets it.
+static HALTED: AtomicBool = AtomicBool::new(false);
+
+/// Starts the watchdog, it must then be fed at least every `ms` milliseconds.
+///
+/// Routes FIQs to EL3 and checks that the timer interrupt, configured in the GIC by the platform
+/// interrupt map, is delivered. Must be called once, after the platform interrupts have been
+/// configured for the calling core.
+pub fn start(ms: u64) {
+    init();
+    arm(ms);
+}
+
+/// Routes FIQs to EL3, then checks that the timer interrupt is delivered.
+fn init() {
+    ScrEl3::read().fiq(true).write();
+    unsafe { asm!("msr DAIFClr, #0b0001") }; // Unmask FIQs
+
+    // Without the interrupt the watchdog would never fire, better fail here than hang later
+    log::debug!("Waiting for a test interrupt from the secure timer");
+    let now = timer::counter();
+    timer::set_secure_deadline(now + timer::ms_to_ticks(1));
+    if !FIRST_INTERRUPT.wait_until(now + timer::ms_to_ticks(FIRST_INTERRUPT_TIMEOUT_MS)) {
+        timer::disable_secure_timer();
+        log::error!(
+            "No interrupt from the secure timer after {FIRST_INTERRUPT_TIMEOUT_MS} ms, is it \
+             routed to EL3?"
+        );
+        platform::exit_failure();
+    }
+}
+
+/// Arms the watchdog, it must then be fed at least every `ms` milliseconds.
+pub fn arm(ms: u64) {
+    let interval = timer::ms_to_ticks(ms);
+    INTERVAL.store(interval, Ordering::Relaxed);
+    reload(interval);
+}
+
+/// Feeds the watchdog, postponing its expiration by one interval.
+///
+/// Does nothing if the watchdog is not armed.
+pub fn feed() {
+    if DEADLINE.load(Ordering::Relaxed) != 0 {
+        reload(INTERVAL.load(Ordering::Relaxed));
+    }
+}
+
+/// Disarms the watchdog.
+pub fn disarm() {
+    critical_section(|_| {
+        DEADLINE.store(0, Ordering::Relaxed);
+        timer::disable_secure_timer();
+    });
+}
+
+/// Waits for an interrupt, as the idle loop. Feeds the watchdog under the [FeedPolicy::Idle]
+/// policy.
+pub fn idle_wait() {
+    IDLING.store(true, Ordering::Relaxed);
+    unsafe { asm!("dsb sy", "wfi") };
+    IDLING.store(false, Ordering::Relaxed);
+    if POLICY == FeedPolicy::Idle {
+        feed();
+    }
+}
+
+/// Waits for the watchdog to expire after a fatal error, it then exits without another report,
+/// standing in for the reset of a hardware watchdog.
+///
+/// Returns right away if the watchdog is not armed.
+pub fn wait_for_reset() {
+    if DEADLINE.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    HALTED.store(true, Ordering::Relaxed);
+    unsafe { asm!("msr DAIFClr, #0b0001") }; // Unmask FIQs
+    loop {
+        unsafe { asm!("wfi") };
+    }
+}
+
+/// Handles the watchdog timer interrupt, which interrupted `frame`.
+///
+/// Bites if the watchdog expired: exits with a failure after a crash report.
+pub fn handle_interrupt(frame: &ExceptionFrame) {
+    match expiry(DEADLINE.load(Ordering::Relaxed), timer::counter()) {
+        Expiry::Disarmed => {
+            // Disarmed while the interrupt was pending, or the test interrupt of init
+            timer::disable_secure_timer();
+            DIT_IN_HANDLER.store(dit::is_enabled(), Ordering::Relaxed);
+            FIRST_INTERRUPT.signal();
+            return;
+        }
+        Expiry::Pending(deadline) => {
+            // Fed while the interrupt was pending
+            timer::set_secure_deadline(deadline);
+            return;
+        }
+        Expiry::Expired => {}
+    }
+    if fed_by_idle(POLICY, IDLING.load(Ordering::Relaxed)) {
+        // The interrupt woke the idle loop up, which didn't get to feed the watchdog yet
+        reload(INTERVAL.load(Ordering::Relaxed));
+        return;
+    }
+
+    #[cfg(feature = "run-tests")]
+    if ktest::is_running() {
+        // The test is abandoned along with this handler, which won't end the interrupt
+        gic::end_of_interrupt_group0(platform::SECURE_TIMER_INTID);
+        ktest::abort_current(ktest::Abort::Timeout, format_args!("timed out"));
+    }
+    bite(frame);
+}
+
+/// The state of the watchdog when its timer fires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Expiry {
+    /// The watchdog is not armed.
+    Disarmed,
+    /// The watchdog was fed since the timer was set, and now expires at the given deadline.
+    Pending(u64),
+    /// The deadline passed.
+    Expired,
+}
+
+/// Returns the state of the watchdog with the given deadline, 0 if disarmed, at counter `now`.
+fn expiry(deadline: u64, now: u64) -> Expiry {
+    if deadline == 0 {
+        Expiry::Disarmed
+    } else if now < deadline {
+        Expiry::Pending(deadline)
+    } else {
+        Expiry::Expired
+    }
+}
+
+/// Returns the deadline of a watchdog fed at counter `now`.
+fn deadline_after(now: u64, interval: u64) -> u64 {
+    // 0 means disarmed, the deadline can't realistically be 0 anyway
+    now.saturating_add(interval).max(1)
+}
+
+/// Returns `true` if an expired watchdog is still considered fed, because the core was idling.
+fn fed_by_idle(policy: FeedPolicy, idling: bool) -> bool {
+    policy == FeedPolicy::Idle && idling
+}
+
+/// Reports the expiration of the watchdog, and exits with a failure.
+///
+/// Only the boot core is online, its interrupted context is the whole picture. After a fatal
+/// error the crash was already reported.
+fn bite(frame: &ExceptionFrame) -> ! {
+    if HALTED.load(Ordering::Relaxed) {
+        emergency_log(format_args!("Watchdog expired, resetting"));
+        platform::exit_failure();
+    }
+    let interval_ms = timer::ticks_to_ms(INTERVAL.load(Ordering::Relaxed));
+    crash::dump(
+        Some(frame),
+        format_args!("Watchdog expired, not fed for {interval_ms} ms"),
+    );
+    // Exiting flushes the UART, without semihosting it spins until an external reset
+    platform::exit_failure();
+}
+
+fn reload(interval: u64) {
+    let deadline = deadline_after(timer::counter(), interval);
+    // The interrupt handler must see the deadline and the timer in sync.
+    critical_section(|_| {
+        DEADLINE.store(deadline, Ordering::Relaxed);
+        timer::set_secure_deadline(deadline);
+    });
+}
+
+kernel_test! {
+    fn idle_feeds_only_under_idle_policy() {
+        assert!(fed_by_idle(FeedPolicy::Idle, true));
+        assert!(!fed_by_idle(FeedPolicy::Idle, false));
+        assert!(!fed_by_idle(FeedPolicy::Explicit, true));
+        assert!(!fed_by_idle(FeedPolicy::Explicit, false));
+    }
+}
+
+kernel_test! {
+    fn arm_feed_expire() {
+        // A fake clock, in ticks, with an interval of 50 ticks
+        let interval = 50;
+        assert_eq!(expiry(0, 1000), Expiry::Disarmed);
+
+        let mut deadline = deadline_after(1000, interval);
+        assert_eq!(expiry(deadline, 1049), Expiry::Pending(1050));
+        // Fed before the timer fires, the interrupt then only moves the timer
+        deadline = deadline_after(1040, interval);
+        assert_eq!(expiry(deadline, 1050), Expiry::Pending(1090));
+        assert_eq!(expiry(deadline, 1090), Expiry::Expired);
+        assert_eq!(expiry(deadline, u64::MAX), Expiry::Expired);
+
+        // The deadline saturates, and is never mistaken for disarmed
+        assert_eq!(deadline_after(u64::MAX - 10, interval), u64::MAX);
+        assert_eq!(deadline_after(0, 0), 1);
+    }
+}
+
+kernel_test! {
+    fn dit_set_in_interrupt_handler() {
+        use crate::arch::feature;
+
+        assert!(FIRST_INTERRUPT.is_signaled(), "no timer interrupt was handled");
+        assert!(
+            DIT_IN_HANDLER.load(Ordering::Relaxed) || !feature::has_dit(),
+            "DIT not set in the interrupt handler"
+        );
+    }
+}
@@ -0,0 +1,54 @@
+#!/usr/bin/env python3
+"""Wraps a raw binary in the next-stage image format of the monitor (see src/image.rs)."""
+
+import argparse
+import struct
+import zlib
+
+MAGIC = b"L4IM"
+VERSION = 1
+
+# A payload that powers the system off with PSCI SYSTEM_OFF, to test the loader
+SYSTEM_OFF = struct.pack(
+    "<4I",
+    0x52B08000,  # movz w0, #0x8400, lsl #16
+    0x72800100,  # movk w0, #0x8
+    0xD4000003,  # smc #0
+    0x14000000,  # b .
+)
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument("output", help="path of the image to write")
+    source = parser.add_mutually_exclusive_group(required=True)
+    source.add_argument("--binary", help="raw binary to wrap")
+    source.add_argument(
+        "--system-off", action="store_true", help="wrap a payload powering the system off"
+    )
+    parser.add_argument("--load-addr", type=lambda x: int(x, 0), default=0x6000_0000)
+    parser.add_argument("--entry-offset", type=lambda x: int(x, 0), default=0)
+    args = parser.parse_args()
+
+    if args.system_off:
+        binary = SYSTEM_OFF
+    else:
+        with open(args.binary, "rb") as f:
+            binary = f.read()
+
+    header = struct.pack(
+        "<4sIQQQII",
+        MAGIC,
+        VERSION,
+        args.load_addr,
+        args.entry_offset,
+        len(binary),
+        zlib.crc32(binary),
+        0,
+    )
+    with open(args.output, "wb") as f:
+        f.write(header + binary)
+
+
+if __name__ == "__main__":
+    main()